@@ -1,12 +1,19 @@
-use crate::context::{Context, ContextManager};
+use crate::approval::{ApprovalDecision, ApprovalHandler, ApprovalPolicy};
+use crate::branch::{BranchDiff, BranchInfo};
+use crate::compaction::CompactionRecord;
+use crate::context::{Context, ContextManager, WindowPolicy};
 use crate::error::{AgentError, AgentResult};
-use crate::executor::{Executor, ExecutorConfig, ExecutionResult};
+use crate::executor::{AgentEvent, AgentEventStream, Executor, ExecutorConfig, ExecutionResult, ModelSettings};
+use crate::observer::{AgentObserver, EventBus, ObservedEvent};
+use crate::rewind::Rewind;
 use crate::state::{AgentState, StateStore};
+use crate::store::{ContextStore, SessionInfo};
 use crate::tool_registry::ToolRegistry;
-use pi_ai::Client;
+use pi_ai::{Client, LlmBackend, ToolCall};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{mpsc, RwLock};
 use futures::StreamExt;
 use std::pin::Pin;
 
@@ -16,11 +23,18 @@ pub struct AgentConfig {
     pub name: String,
     pub description: Option<String>,
     pub system_prompt: String,
+    // 为空时从 model 的 "provider/model" 前缀或后端默认值推断
+    #[serde(default)]
+    pub provider: Option<String>,
     pub model: String,
     pub temperature: Option<f32>,
     pub max_tokens: Option<u32>,
     pub executor_config: ExecutorConfig,
     pub enabled_tools: Vec<String>,
+    #[serde(default)]
+    pub approval_policy: ApprovalPolicy,
+    #[serde(default)]
+    pub context_window: WindowPolicy,
 }
 
 impl AgentConfig {
@@ -30,11 +44,14 @@ impl AgentConfig {
             name,
             description: None,
             system_prompt,
+            provider: None,
             model: "gpt-4".to_string(),
             temperature: None,
             max_tokens: None,
             executor_config: ExecutorConfig::default(),
             enabled_tools: Vec::new(),
+            approval_policy: ApprovalPolicy::default(),
+            context_window: WindowPolicy::default(),
         }
     }
 
@@ -48,6 +65,11 @@ impl AgentConfig {
         self
     }
 
+    pub fn with_provider(mut self, provider: String) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
     pub fn with_temperature(mut self, temperature: f32) -> Self {
         self.temperature = Some(temperature);
         self
@@ -67,32 +89,69 @@ impl AgentConfig {
         self.enabled_tools = tools;
         self
     }
+
+    pub fn with_approval_policy(mut self, policy: ApprovalPolicy) -> Self {
+        self.approval_policy = policy;
+        self
+    }
+
+    pub fn with_context_window(mut self, window: WindowPolicy) -> Self {
+        self.context_window = window;
+        self
+    }
+
+    pub fn model_settings(&self) -> ModelSettings {
+        ModelSettings {
+            provider: self.provider.clone(),
+            model: Some(self.model.clone()).filter(|model| !model.is_empty()),
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            top_p: None,
+        }
+    }
 }
 
-#[derive(Clone)]
-pub struct Agent {
+pub struct Agent<B: LlmBackend + ?Sized = Client> {
     config: AgentConfig,
-    executor: Arc<Executor>,
+    executor: Arc<Executor<B>>,
     context_manager: Arc<ContextManager>,
     state_store: Arc<StateStore>,
     tool_registry: Arc<ToolRegistry>,
     initialized: Arc<RwLock<bool>>,
 }
 
-impl Agent {
+// 手写 Clone，避免要求后端本身实现 Clone
+impl<B: LlmBackend + ?Sized> Clone for Agent<B> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            executor: self.executor.clone(),
+            context_manager: self.context_manager.clone(),
+            state_store: self.state_store.clone(),
+            tool_registry: self.tool_registry.clone(),
+            initialized: self.initialized.clone(),
+        }
+    }
+}
+
+impl<B: LlmBackend + ?Sized> Agent<B> {
     pub fn new(
         config: AgentConfig,
-        llm_client: Arc<Client>,
+        llm_client: Arc<B>,
         tool_registry: Arc<ToolRegistry>,
     ) -> Self {
         let state_store = Arc::new(StateStore::new());
         let context_manager = Arc::new(ContextManager::new());
-        let executor = Arc::new(Executor::new(
-            config.executor_config.clone(),
-            llm_client,
-            tool_registry.clone(),
-            state_store.clone(),
-        ));
+        let executor = Arc::new(
+            Executor::new(
+                config.executor_config.clone(),
+                llm_client,
+                tool_registry.clone(),
+                state_store.clone(),
+            )
+            .with_model_settings(config.model_settings())
+            .with_approval_policy(config.approval_policy.clone()),
+        );
 
         Self {
             config,
@@ -104,6 +163,17 @@ impl Agent {
         }
     }
 
+    // 会话保存到 store，重启后可以继续之前的对话
+    pub fn with_context_store(mut self, store: Arc<dyn ContextStore>) -> Self {
+        self.context_manager = Arc::new(ContextManager::with_store(store));
+        self
+    }
+
+    pub fn with_approval_handler(mut self, handler: Arc<dyn ApprovalHandler>) -> Self {
+        self.executor = Arc::new((*self.executor).clone().with_approval_handler(handler));
+        self
+    }
+
     pub async fn initialize(&self) -> AgentResult<()> {
         let mut initialized = self.initialized.write().await;
         *initialized = true;
@@ -115,54 +185,51 @@ impl Agent {
     }
 
     pub async fn chat(&self, user_message: String) -> AgentResult<ExecutionResult> {
-        self.ensure_initialized().await?;
+        self.chat_with_settings(user_message, ModelSettings::default()).await
+    }
 
+    // 只对这一轮生效，例如临时换模型或调低 temperature
+    pub async fn chat_with_settings(
+        &self,
+        user_message: String,
+        overrides: ModelSettings,
+    ) -> AgentResult<ExecutionResult> {
         let context_id = format!("{}:default", self.config.id);
-        let mut context = self
-            .context_manager
-            .get(&context_id)
-            .await
-            .unwrap_or_else(|| Context::new(context_id.clone()));
-
-        if context.message_count() == 0 {
-            context.add_system_message(self.config.system_prompt.clone());
-        }
-
-        context.add_user_message(user_message);
-
-        let result = self.executor.execute(&mut context).await?;
-
-        self.context_manager.update(&context_id, |ctx| {
-            *ctx = context;
-        }).await?;
-
-        Ok(result)
+        self.run(context_id, user_message, &overrides).await
     }
 
     pub async fn chat_with_context(
         &self,
         context_id: String,
         user_message: String,
+    ) -> AgentResult<ExecutionResult> {
+        self.run(context_id, user_message, &ModelSettings::default()).await
+    }
+
+    async fn run(
+        &self,
+        context_id: String,
+        user_message: String,
+        overrides: &ModelSettings,
     ) -> AgentResult<ExecutionResult> {
         self.ensure_initialized().await?;
 
         let mut context = self
             .context_manager
             .get(&context_id)
-            .await
+            .await?
             .unwrap_or_else(|| Context::new(context_id.clone()));
 
+        context.set_window(self.config.context_window.clone());
         if context.message_count() == 0 {
             context.add_system_message(self.config.system_prompt.clone());
         }
 
         context.add_user_message(user_message);
 
-        let result = self.executor.execute(&mut context).await?;
+        let result = self.executor.execute_with(&mut context, overrides).await?;
 
-        self.context_manager.update(&context_id, |ctx| {
-            *ctx = context;
-        }).await?;
+        self.context_manager.save(context).await?;
 
         Ok(result)
     }
@@ -170,55 +237,224 @@ impl Agent {
     pub async fn chat_stream(
         &self,
         user_message: String,
-    ) -> AgentResult<Pin<Box<dyn futures::Stream<Item = AgentResult<String>> + Send>>> {
+    ) -> AgentResult<Pin<Box<dyn futures::Stream<Item = AgentResult<String>> + Send>>>
+    where
+        B: 'static,
+    {
+        self.chat_stream_with_settings(user_message, ModelSettings::default()).await
+    }
+
+    pub async fn chat_stream_with_settings(
+        &self,
+        user_message: String,
+        overrides: ModelSettings,
+    ) -> AgentResult<Pin<Box<dyn futures::Stream<Item = AgentResult<String>> + Send>>>
+    where
+        B: 'static,
+    {
+        let events = self.chat_events(user_message, overrides).await?;
+        let stream = events.filter_map(|result| {
+            let text = match result {
+                Ok(AgentEvent::TextDelta(token)) => Some(Ok(token)),
+                Ok(AgentEvent::ToolCallFinished { id, name, arguments }) => {
+                    Some(Ok(format!("[ToolCall: {}({}) args={}]", name, id, arguments)))
+                }
+                Ok(AgentEvent::Error(err)) => Some(Ok(format!("[Error: {}]", err))),
+                Ok(AgentEvent::Finished(_)) => Some(Ok("[Done]".to_string())),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            };
+            futures::future::ready(text)
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    // 完整的事件流；执行在后台任务中进行，context 在流结束（或被丢弃）后写回
+    pub async fn chat_events(
+        &self,
+        user_message: String,
+        overrides: ModelSettings,
+    ) -> AgentResult<AgentEventStream<'static>>
+    where
+        B: 'static,
+    {
         self.ensure_initialized().await?;
 
         let context_id = format!("{}:default", self.config.id);
         let mut context = self
             .context_manager
             .get(&context_id)
-            .await
+            .await?
             .unwrap_or_else(|| Context::new(context_id.clone()));
 
+        context.set_window(self.config.context_window.clone());
         if context.message_count() == 0 {
             context.add_system_message(self.config.system_prompt.clone());
         }
 
         context.add_user_message(user_message);
 
-        let stream = self.executor.execute_stream(&mut context).await?;
-        let stream = stream.map(|result| {
-            result.map(|event| match event {
-                crate::executor::StreamEvent::Token(token) => token,
-                crate::executor::StreamEvent::ToolCall { id, name, arguments } => {
-                    format!("[ToolCall: {}({}) args={}]", name, id, arguments)
+        let executor = self.executor.clone();
+        let context_manager = self.context_manager.clone();
+        let (tx, rx) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut events = executor.execute_stream_with(&mut context, overrides);
+            while let Some(event) = events.next().await {
+                if tx.send(event).await.is_err() {
+                    break;
                 }
-                crate::executor::StreamEvent::Done => "[Done]".to_string(),
-                crate::executor::StreamEvent::Error(err) => format!("[Error: {}]", err),
-            })
+            }
+            drop(events);
+            if let Err(e) = context_manager.save(context).await {
+                tracing::warn!("Failed to save context: {}", e);
+            }
         });
 
+        let stream = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|event| (event, rx)) });
         Ok(Box::pin(stream))
     }
 
+    // 立即压缩默认会话的较早消息
+    pub async fn compact(&self) -> AgentResult<Option<CompactionRecord>> {
+        self.ensure_initialized().await?;
+        let context_id = format!("{}:default", self.config.id);
+        let Some(mut context) = self.context_manager.get(&context_id).await? else {
+            return Ok(None);
+        };
+        let record = self.executor.compact(&mut context, &ModelSettings::default()).await?;
+        if record.is_some() {
+            self.context_manager.save(context).await?;
+        }
+        Ok(record)
+    }
+
+    pub async fn undo_compaction(&self) -> AgentResult<Option<CompactionRecord>> {
+        let context_id = format!("{}:default", self.config.id);
+        let Some(mut context) = self.context_manager.get(&context_id).await? else {
+            return Ok(None);
+        };
+        let record = context.undo_compaction()?;
+        if record.is_some() {
+            self.context_manager.save(context).await?;
+        }
+        Ok(record)
+    }
+
+    // 撤销默认会话的最近一轮，包括其中的工具调用和结果
+    pub async fn undo_last_turn(&self) -> AgentResult<Option<Rewind>> {
+        let context_id = format!("{}:default", self.config.id);
+        let Some(mut context) = self.context_manager.get(&context_id).await? else {
+            return Ok(None);
+        };
+        let rewind = self.executor.undo_turn(&mut context).await?;
+        if rewind.is_some() {
+            self.context_manager.save(context).await?;
+        }
+        Ok(rewind)
+    }
+
+    // 默认会话中等待审批的 tool call
+    pub async fn pending_tool_calls(&self) -> AgentResult<Vec<ToolCall>> {
+        Ok(self.default_context().await?.pending_tool_calls())
+    }
+
+    // 对等待审批的 tool call 作出决定并继续执行，没有给出决定的调用按拒绝处理
+    pub async fn resolve_pending(
+        &self,
+        decisions: HashMap<String, ApprovalDecision>,
+    ) -> AgentResult<ExecutionResult> {
+        self.ensure_initialized().await?;
+        let mut context = self.default_context().await?;
+        context.set_window(self.config.context_window.clone());
+        let result = self
+            .executor
+            .resolve_pending(&mut context, decisions, &ModelSettings::default())
+            .await;
+        self.context_manager.save(context).await?;
+        result
+    }
+
+    pub async fn regenerate(&self) -> AgentResult<ExecutionResult> {
+        self.regenerate_with_settings(ModelSettings::default()).await
+    }
+
+    // 重新生成最后一条 user 消息的回复，可以临时换模型
+    pub async fn regenerate_with_settings(&self, overrides: ModelSettings) -> AgentResult<ExecutionResult> {
+        self.ensure_initialized().await?;
+        let mut context = self.default_context().await?;
+        context.set_window(self.config.context_window.clone());
+        // 执行失败时旧回复已经移除，同样写回
+        let result = self.executor.regenerate(&mut context, &overrides).await;
+        self.context_manager.save(context).await?;
+        result
+    }
+
+    // 修改默认会话第 index 条 user 消息并从那里重新执行，之后的内容都会丢弃
+    pub async fn edit_message(&self, index: usize, content: String) -> AgentResult<ExecutionResult> {
+        self.ensure_initialized().await?;
+        let mut context = self.default_context().await?;
+        context.set_window(self.config.context_window.clone());
+        let result = self
+            .executor
+            .edit_and_rerun(&mut context, index, content, &ModelSettings::default())
+            .await;
+        self.context_manager.save(context).await?;
+        result
+    }
+
+    // 从默认会话活动分支的第 at 条消息分出新分支，之后的 chat 在新分支上继续
+    pub async fn fork(&self, name: &str, at: usize) -> AgentResult<()> {
+        self.update_default_context(|context| context.fork(name, at)).await
+    }
+
+    pub async fn switch_branch(&self, name: &str) -> AgentResult<()> {
+        self.update_default_context(|context| context.switch_branch(name)).await
+    }
+
+    pub async fn list_branches(&self) -> AgentResult<Vec<BranchInfo>> {
+        Ok(self.default_context().await?.list_branches())
+    }
+
+    pub async fn diff_branches(&self, left: &str, right: &str) -> AgentResult<BranchDiff> {
+        self.default_context().await?.diff_branches(left, right)
+    }
+
+    async fn default_context(&self) -> AgentResult<Context> {
+        let context_id = format!("{}:default", self.config.id);
+        self.context_manager
+            .get(&context_id)
+            .await?
+            .ok_or_else(|| AgentError::Context(format!("Context {} not found", context_id)))
+    }
+
+    async fn update_default_context<T>(&self, f: impl FnOnce(&mut Context) -> AgentResult<T>) -> AgentResult<T> {
+        let mut context = self.default_context().await?;
+        let result = f(&mut context)?;
+        self.context_manager.save(context).await?;
+        Ok(result)
+    }
+
     pub async fn create_context(&self, context_id: String) -> AgentResult<Context> {
-        let context = self.context_manager.create(context_id).await;
-        Ok(context)
+        self.context_manager.create(context_id).await
     }
 
     pub async fn get_context(&self, context_id: &str) -> AgentResult<Option<Context>> {
-        Ok(self.context_manager.get(context_id).await)
+        self.context_manager.get(context_id).await
     }
 
     pub async fn delete_context(&self, context_id: &str) -> AgentResult<()> {
-        self.context_manager.delete(context_id).await;
-        Ok(())
+        self.context_manager.delete(context_id).await
     }
 
     pub async fn list_contexts(&self) -> AgentResult<Vec<Context>> {
         Ok(self.context_manager.list().await)
     }
 
+    pub async fn list_sessions(&self) -> AgentResult<Vec<SessionInfo>> {
+        self.context_manager.sessions().await
+    }
+
     pub async fn get_state(&self) -> AgentResult<Option<AgentState>> {
         Ok(self.state_store.get(&self.config.id).await)
     }
@@ -256,7 +492,7 @@ impl Agent {
 
     pub async fn reset(&self) -> AgentResult<()> {
         let context_id = format!("{}:default", self.config.id);
-        self.context_manager.delete(&context_id).await;
+        self.context_manager.delete(&context_id).await?;
         self.state_store.remove(&self.config.id).await;
         Ok(())
     }
@@ -265,7 +501,7 @@ impl Agent {
         &self.config
     }
 
-    pub fn executor(&self) -> &Arc<Executor> {
+    pub fn executor(&self) -> &Arc<Executor<B>> {
         &self.executor
     }
 
@@ -273,6 +509,19 @@ impl Agent {
         &self.tool_registry
     }
 
+    // chat、chat_stream 等所有调用的生命周期事件都会发到这里
+    pub fn events(&self) -> &EventBus {
+        self.executor.events()
+    }
+
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<ObservedEvent> {
+        self.executor.events().subscribe()
+    }
+
+    pub fn add_observer(&self, observer: Arc<dyn AgentObserver>) {
+        self.executor.events().add_observer(observer);
+    }
+
     async fn ensure_initialized(&self) -> AgentResult<()> {
         if !self.is_initialized().await {
             return Err(AgentError::NotInitialized);
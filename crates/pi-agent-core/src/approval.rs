@@ -0,0 +1,144 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::error::AgentResult;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApprovalRequest {
+    pub context_id: String,
+    pub tool_call_id: String,
+    pub tool_name: String,
+    pub arguments: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApprovalDecision {
+    Approve,
+    Deny { reason: String },
+    // 用修改后的参数执行
+    Edit { arguments: String },
+    // 本次执行，并且同一个 context 里不再询问这个工具
+    ApproveAlways,
+}
+
+// TUI、Web、Slack 等各自实现，决定如何向用户展示待批准的调用
+#[async_trait]
+pub trait ApprovalHandler: Send + Sync {
+    async fn review(&self, request: &ApprovalRequest) -> AgentResult<ApprovalDecision>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAction {
+    Allow,
+    Deny,
+    #[default]
+    Ask,
+}
+
+// tool 和 arguments 支持 * 和 ? 通配符，arguments 匹配原始 JSON 字符串
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalRule {
+    pub tool: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<String>,
+    pub action: PolicyAction,
+}
+
+impl ApprovalRule {
+    pub fn new(tool: impl Into<String>, action: PolicyAction) -> Self {
+        Self {
+            tool: tool.into(),
+            arguments: None,
+            action,
+        }
+    }
+
+    pub fn with_arguments(mut self, pattern: impl Into<String>) -> Self {
+        self.arguments = Some(pattern.into());
+        self
+    }
+
+    pub fn matches(&self, tool_name: &str, arguments: &str) -> bool {
+        wildcard_match(&self.tool, tool_name)
+            && self
+                .arguments
+                .as_deref()
+                .is_none_or(|pattern| wildcard_match(pattern, arguments))
+    }
+}
+
+// 规则按顺序匹配，第一条命中的生效
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApprovalPolicy {
+    #[serde(default)]
+    pub rules: Vec<ApprovalRule>,
+    #[serde(default)]
+    pub default_action: PolicyAction,
+}
+
+impl ApprovalPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: ApprovalRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn allow(self, tool: impl Into<String>) -> Self {
+        self.with_rule(ApprovalRule::new(tool, PolicyAction::Allow))
+    }
+
+    pub fn deny(self, tool: impl Into<String>) -> Self {
+        self.with_rule(ApprovalRule::new(tool, PolicyAction::Deny))
+    }
+
+    pub fn ask(self, tool: impl Into<String>) -> Self {
+        self.with_rule(ApprovalRule::new(tool, PolicyAction::Ask))
+    }
+
+    pub fn with_default_action(mut self, action: PolicyAction) -> Self {
+        self.default_action = action;
+        self
+    }
+
+    pub fn evaluate(&self, tool_name: &str, arguments: &str) -> PolicyAction {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(tool_name, arguments))
+            .map_or(self.default_action, |rule| rule.action)
+    }
+}
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    // 最近一个 * 的位置，以及它当时对应的文本位置
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
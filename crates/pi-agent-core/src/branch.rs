@@ -0,0 +1,224 @@
+use crate::context::Context;
+use crate::error::{AgentError, AgentResult};
+use pi_ai::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const DEFAULT_BRANCH: &str = "main";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Branch {
+    // 从哪个分支分出，根分支为 None
+    pub parent: Option<String>,
+    // 与父分支共享的消息数；任一方的历史被改写后只会变小
+    pub fork_point: usize,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    // 非活动分支的完整消息路径；活动分支的消息就是 Context::messages，这里为空
+    #[serde(default)]
+    pub messages: Vec<Message>,
+}
+
+impl Branch {
+    fn new(parent: Option<String>, fork_point: usize) -> Self {
+        Self {
+            parent,
+            fork_point,
+            created_at: chrono::Utc::now(),
+            messages: Vec::new(),
+        }
+    }
+}
+
+// 分支按 parent 组成一棵树，executor 只看得到活动分支的消息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Branches {
+    pub active: String,
+    pub branches: BTreeMap<String, Branch>,
+}
+
+impl Default for Branches {
+    fn default() -> Self {
+        let mut branches = BTreeMap::new();
+        branches.insert(DEFAULT_BRANCH.to_string(), Branch::new(None, 0));
+        Self {
+            active: DEFAULT_BRANCH.to_string(),
+            branches,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BranchInfo {
+    pub name: String,
+    pub parent: Option<String>,
+    pub fork_point: usize,
+    pub message_count: usize,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BranchDiff {
+    // 两条路径开头相同的消息数
+    pub common: usize,
+    // 各自在共同部分之后的消息
+    pub left: Vec<Message>,
+    pub right: Vec<Message>,
+}
+
+impl Context {
+    pub fn active_branch(&self) -> &str {
+        &self.branches.active
+    }
+
+    // 从活动分支的第 at 条消息（含）分出新分支并切换过去，原分支保持不变
+    pub fn fork(&mut self, name: &str, at: usize) -> AgentResult<()> {
+        if self.branches.branches.contains_key(name) {
+            return Err(AgentError::Context(format!("Branch {} already exists", name)));
+        }
+        if at >= self.messages.len() {
+            return Err(AgentError::Context(format!(
+                "Cannot fork at message {}, branch {} has {} messages",
+                at,
+                self.branches.active,
+                self.messages.len()
+            )));
+        }
+
+        let parent = self.branches.active.clone();
+        self.stash_active();
+        self.branches
+            .branches
+            .insert(name.to_string(), Branch::new(Some(parent), at + 1));
+        self.branches.active = name.to_string();
+        self.messages.truncate(at + 1);
+        self.metadata.updated_at = chrono::Utc::now();
+        Ok(())
+    }
+
+    pub fn switch_branch(&mut self, name: &str) -> AgentResult<()> {
+        if name == self.branches.active {
+            return Ok(());
+        }
+        let messages = std::mem::take(
+            &mut self
+                .branches
+                .branches
+                .get_mut(name)
+                .ok_or_else(|| AgentError::Context(format!("Branch {} not found", name)))?
+                .messages,
+        );
+
+        self.stash_active();
+        self.branches.active = name.to_string();
+        self.messages = messages.into();
+        self.metadata.updated_at = chrono::Utc::now();
+        Ok(())
+    }
+
+    // 活动分支不能删除；子分支保留，parent 改为被删分支的 parent
+    pub fn delete_branch(&mut self, name: &str) -> AgentResult<()> {
+        if name == self.branches.active {
+            return Err(AgentError::Context(format!("Cannot delete the active branch {}", name)));
+        }
+        let removed = self
+            .branches
+            .branches
+            .remove(name)
+            .ok_or_else(|| AgentError::Context(format!("Branch {} not found", name)))?;
+        for branch in self.branches.branches.values_mut() {
+            if branch.parent.as_deref() == Some(name) {
+                branch.parent = removed.parent.clone();
+                branch.fork_point = branch.fork_point.min(removed.fork_point);
+            }
+        }
+        self.metadata.updated_at = chrono::Utc::now();
+        Ok(())
+    }
+
+    pub fn list_branches(&self) -> Vec<BranchInfo> {
+        self.branches
+            .branches
+            .iter()
+            .map(|(name, branch)| {
+                let active = *name == self.branches.active;
+                BranchInfo {
+                    name: name.clone(),
+                    parent: branch.parent.clone(),
+                    fork_point: branch.fork_point,
+                    message_count: if active { self.messages.len() } else { branch.messages.len() },
+                    created_at: branch.created_at,
+                    active,
+                }
+            })
+            .collect()
+    }
+
+    pub fn branch_messages(&self, name: &str) -> AgentResult<Vec<Message>> {
+        if name == self.branches.active {
+            return Ok(self.get_messages());
+        }
+        self.branches
+            .branches
+            .get(name)
+            .map(|branch| branch.messages.clone())
+            .ok_or_else(|| AgentError::Context(format!("Branch {} not found", name)))
+    }
+
+    pub fn diff_branches(&self, left: &str, right: &str) -> AgentResult<BranchDiff> {
+        let left = self.branch_messages(left)?;
+        let right = self.branch_messages(right)?;
+        let common = common_prefix(&left, &right);
+
+        Ok(BranchDiff {
+            common,
+            left: left[common..].to_vec(),
+            right: right[common..].to_vec(),
+        })
+    }
+
+    // 活动分支的历史被改写（裁剪、压缩、回退）后，和它相邻的分支的分叉点
+    // 不能超过两边实际共享的消息数
+    pub(crate) fn clamp_fork_points(&mut self) {
+        if self.branches.branches.len() < 2 {
+            return;
+        }
+        let active = self.branches.active.clone();
+        let pairs: Vec<(String, String)> = self
+            .branches
+            .branches
+            .iter()
+            .filter_map(|(name, branch)| Some((name.clone(), branch.parent.clone()?)))
+            .filter(|(name, parent)| *name == active || *parent == active)
+            .collect();
+        for (name, parent) in pairs {
+            let (Ok(child), Ok(parent)) = (self.branch_messages(&name), self.branch_messages(&parent)) else {
+                continue;
+            };
+            let shared = common_prefix(&child, &parent);
+            if let Some(branch) = self.branches.branches.get_mut(&name) {
+                branch.fork_point = branch.fork_point.min(shared);
+            }
+        }
+    }
+
+    // 把活动分支当前的消息存回它的记录
+    fn stash_active(&mut self) {
+        let messages = self.get_messages();
+        let active = self.branches.active.clone();
+        self.branches
+            .branches
+            .entry(active)
+            .or_insert_with(|| Branch::new(None, 0))
+            .messages = messages;
+    }
+}
+
+// Message 没有实现 PartialEq，按序列化结果比较
+fn common_prefix(left: &[Message], right: &[Message]) -> usize {
+    left.iter()
+        .zip(right)
+        .take_while(|(a, b)| serde_json::to_value(a).ok() == serde_json::to_value(b).ok())
+        .count()
+}
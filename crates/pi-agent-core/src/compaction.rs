@@ -0,0 +1,274 @@
+use crate::context::Context;
+use crate::error::{AgentError, AgentResult};
+use pi_ai::models::ChatCompletionRequest;
+use pi_ai::overflow::estimate_message_tokens;
+use pi_ai::{LlmBackend, Message, MessageRole};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+// 摘要消息的固定开头，用来在 context 中识别之前的摘要
+pub const SUMMARY_PREFIX: &str = "[Conversation summary]";
+
+// 转写给摘要模型时单条工具结果最多保留的字符数
+const TOOL_RESULT_MAX_CHARS: usize = 2000;
+
+const SUMMARY_INSTRUCTIONS: &str = "You are compacting the earlier part of a conversation between a user and an AI assistant so that it can continue without the full history. \
+Write a concise summary using exactly these sections:\n\
+## Goal\n## Decisions\n## Files and artifacts\n## Tool results\n## Open tasks\n\
+Keep names, paths, identifiers, numbers and error messages verbatim. Omit small talk. \
+If an earlier summary is included, merge it into the new one. Reply with the summary only.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactionConfig {
+    // 估算的 token 数超过上下文窗口的这个比例时开始压缩
+    #[serde(default = "default_threshold")]
+    pub threshold: f32,
+    // 最近几轮（以 user 消息开始算一轮）原样保留
+    #[serde(default = "default_keep_recent_turns")]
+    pub keep_recent_turns: usize,
+    // 按模型名覆盖上下文窗口大小
+    #[serde(default)]
+    pub context_limits: HashMap<String, u32>,
+    // 配置和模型列表里都查不到时使用；为空时不知道窗口大小，不自动压缩
+    #[serde(default)]
+    pub default_context_limit: Option<u32>,
+    // 为空时用对话本身的 provider 和模型生成摘要，格式同 ModelSettings::model
+    #[serde(default)]
+    pub summary_model: Option<String>,
+    #[serde(default = "default_summary_max_tokens")]
+    pub summary_max_tokens: u32,
+}
+
+fn default_threshold() -> f32 {
+    0.8
+}
+
+fn default_keep_recent_turns() -> usize {
+    2
+}
+
+fn default_summary_max_tokens() -> u32 {
+    1024
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            threshold: default_threshold(),
+            keep_recent_turns: default_keep_recent_turns(),
+            context_limits: HashMap::new(),
+            default_context_limit: None,
+            summary_model: None,
+            summary_max_tokens: default_summary_max_tokens(),
+        }
+    }
+}
+
+impl CompactionConfig {
+    pub fn with_threshold(mut self, threshold: f32) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub fn with_keep_recent_turns(mut self, turns: usize) -> Self {
+        self.keep_recent_turns = turns;
+        self
+    }
+
+    pub fn with_context_limit(mut self, model: impl Into<String>, limit: u32) -> Self {
+        self.context_limits.insert(model.into(), limit);
+        self
+    }
+
+    pub fn with_default_context_limit(mut self, limit: u32) -> Self {
+        self.default_context_limit = Some(limit);
+        self
+    }
+
+    pub fn with_summary_model(mut self, model: String) -> Self {
+        self.summary_model = Some(model);
+        self
+    }
+}
+
+// 一次压缩的记录，保存在 ContextMetadata 中，可以用 Context::undo_compaction 还原
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompactionRecord {
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub model: String,
+    // 写入 context 的摘要消息内容（带 SUMMARY_PREFIX）
+    pub summary: String,
+    pub replaced_messages: Vec<Message>,
+    pub tokens_before: u32,
+    pub tokens_after: u32,
+}
+
+pub fn estimate_tokens(messages: impl IntoIterator<Item = impl std::borrow::Borrow<Message>>) -> u32 {
+    messages.into_iter().map(|m| estimate_message_tokens(m.borrow())).sum()
+}
+
+pub fn is_summary(message: &Message) -> bool {
+    message.role == MessageRole::System && message.content.starts_with(SUMMARY_PREFIX)
+}
+
+pub struct Compactor {
+    config: CompactionConfig,
+    // (provider, model) -> 模型列表里的上下文窗口
+    listed_limits: Mutex<HashMap<(String, String), Option<u32>>>,
+}
+
+impl Compactor {
+    pub fn new(config: CompactionConfig) -> Self {
+        Self {
+            config,
+            listed_limits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn config(&self) -> &CompactionConfig {
+        &self.config
+    }
+
+    pub async fn context_limit<B: LlmBackend + ?Sized>(&self, backend: &B, provider: &str, model: &str) -> Option<u32> {
+        if let Some(limit) = self.config.context_limits.get(model) {
+            return Some(*limit);
+        }
+
+        let key = (provider.to_string(), model.to_string());
+        let cached = self.listed_limits.lock().unwrap().get(&key).copied();
+        let listed = match cached {
+            Some(listed) => listed,
+            None => {
+                let listed = match backend.list_models(provider).await {
+                    Ok(models) => models.into_iter().find(|m| m.id == model).and_then(|m| m.context_length),
+                    Err(e) => {
+                        tracing::debug!("Could not list models for {}: {}", provider, e);
+                        None
+                    }
+                };
+                self.listed_limits.lock().unwrap().insert(key, listed);
+                listed
+            }
+        };
+        listed.or(self.config.default_context_limit)
+    }
+
+    pub async fn should_compact<B: LlmBackend + ?Sized>(
+        &self,
+        backend: &B,
+        provider: &str,
+        model: &str,
+        context: &Context,
+    ) -> bool {
+        let Some(limit) = self.context_limit(backend, provider, model).await else {
+            return false;
+        };
+        estimate_tokens(&context.messages) as f32 >= limit as f32 * self.config.threshold
+    }
+
+    // 把开头的 system 提示之后、最近 keep_recent_turns 轮之前的消息换成一条摘要消息。
+    // 只在 user 消息处切分，所以 tool call 和对应的结果总在同一侧。没有可压缩的内容时返回 None
+    pub async fn compact<B: LlmBackend + ?Sized>(
+        &self,
+        backend: &B,
+        provider: &str,
+        model: &str,
+        context: &mut Context,
+    ) -> AgentResult<Option<CompactionRecord>> {
+        let messages = context.get_messages();
+        let start = messages
+            .iter()
+            .position(|m| m.role != MessageRole::System || is_summary(m))
+            .unwrap_or(messages.len());
+        let turn_starts: Vec<usize> = messages
+            .iter()
+            .enumerate()
+            .skip(start)
+            .filter(|(_, m)| m.role == MessageRole::User)
+            .map(|(i, _)| i)
+            .collect();
+        let keep = self.config.keep_recent_turns.max(1);
+        if turn_starts.len() <= keep {
+            return Ok(None);
+        }
+        let end = turn_starts[turn_starts.len() - keep];
+        if end <= start {
+            return Ok(None);
+        }
+
+        let (summary_provider, summary_model) = match self.config.summary_model.as_deref() {
+            Some(summary_model) => match summary_model.split_once('/') {
+                Some((p, m)) if !p.is_empty() && !m.is_empty() => (p.to_string(), m.to_string()),
+                _ => (provider.to_string(), summary_model.to_string()),
+            },
+            None => (provider.to_string(), model.to_string()),
+        };
+
+        let replaced = messages[start..end].to_vec();
+        let request = ChatCompletionRequest::new(
+            summary_model.clone(),
+            vec![Message::system(SUMMARY_INSTRUCTIONS), Message::user(transcript(&replaced))],
+        )
+        .with_max_tokens(self.config.summary_max_tokens);
+        let response = backend.chat(&summary_provider, request).await?;
+        let text = response
+            .choices
+            .first()
+            .map(|choice| choice.message.content.trim().to_string())
+            .filter(|text| !text.is_empty())
+            .ok_or_else(|| AgentError::Context("Summarization returned an empty response".to_string()))?;
+
+        let summary = format!("{}\n{}", SUMMARY_PREFIX, text);
+        let tokens_before = estimate_tokens(&messages);
+        let mut compacted = messages[..start].to_vec();
+        compacted.push(Message::system(summary.clone()));
+        compacted.extend_from_slice(&messages[end..]);
+        let tokens_after = estimate_tokens(&compacted);
+
+        let record = CompactionRecord {
+            created_at: chrono::Utc::now(),
+            model: format!("{}/{}", summary_provider, summary_model),
+            summary,
+            replaced_messages: replaced,
+            tokens_before,
+            tokens_after,
+        };
+        context.messages = compacted.into();
+        context.clamp_fork_points();
+        context.metadata.compactions.push(record.clone());
+        context.metadata.updated_at = chrono::Utc::now();
+        Ok(Some(record))
+    }
+}
+
+// 把消息转成纯文本给摘要模型，工具调用和结果按 id 对应
+fn transcript(messages: &[Message]) -> String {
+    let mut out = String::new();
+    for message in messages {
+        match message.role {
+            MessageRole::System => out.push_str(&format!("[system]\n{}\n\n", message.content)),
+            MessageRole::User => out.push_str(&format!("[user]\n{}\n\n", message.content)),
+            MessageRole::Assistant => {
+                if !message.content.is_empty() {
+                    out.push_str(&format!("[assistant]\n{}\n\n", message.content));
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    out.push_str(&format!(
+                        "[assistant called {} ({})]\n{}\n\n",
+                        call.function.name, call.id, call.function.arguments
+                    ));
+                }
+            }
+            MessageRole::Tool => {
+                let id = message.tool_call_id.as_deref().unwrap_or("unknown");
+                let mut content: String = message.content.chars().take(TOOL_RESULT_MAX_CHARS).collect();
+                if content.len() < message.content.len() {
+                    content.push_str(" ...");
+                }
+                out.push_str(&format!("[tool result for {}]\n{}\n\n", id, content));
+            }
+        }
+    }
+    out
+}
@@ -1,6 +1,11 @@
-use pi_ai::{Message, MessageRole};
+use crate::branch::Branches;
+use crate::compaction::{estimate_tokens, CompactionRecord};
+use crate::error::{AgentError, AgentResult};
+use crate::store::{ContextStore, SessionInfo};
+use pi_ai::overflow::truncate_middle;
+use pi_ai::{Message, MessageRole, ToolCall};
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
@@ -10,6 +15,42 @@ pub struct Context {
     pub messages: VecDeque<Message>,
     pub max_messages: usize,
     pub metadata: ContextMetadata,
+    #[serde(default)]
+    pub window: WindowPolicy,
+    // messages 是活动分支的消息
+    #[serde(default)]
+    pub branches: Branches,
+    // 因超出窗口被丢弃的消息总数，只在内存里累计
+    #[serde(skip)]
+    trimmed: usize,
+}
+
+// 决定 context 里保留哪些消息。开头的 system 消息始终保留；其余消息按组从最早的开始丢弃，
+// 带 tool_calls 的 assistant 消息和它的 tool 结果是一组，不会被拆开
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WindowPolicy {
+    // 估算的 token 上限，为空时只按 max_messages 限制
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    // 超出 token 上限时先把过长的 tool 输出截断到这个字符数，仍然超出再丢弃消息
+    #[serde(default)]
+    pub max_tool_output_chars: Option<usize>,
+}
+
+impl WindowPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_max_tool_output_chars(mut self, max_chars: usize) -> Self {
+        self.max_tool_output_chars = Some(max_chars);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +60,15 @@ pub struct ContextMetadata {
     pub session_id: Option<String>,
     pub user_id: Option<String>,
     pub tags: Vec<String>,
+    // 为空时会话列表用第一条用户消息作为标题
+    #[serde(default)]
+    pub title: Option<String>,
+    // 最近一次请求实际使用的模型
+    #[serde(default)]
+    pub model: Option<String>,
+    // 按时间顺序，最后一条是最近一次压缩
+    #[serde(default)]
+    pub compactions: Vec<CompactionRecord>,
 }
 
 impl Default for ContextMetadata {
@@ -30,6 +80,9 @@ impl Default for ContextMetadata {
             session_id: None,
             user_id: None,
             tags: Vec::new(),
+            title: None,
+            model: None,
+            compactions: Vec::new(),
         }
     }
 }
@@ -41,6 +94,9 @@ impl Context {
             messages: VecDeque::new(),
             max_messages: 100,
             metadata: ContextMetadata::default(),
+            window: WindowPolicy::default(),
+            branches: Branches::default(),
+            trimmed: 0,
         }
     }
 
@@ -49,6 +105,17 @@ impl Context {
         self
     }
 
+    pub fn with_window(mut self, window: WindowPolicy) -> Self {
+        self.set_window(window);
+        self
+    }
+
+    // 立即按新的策略裁剪
+    pub fn set_window(&mut self, window: WindowPolicy) {
+        self.window = window;
+        self.trim();
+    }
+
     pub fn with_session_id(mut self, session_id: String) -> Self {
         self.metadata.session_id = Some(session_id);
         self
@@ -59,6 +126,11 @@ impl Context {
         self
     }
 
+    pub fn with_title(mut self, title: String) -> Self {
+        self.metadata.title = Some(title);
+        self
+    }
+
     pub fn add_message(&mut self, message: Message) {
         self.messages.push_back(message);
         self.trim();
@@ -81,6 +153,44 @@ impl Context {
         self.add_message(Message::tool(content, tool_call_id));
     }
 
+    // 修改已记录的 tool call 的参数（审批时被编辑），找不到这个调用时返回 false
+    pub fn set_tool_call_arguments(&mut self, tool_call_id: &str, arguments: String) -> bool {
+        let call = self
+            .messages
+            .iter_mut()
+            .rev()
+            .flat_map(|m| m.tool_calls.iter_mut().flatten())
+            .find(|call| call.id == tool_call_id);
+        match call {
+            Some(call) => {
+                call.function.arguments = arguments;
+                self.metadata.updated_at = chrono::Utc::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 最后一条 assistant 消息中还没有结果的 tool call，即等待审批的调用
+    pub fn pending_tool_calls(&self) -> Vec<ToolCall> {
+        let Some(index) = self.messages.iter().rposition(|m| m.role == MessageRole::Assistant) else {
+            return Vec::new();
+        };
+        let answered = self
+            .messages
+            .iter()
+            .skip(index + 1)
+            .filter_map(|m| m.tool_call_id.as_deref())
+            .collect::<HashSet<_>>();
+        self.messages[index]
+            .tool_calls
+            .iter()
+            .flatten()
+            .filter(|call| !answered.contains(call.id.as_str()))
+            .cloned()
+            .collect()
+    }
+
     pub fn get_messages(&self) -> Vec<Message> {
         self.messages.iter().cloned().collect()
     }
@@ -99,6 +209,7 @@ impl Context {
 
     pub fn clear(&mut self) {
         self.messages.clear();
+        self.clamp_fork_points();
         self.metadata.updated_at = chrono::Utc::now();
     }
 
@@ -113,23 +224,90 @@ impl Context {
         self.messages.len()
     }
 
+    // 和窗口、压缩用同一种估算，工具调用的参数也计入
     pub fn token_estimate(&self) -> usize {
-        self.messages
-            .iter()
-            .map(|m| m.content.len() / 4)
-            .sum()
+        estimate_tokens(&self.messages) as usize
+    }
+
+    fn over_token_budget(&self) -> bool {
+        self.window.max_tokens.is_some_and(|max| estimate_tokens(&self.messages) > max)
+    }
+
+    fn over_budget(&self) -> bool {
+        self.messages.len() > self.max_messages || self.over_token_budget()
     }
 
     fn trim(&mut self) {
-        while self.messages.len() > self.max_messages {
-            if let Some(role) = self.messages.front().map(|m| m.role.clone()) {
-                if role == MessageRole::System {
-                    self.messages.pop_front();
-                    continue;
-                }
+        if !self.over_budget() {
+            return;
+        }
+
+        // 超出 token 预算时先截断过长的 tool 输出
+        if let Some(max_chars) = self.window.max_tool_output_chars.filter(|_| self.over_token_budget()) {
+            for message in self.messages.iter_mut().filter(|m| m.role == MessageRole::Tool) {
+                truncate_middle(&mut message.content, max_chars);
             }
-            self.messages.pop_front();
         }
+
+        let pinned = self
+            .messages
+            .iter()
+            .take_while(|m| m.role == MessageRole::System)
+            .count();
+        let trimmed = self.trimmed;
+        while self.over_budget() {
+            let group = self.first_group_len(pinned);
+            // 最后一组（通常是刚加入的消息或进行中的工具调用）始终保留
+            if group == 0 || pinned + group >= self.messages.len() {
+                break;
+            }
+            self.messages.drain(pinned..pinned + group);
+            self.trimmed += group;
+        }
+        if self.trimmed > trimmed {
+            self.clamp_fork_points();
+        }
+    }
+
+    // pinned 之后第一组消息的长度：带 tool_calls 的 assistant 消息连同紧随其后的 tool 结果，
+    // 或者单条消息；开头孤立的 tool 结果也一并算进这一组
+    fn first_group_len(&self, pinned: usize) -> usize {
+        let mut rest = self.messages.iter().skip(pinned);
+        let Some(first) = rest.next() else {
+            return 0;
+        };
+        let has_calls = first.role == MessageRole::Assistant
+            && first.tool_calls.as_ref().is_some_and(|calls| !calls.is_empty());
+        if first.role != MessageRole::Tool && !has_calls {
+            return 1;
+        }
+        1 + rest.take_while(|m| m.role == MessageRole::Tool).count()
+    }
+
+    pub fn trimmed_count(&self) -> usize {
+        self.trimmed
+    }
+
+    // 撤销最近一次压缩，把摘要消息换回原来的消息。摘要已被后续压缩合并或被裁掉时
+    // 无法还原，返回 Err 且不修改 context
+    pub fn undo_compaction(&mut self) -> AgentResult<Option<CompactionRecord>> {
+        let Some(record) = self.metadata.compactions.last() else {
+            return Ok(None);
+        };
+        let position = self
+            .messages
+            .iter()
+            .position(|m| m.role == MessageRole::System && m.content == record.summary)
+            .ok_or_else(|| AgentError::Context("Compaction summary is no longer in the context".to_string()))?;
+
+        let record = self.metadata.compactions.pop().expect("checked above");
+        self.messages.remove(position);
+        for (offset, message) in record.replaced_messages.iter().enumerate() {
+            self.messages.insert(position + offset, message.clone());
+        }
+        self.clamp_fork_points();
+        self.metadata.updated_at = chrono::Utc::now();
+        Ok(Some(record))
     }
 
     pub fn add_tag(&mut self, tag: String) {
@@ -147,52 +325,107 @@ impl Context {
     }
 }
 
+// 内存中缓存已加载的会话；配置了 ContextStore 时按需加载并在每次修改后写回
 #[derive(Clone)]
 pub struct ContextManager {
     contexts: Arc<RwLock<std::collections::HashMap<String, Context>>>,
+    store: Option<Arc<dyn ContextStore>>,
 }
 
 impl ContextManager {
     pub fn new() -> Self {
         Self {
             contexts: Arc::new(RwLock::new(std::collections::HashMap::new())),
+            store: None,
         }
     }
 
-    pub async fn create(&self, id: String) -> Context {
-        let context = Context::new(id.clone());
-        let mut contexts = self.contexts.write().await;
-        contexts.insert(id, context.clone());
-        context
+    pub fn with_store(store: Arc<dyn ContextStore>) -> Self {
+        Self {
+            store: Some(store),
+            ..Self::new()
+        }
     }
 
-    pub async fn get(&self, id: &str) -> Option<Context> {
-        let contexts = self.contexts.read().await;
-        contexts.get(id).cloned()
+    pub fn store(&self) -> Option<&Arc<dyn ContextStore>> {
+        self.store.as_ref()
+    }
+
+    pub async fn create(&self, id: String) -> AgentResult<Context> {
+        let context = Context::new(id);
+        self.save(context.clone()).await?;
+        Ok(context)
+    }
+
+    pub async fn get(&self, id: &str) -> AgentResult<Option<Context>> {
+        if let Some(context) = self.contexts.read().await.get(id) {
+            return Ok(Some(context.clone()));
+        }
+        let Some(store) = &self.store else {
+            return Ok(None);
+        };
+
+        let Some(context) = store.load(id).await? else {
+            return Ok(None);
+        };
+        let mut contexts = self.contexts.write().await;
+        // 加载期间可能已有其他调用写入，以内存中的为准
+        let context = contexts.entry(id.to_string()).or_insert(context);
+        Ok(Some(context.clone()))
     }
 
     pub async fn update<F>(&self, id: &str, f: F) -> AgentResult<()>
     where
         F: FnOnce(&mut Context),
     {
-        let mut contexts = self.contexts.write().await;
-        let context = contexts
-            .get_mut(id)
+        let mut context = self
+            .get(id)
+            .await?
             .ok_or_else(|| AgentError::Context(format!("Context {} not found", id)))?;
-        f(context);
+        f(&mut context);
+        self.save(context).await
+    }
+
+    // 按 context.id 插入或覆盖
+    pub async fn save(&self, context: Context) -> AgentResult<()> {
+        if let Some(store) = &self.store {
+            store.save(&context).await?;
+        }
+        let mut contexts = self.contexts.write().await;
+        contexts.insert(context.id.clone(), context);
         Ok(())
     }
 
-    pub async fn delete(&self, id: &str) {
+    pub async fn delete(&self, id: &str) -> AgentResult<()> {
+        if let Some(store) = &self.store {
+            store.delete(id).await?;
+        }
         let mut contexts = self.contexts.write().await;
         contexts.remove(id);
+        Ok(())
     }
 
+    // 只返回已加载到内存中的会话，完整列表见 sessions
     pub async fn list(&self) -> Vec<Context> {
         let contexts = self.contexts.read().await;
         contexts.values().cloned().collect()
     }
 
+    // 所有会话的摘要，不会加载消息；按 updated_at 从新到旧排列
+    pub async fn sessions(&self) -> AgentResult<Vec<SessionInfo>> {
+        let mut sessions = match &self.store {
+            Some(store) => store.list().await?,
+            None => Vec::new(),
+        };
+
+        let contexts = self.contexts.read().await;
+        sessions.retain(|session| !contexts.contains_key(&session.id));
+        sessions.extend(contexts.values().map(SessionInfo::from_context));
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.updated_at));
+        Ok(sessions)
+    }
+
+    // 只清空内存缓存，不删除已持久化的会话
     pub async fn clear(&self) {
         let mut contexts = self.contexts.write().await;
         contexts.clear();
@@ -204,5 +437,3 @@ impl Default for ContextManager {
         Self::new()
     }
 }
-
-use crate::error::{AgentError, AgentResult};
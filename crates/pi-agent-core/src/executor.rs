@@ -1,12 +1,30 @@
+use crate::approval::{ApprovalDecision, ApprovalHandler, ApprovalPolicy, ApprovalRequest, PolicyAction};
+use crate::compaction::{CompactionConfig, CompactionRecord, Compactor};
 use crate::context::Context;
 use crate::error::{AgentError, AgentResult};
+use crate::observer::EventBus;
+use crate::rewind::Rewind;
 use crate::state::StateStore;
 use crate::tool_registry::ToolRegistry;
-use pi_ai::{Client, Message};
+use pi_ai::models::Usage;
+use pi_ai::tool_ids::NOT_EXECUTED;
+use pi_ai::{Client, LlmBackend, Message, ToolCall};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use std::pin::Pin;
+use std::time::{Duration, Instant};
 use futures::StreamExt;
+use tokio::sync::mpsc;
+
+// 事件通道的容量；消费方跟不上时执行循环会等待
+const EVENT_BUFFER: usize = 64;
+
+// 被拒绝的调用写入 context 的结果的开头，回滚时据此跳过没有执行过的调用
+const DENIED_PREFIX: &str = "Tool call denied: ";
+
+// 审批时参数被修改过，附在工具结果后面告诉模型
+const EDITED_NOTE: &str = "Note: the user edited the arguments before this call ran. Original arguments: ";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExecutorConfig {
@@ -15,6 +33,22 @@ pub struct ExecutorConfig {
     pub enable_tools: bool,
     pub auto_tool_execution: bool,
     pub stop_on_tool_error: bool,
+    // 同一轮里最多同时执行的 tool call 数
+    #[serde(default = "default_max_concurrent_tools")]
+    pub max_concurrent_tools: usize,
+    // 单个 tool call 的超时，工具自己声明的超时优先
+    #[serde(default)]
+    pub tool_timeout_secs: Option<u64>,
+    // 为空时不压缩，超出窗口的消息直接丢弃
+    #[serde(default)]
+    pub compaction: Option<CompactionConfig>,
+    // 撤销、编辑或重新生成时，对被移除的工具调用调用 ToolHandler::rollback
+    #[serde(default)]
+    pub rollback_tools: bool,
+}
+
+fn default_max_concurrent_tools() -> usize {
+    4
 }
 
 impl Default for ExecutorConfig {
@@ -25,6 +59,97 @@ impl Default for ExecutorConfig {
             enable_tools: true,
             auto_tool_execution: true,
             stop_on_tool_error: false,
+            max_concurrent_tools: default_max_concurrent_tools(),
+            tool_timeout_secs: None,
+            compaction: None,
+            rollback_tools: false,
+        }
+    }
+}
+
+// 模型选择和采样参数。provider 为空时 model 可以写成 "provider/model"；
+// model 本身带斜杠（如 "meta-llama/Llama-3"）时需要显式指定 provider
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelSettings {
+    #[serde(default)]
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+}
+
+impl ModelSettings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_provider(mut self, provider: String) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    // 返回 (provider, model)，两者都可能需要由后端的默认值补齐
+    pub fn address(&self) -> (Option<String>, Option<String>) {
+        match (&self.provider, &self.model) {
+            (Some(provider), model) => {
+                let model = model.as_ref().map(|model| {
+                    model
+                        .strip_prefix(provider.as_str())
+                        .and_then(|rest| rest.strip_prefix('/'))
+                        .unwrap_or(model)
+                        .to_string()
+                });
+                (Some(provider.clone()), model)
+            }
+            (None, Some(model)) => match model.split_once('/') {
+                Some((provider, model)) if !provider.is_empty() && !model.is_empty() => {
+                    (Some(provider.to_string()), Some(model.to_string()))
+                }
+                _ => (None, Some(model.clone())),
+            },
+            (None, None) => (None, None),
+        }
+    }
+
+    // overrides 中设置的字段优先；换了 provider 时不沿用原来的 model
+    pub fn merged_with(&self, overrides: &ModelSettings) -> ModelSettings {
+        let (provider, model) = match overrides.address() {
+            (Some(provider), model) => (Some(provider), model),
+            (None, Some(model)) => (self.address().0, Some(model)),
+            (None, None) => self.address(),
+        };
+
+        ModelSettings {
+            provider,
+            model,
+            temperature: overrides.temperature.or(self.temperature),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            top_p: overrides.top_p.or(self.top_p),
         }
     }
 }
@@ -45,39 +170,168 @@ pub struct ToolCallInfo {
     pub arguments: String,
     pub result: Option<String>,
     pub success: bool,
+    // 被策略或 ApprovalHandler 拒绝，result 中是拒绝原因
+    #[serde(default)]
+    pub denied: bool,
+    // 等待审批，还没有结果，见 Executor::resolve_pending
+    #[serde(default)]
+    pub pending: bool,
+}
+
+impl ToolCallInfo {
+    fn new(tool_call: &ToolCall) -> Self {
+        Self {
+            id: tool_call.id.clone(),
+            name: tool_call.function.name.clone(),
+            arguments: tool_call.function.arguments.clone(),
+            result: None,
+            success: false,
+            denied: false,
+            pending: false,
+        }
+    }
+}
+
+// 审批后每个 tool call 的处理方式
+enum Review {
+    Run(ToolCall),
+    Denied(String),
+    // 没有 ApprovalHandler 且关闭了自动执行，不写入结果，等待 resolve_pending
+    Pending,
 }
 
 #[derive(Debug, Clone)]
-pub enum StreamEvent {
-    Token(String),
-    ToolCall { id: String, name: String, arguments: String },
-    Done,
+pub enum AgentEvent {
+    TurnStarted { iteration: usize },
+    // messages 是发出请求时 context 中的消息数
+    LlmRequest { provider: String, model: String, messages: usize },
+    // 流式调用时在流结束后给出
+    LlmResponse { provider: String, model: String, duration: Duration },
+    TextDelta(String),
+    ToolCallStarted { id: String, name: String },
+    ToolCallDelta { id: String, arguments: String },
+    ToolCallFinished { id: String, name: String, arguments: String },
+    ToolResult(ToolCallInfo),
+    Usage(Usage),
+    // 超出窗口（Context::window、max_messages），较早的消息被丢弃
+    ContextTrimmed { removed: usize },
+    // 较早的 summarized 条消息被替换成一条摘要
+    ContextCompacted { summarized: usize, tokens_before: u32, tokens_after: u32 },
+    // 撤销、编辑或重新生成时移除了末尾的 removed 条消息，其中 rolled_back 个工具调用已回滚
+    Rewound { removed: usize, rolled_back: usize, failures: Vec<String> },
+    // 本轮 assistant 消息已写入 context
+    TurnFinished { iteration: usize, message: Message },
+    // provider 在流中返回的错误，随后仍会给出 Finished；
+    // 中断执行的错误只发往 EventBus，不会再有 Finished
     Error(String),
+    Finished(ExecutionResult),
 }
 
-pub struct Executor {
+pub type AgentEventStream<'a> = Pin<Box<dyn futures::Stream<Item = AgentResult<AgentEvent>> + Send + 'a>>;
+
+pub struct Executor<B: LlmBackend + ?Sized = Client> {
     config: ExecutorConfig,
-    llm_client: Arc<Client>,
+    model_settings: ModelSettings,
+    llm_client: Arc<B>,
     tool_registry: Arc<ToolRegistry>,
     state_store: Arc<StateStore>,
+    approval_handler: Option<Arc<dyn ApprovalHandler>>,
+    approval_policy: ApprovalPolicy,
+    // (context id, 工具名)，由 ApproveAlways 加入
+    always_approved: Arc<Mutex<HashSet<(String, String)>>>,
+    events: EventBus,
+    compactor: Option<Arc<Compactor>>,
+}
+
+// 手写 Clone，避免要求后端本身实现 Clone
+impl<B: LlmBackend + ?Sized> Clone for Executor<B> {
+    fn clone(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            model_settings: self.model_settings.clone(),
+            llm_client: self.llm_client.clone(),
+            tool_registry: self.tool_registry.clone(),
+            state_store: self.state_store.clone(),
+            approval_handler: self.approval_handler.clone(),
+            approval_policy: self.approval_policy.clone(),
+            always_approved: self.always_approved.clone(),
+            events: self.events.clone(),
+            compactor: self.compactor.clone(),
+        }
+    }
 }
 
-impl Executor {
+impl<B: LlmBackend + ?Sized> Executor<B> {
     pub fn new(
         config: ExecutorConfig,
-        llm_client: Arc<Client>,
+        llm_client: Arc<B>,
         tool_registry: Arc<ToolRegistry>,
         state_store: Arc<StateStore>,
     ) -> Self {
+        let compactor = config.compaction.clone().map(|compaction| Arc::new(Compactor::new(compaction)));
         Self {
             config,
+            model_settings: ModelSettings::default(),
             llm_client,
             tool_registry,
             state_store,
+            approval_handler: None,
+            approval_policy: ApprovalPolicy::default(),
+            always_approved: Arc::new(Mutex::new(HashSet::new())),
+            events: EventBus::default(),
+            compactor,
         }
     }
 
+    pub fn with_model_settings(mut self, settings: ModelSettings) -> Self {
+        self.model_settings = settings;
+        self
+    }
+
+    pub fn with_approval_handler(mut self, handler: Arc<dyn ApprovalHandler>) -> Self {
+        self.approval_handler = Some(handler);
+        self
+    }
+
+    pub fn with_approval_policy(mut self, policy: ApprovalPolicy) -> Self {
+        self.approval_policy = policy;
+        self
+    }
+
+    // 多个 executor 可以共用一个 EventBus
+    pub fn with_event_bus(mut self, events: EventBus) -> Self {
+        self.events = events;
+        self
+    }
+
     pub async fn execute(&self, context: &mut Context) -> AgentResult<ExecutionResult> {
+        self.execute_with(context, &ModelSettings::default()).await
+    }
+
+    // overrides 只对这一次调用生效
+    pub async fn execute_with(
+        &self,
+        context: &mut Context,
+        overrides: &ModelSettings,
+    ) -> AgentResult<ExecutionResult> {
+        let events = EventSink {
+            bus: &self.events,
+            context_id: context.id.clone(),
+            stream: None,
+        };
+        let result = self.run_turns(context, overrides, &events).await;
+        if let Err(e) = &result {
+            self.events.publish(&events.context_id, &AgentEvent::Error(e.to_string()));
+        }
+        result
+    }
+
+    async fn run_turns(
+        &self,
+        context: &mut Context,
+        overrides: &ModelSettings,
+        events: &EventSink<'_>,
+    ) -> AgentResult<ExecutionResult> {
         let mut iterations = 0;
         let mut executed_tool_calls = Vec::new();
         let mut success = true;
@@ -85,143 +339,686 @@ impl Executor {
 
         while iterations < self.config.max_iterations {
             iterations += 1;
+            let trimmed = context.trimmed_count();
+            events.emit(AgentEvent::TurnStarted { iteration: iterations }).await?;
+            self.compact_if_needed(context, overrides, events).await?;
 
-            let messages = context.get_messages();
-            let request = pi_ai::models::ChatCompletionRequest {
-                model: "gpt-4".to_string(),
-                messages: messages.clone(),
-                tools: if self.config.enable_tools {
-                    Some(self.tool_registry.get_tool_definitions().await)
-                } else {
-                    None
-                },
-                tool_choice: None,
-                temperature: None,
-                top_p: None,
-                max_tokens: None,
-                stream: Some(false),
-                stop: None,
-                presence_penalty: None,
-                frequency_penalty: None,
-                user: None,
-            };
-
-            let provider_name = "openai";
-            let response = self.llm_client.chat(provider_name, request).await?;
+            let (provider_name, request) = self.build_request(context, overrides, false).await?;
+            let model = request.model.clone();
+            context.metadata.model = Some(format!("{}/{}", provider_name, model));
+            events
+                .emit(AgentEvent::LlmRequest {
+                    provider: provider_name.clone(),
+                    model: model.clone(),
+                    messages: request.messages.len(),
+                })
+                .await?;
+            let started_at = Instant::now();
+            let response = self.llm_client.chat(&provider_name, request).await?;
+            events
+                .emit(AgentEvent::LlmResponse {
+                    provider: provider_name,
+                    model,
+                    duration: started_at.elapsed(),
+                })
+                .await?;
+            events.emit(AgentEvent::Usage(response.usage.clone())).await?;
 
+            let mut again = false;
             if let Some(choice) = response.choices.first() {
                 let message = &choice.message;
                 context.add_message(message.clone());
-
-                if let Some(tool_calls) = &message.tool_calls {
-                    for tool_call in tool_calls {
-                        let tool_call_info = ToolCallInfo {
+                for tool_call in message.tool_calls.iter().flatten() {
+                    events
+                        .emit(AgentEvent::ToolCallFinished {
                             id: tool_call.id.clone(),
                             name: tool_call.function.name.clone(),
                             arguments: tool_call.function.arguments.clone(),
-                            result: None,
-                            success: false,
-                        };
+                        })
+                        .await?;
+                }
+                events
+                    .emit(AgentEvent::TurnFinished { iteration: iterations, message: message.clone() })
+                    .await?;
 
-                        if self.config.auto_tool_execution {
-                            match self.execute_tool(tool_call).await {
-                                Ok(result) => {
-                                    context.add_tool_message(
-                                        result.clone(),
-                                        tool_call.id.clone(),
-                                    );
-                                    executed_tool_calls.push(ToolCallInfo {
-                                        result: Some(result),
-                                        success: true,
-                                        ..tool_call_info
-                                    });
-                                }
-                                Err(e) => {
-                                    if self.config.stop_on_tool_error {
-                                        success = false;
-                                        error = Some(e.to_string());
-                                        break;
-                                    }
-                                    context.add_tool_message(
-                                        format!("Error: {}", e),
-                                        tool_call.id.clone(),
-                                    );
-                                    executed_tool_calls.push(ToolCallInfo {
-                                        result: Some(format!("Error: {}", e)),
-                                        success: false,
-                                        ..tool_call_info
-                                    });
-                                }
+                if let Some(tool_calls) = &message.tool_calls {
+                    let before = executed_tool_calls.len();
+                    let mut denied = false;
+                    let mut stopped = false;
+                    match self.run_tool_calls(context, tool_calls, &mut executed_tool_calls).await {
+                        Ok(any_denied) => denied = any_denied,
+                        Err(e) => {
+                            success = false;
+                            error = Some(e);
+                            stopped = true;
+                        }
+                    }
+                    events.emit_tool_results(&executed_tool_calls[before..]).await?;
+
+                    // 拒绝原因已作为工具结果写入，让模型继续回应；有调用等待审批时停下
+                    let new = &executed_tool_calls[before..];
+                    again = !stopped
+                        && !new.iter().any(|t| t.pending)
+                        && (denied || new.iter().any(|t| t.success));
+                }
+            }
+
+            events.emit_trimmed(context, trimmed).await?;
+            if !again {
+                break;
+            }
+        }
+
+        let result = ExecutionResult {
+            messages: context.get_messages(),
+            tool_calls: executed_tool_calls,
+            iterations,
+            success,
+            error,
+        };
+        events.emit(AgentEvent::Finished(result.clone())).await?;
+        Ok(result)
+    }
+
+    pub fn execute_stream<'a>(&'a self, context: &'a mut Context) -> AgentEventStream<'a> {
+        self.execute_stream_with(context, ModelSettings::default())
+    }
+
+    // 和 execute 相同的多轮工具循环，边执行边产出事件并更新 context；最后一个事件是 Finished
+    pub fn execute_stream_with<'a>(&'a self, context: &'a mut Context, overrides: ModelSettings) -> AgentEventStream<'a> {
+        let (tx, rx) = mpsc::channel(EVENT_BUFFER);
+        let driver = async move {
+            let events = EventSink {
+                bus: &self.events,
+                context_id: context.id.clone(),
+                stream: Some(&tx),
+            };
+            if let Err(e) = self.stream_turns(context, &overrides, &events).await {
+                self.events.publish(&events.context_id, &AgentEvent::Error(e.to_string()));
+                let _ = tx.send(Err(e)).await;
+            }
+        };
+
+        let events = futures::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|event| (event, rx)) });
+        let driver = futures::stream::once(driver).filter_map(|()| futures::future::ready(None));
+        Box::pin(futures::stream::select(events, driver))
+    }
+
+    async fn stream_turns(
+        &self,
+        context: &mut Context,
+        overrides: &ModelSettings,
+        events: &EventSink<'_>,
+    ) -> AgentResult<()> {
+        let mut iterations = 0;
+        let mut executed_tool_calls = Vec::new();
+        let mut success = true;
+        let mut error = None;
+
+        while iterations < self.config.max_iterations {
+            iterations += 1;
+            let trimmed = context.trimmed_count();
+            events.emit(AgentEvent::TurnStarted { iteration: iterations }).await?;
+            self.compact_if_needed(context, overrides, events).await?;
+
+            let (provider_name, request) = self.build_request(context, overrides, true).await?;
+            let model = request.model.clone();
+            context.metadata.model = Some(format!("{}/{}", provider_name, model));
+            events
+                .emit(AgentEvent::LlmRequest {
+                    provider: provider_name.clone(),
+                    model: model.clone(),
+                    messages: request.messages.len(),
+                })
+                .await?;
+            let started_at = Instant::now();
+            let mut stream = self.llm_client.chat_stream(&provider_name, request).await?;
+
+            let mut text = String::new();
+            let mut tool_calls = Vec::new();
+            let mut reasoning = Vec::new();
+            // 分片按 index 关联到调用 id
+            let mut call_ids: HashMap<u32, String> = HashMap::new();
+            let mut started: HashSet<String> = HashSet::new();
+            let mut stream_error = None;
+
+            while let Some(event) = stream.next().await {
+                match event? {
+                    pi_ai::StreamEvent::Token { text: token, .. } => {
+                        text.push_str(&token);
+                        events.emit(AgentEvent::TextDelta(token)).await?;
+                    }
+                    pi_ai::StreamEvent::ToolCallDelta { index, id, name, arguments, .. } => {
+                        if let Some(id) = id {
+                            call_ids.insert(index, id.clone());
+                            if started.insert(id.clone()) {
+                                let name = name.unwrap_or_default();
+                                events.emit(AgentEvent::ToolCallStarted { id, name }).await?;
                             }
-                        } else {
-                            executed_tool_calls.push(tool_call_info);
                         }
+                        if let Some(id) = call_ids.get(&index).filter(|_| !arguments.is_empty()) {
+                            let id = id.clone();
+                            events.emit(AgentEvent::ToolCallDelta { id, arguments }).await?;
+                        }
+                    }
+                    pi_ai::StreamEvent::ToolCall { id, name, arguments } => {
+                        if started.insert(id.clone()) {
+                            events.emit(AgentEvent::ToolCallStarted { id: id.clone(), name: name.clone() }).await?;
+                        }
+                        tool_calls.push(ToolCall {
+                            id: id.clone(),
+                            tool_type: "function".to_string(),
+                            function: pi_ai::message::FunctionCall {
+                                name: name.clone(),
+                                arguments: arguments.clone(),
+                            },
+                        });
+                        events.emit(AgentEvent::ToolCallFinished { id, name, arguments }).await?;
+                    }
+                    pi_ai::StreamEvent::Usage(usage) => events.emit(AgentEvent::Usage(usage)).await?,
+                    pi_ai::StreamEvent::Reasoning(item) => reasoning.push(item),
+                    pi_ai::StreamEvent::Error(err) => {
+                        stream_error = Some(err.clone());
+                        events.emit(AgentEvent::Error(err)).await?;
                     }
+                    // 这里的请求不会开启 logprobs 或服务端工具
+                    pi_ai::StreamEvent::Logprobs { .. }
+                    | pi_ai::StreamEvent::Citation(_)
+                    | pi_ai::StreamEvent::HostedToolCall(_)
+                    | pi_ai::StreamEvent::FinishReason(_)
+                    | pi_ai::StreamEvent::ResponseId(_)
+                    | pi_ai::StreamEvent::Done => {}
+                }
+            }
+
+            events
+                .emit(AgentEvent::LlmResponse {
+                    provider: provider_name,
+                    model,
+                    duration: started_at.elapsed(),
+                })
+                .await?;
+
+            let mut message = Message::assistant(text);
+            if !tool_calls.is_empty() {
+                message = message.with_tool_calls(tool_calls.clone());
+            }
+            if !reasoning.is_empty() {
+                message = message.with_reasoning(reasoning);
+            }
+            context.add_message(message.clone());
+            events.emit(AgentEvent::TurnFinished { iteration: iterations, message }).await?;
+
+            if let Some(err) = stream_error {
+                success = false;
+                error = Some(err);
+                events.emit_trimmed(context, trimmed).await?;
+                break;
+            }
 
-                    if self.config.auto_tool_execution && executed_tool_calls.iter().any(|t| t.success) {
-                        continue;
+            let mut again = false;
+            if !tool_calls.is_empty() {
+                let before = executed_tool_calls.len();
+                let mut denied = false;
+                let mut stopped = false;
+                match self.run_tool_calls(context, &tool_calls, &mut executed_tool_calls).await {
+                    Ok(any_denied) => denied = any_denied,
+                    Err(e) => {
+                        success = false;
+                        error = Some(e);
+                        stopped = true;
                     }
                 }
+                events.emit_tool_results(&executed_tool_calls[before..]).await?;
+
+                let new = &executed_tool_calls[before..];
+                again = !stopped
+                    && !new.iter().any(|t| t.pending)
+                    && (denied || new.iter().any(|t| t.success));
             }
 
-            break;
+            events.emit_trimmed(context, trimmed).await?;
+            if !again {
+                break;
+            }
         }
 
-        Ok(ExecutionResult {
+        let result = ExecutionResult {
             messages: context.get_messages(),
             tool_calls: executed_tool_calls,
             iterations,
             success,
             error,
-        })
+        };
+        events.emit(AgentEvent::Finished(result)).await
+    }
+
+    // 先逐个审批，再执行一轮中的 tool call，并按原顺序把结果写回 context；
+    // 相邻的可并发调用合成一批并发执行，不可并发的工具单独成批。
+    // 返回是否有调用被拒绝。stop_on_tool_error 时，出错所在批次的结果照常写回，
+    // 之后的调用不再执行，写入 NOT_EXECUTED 结果，并返回第一个错误
+    async fn run_tool_calls(
+        &self,
+        context: &mut Context,
+        tool_calls: &[ToolCall],
+        executed_tool_calls: &mut Vec<ToolCallInfo>,
+    ) -> Result<bool, String> {
+        let mut reviews = Vec::with_capacity(tool_calls.len());
+        for tool_call in tool_calls {
+            reviews.push(self.review_tool_call(&context.id, tool_call).await);
+        }
+        self.apply_reviews(context, tool_calls, reviews, executed_tool_calls).await
     }
 
-    pub async fn execute_stream(
+    async fn apply_reviews(
         &self,
         context: &mut Context,
-    ) -> AgentResult<Pin<Box<dyn futures::Stream<Item = AgentResult<StreamEvent>> + Send>>> {
-        let messages = context.get_messages();
+        tool_calls: &[ToolCall],
+        reviews: Vec<Review>,
+        executed_tool_calls: &mut Vec<ToolCallInfo>,
+    ) -> Result<bool, String> {
+        let mut batches: Vec<Vec<usize>> = Vec::new();
+        let mut current = Vec::new();
+        for (i, review) in reviews.iter().enumerate() {
+            let Review::Run(tool_call) = review else {
+                continue;
+            };
+            let parallel_safe = self
+                .tool_registry
+                .get(&tool_call.function.name)
+                .await
+                .is_none_or(|tool| tool.parallel_safe());
+            if parallel_safe {
+                current.push(i);
+            } else {
+                if !current.is_empty() {
+                    batches.push(std::mem::take(&mut current));
+                }
+                batches.push(vec![i]);
+            }
+        }
+        if !current.is_empty() {
+            batches.push(current);
+        }
+
+        let limit = self.config.max_concurrent_tools.max(1);
+        let mut results: Vec<Option<AgentResult<String>>> = tool_calls.iter().map(|_| None).collect();
+        let mut denied = false;
+        let mut stopped = None;
+        let mut next = 0;
+
+        for batch in batches.into_iter().map(Some).chain(std::iter::once(None)) {
+            // 最后一个 None 用来写回批次之后剩下的拒绝/待定调用
+            let end = match batch {
+                Some(batch) if stopped.is_some() => batch.last().map_or(next, |last| last + 1),
+                Some(batch) => {
+                    let pending = batch
+                        .iter()
+                        .filter_map(|&i| match &reviews[i] {
+                            Review::Run(tool_call) => Some(async move { (i, self.execute_tool(tool_call).await) }),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    let executed = futures::stream::iter(pending)
+                        .buffer_unordered(limit)
+                        .collect::<Vec<_>>()
+                        .await;
+                    for (i, result) in executed {
+                        results[i] = Some(result);
+                    }
+                    batch.last().map_or(next, |last| last + 1)
+                }
+                None => tool_calls.len(),
+            };
+
+            for i in next..end {
+                let mut tool_call_info = ToolCallInfo::new(&tool_calls[i]);
+                match (&reviews[i], results[i].take()) {
+                    (Review::Run(tool_call), Some(result)) => {
+                        let (mut output, success) = match result {
+                            Ok(output) => (output, true),
+                            Err(e) => {
+                                if self.config.stop_on_tool_error && stopped.is_none() {
+                                    stopped = Some(e.to_string());
+                                }
+                                (format!("Error: {}", e), false)
+                            }
+                        };
+                        // context 里记录实际执行的参数，回滚时也按它处理
+                        let original = &tool_calls[i].function.arguments;
+                        if tool_call.function.arguments != *original {
+                            context.set_tool_call_arguments(&tool_call.id, tool_call.function.arguments.clone());
+                            output.push_str(&format!("\n\n{}{}", EDITED_NOTE, original));
+                        }
+                        context.add_tool_message(output.clone(), tool_call.id.clone());
+                        tool_call_info.arguments = tool_call.function.arguments.clone();
+                        tool_call_info.result = Some(output);
+                        tool_call_info.success = success;
+                    }
+                    // 前面的调用出错后没有执行
+                    (Review::Run(tool_call), None) => {
+                        context.add_tool_message(NOT_EXECUTED.to_string(), tool_call.id.clone());
+                        tool_call_info.result = Some(NOT_EXECUTED.to_string());
+                    }
+                    (Review::Denied(reason), _) => {
+                        let message = format!("{}{}", DENIED_PREFIX, reason);
+                        context.add_tool_message(message.clone(), tool_calls[i].id.clone());
+                        tool_call_info.result = Some(message);
+                        tool_call_info.denied = true;
+                        denied = true;
+                    }
+                    (Review::Pending, _) => tool_call_info.pending = true,
+                }
+                executed_tool_calls.push(tool_call_info);
+            }
+            next = end;
+        }
+
+        match stopped {
+            Some(e) => Err(e),
+            None => Ok(denied),
+        }
+    }
+
+    // 顺序：本 context 已 ApproveAlways 的工具 -> 策略规则 -> ApprovalHandler
+    async fn review_tool_call(&self, context_id: &str, tool_call: &ToolCall) -> Review {
+        let name = &tool_call.function.name;
+        let key = (context_id.to_string(), name.clone());
+        if self.always_approved.lock().unwrap().contains(&key) {
+            return Review::Run(tool_call.clone());
+        }
+
+        match self.approval_policy.evaluate(name, &tool_call.function.arguments) {
+            PolicyAction::Allow => return Review::Run(tool_call.clone()),
+            PolicyAction::Deny => return Review::Denied(format!("{} is not allowed by policy", name)),
+            PolicyAction::Ask => {}
+        }
+
+        let Some(handler) = &self.approval_handler else {
+            return if self.config.auto_tool_execution {
+                Review::Run(tool_call.clone())
+            } else {
+                Review::Pending
+            };
+        };
+
+        let request = ApprovalRequest {
+            context_id: context_id.to_string(),
+            tool_call_id: tool_call.id.clone(),
+            tool_name: name.clone(),
+            arguments: tool_call.function.arguments.clone(),
+        };
+        let decision = handler.review(&request).await;
+        self.apply_decision(context_id, tool_call, decision)
+    }
+
+    fn apply_decision(&self, context_id: &str, tool_call: &ToolCall, decision: AgentResult<ApprovalDecision>) -> Review {
+        match decision {
+            Ok(ApprovalDecision::Approve) => Review::Run(tool_call.clone()),
+            Ok(ApprovalDecision::ApproveAlways) => {
+                let key = (context_id.to_string(), tool_call.function.name.clone());
+                self.always_approved.lock().unwrap().insert(key);
+                Review::Run(tool_call.clone())
+            }
+            Ok(ApprovalDecision::Edit { arguments }) => {
+                let mut tool_call = tool_call.clone();
+                tool_call.function.arguments = arguments;
+                Review::Run(tool_call)
+            }
+            Ok(ApprovalDecision::Deny { reason }) => Review::Denied(reason),
+            // 审批失败时按拒绝处理
+            Err(e) => Review::Denied(format!("approval failed: {}", e)),
+        }
+    }
+
+    // 解析出本次调用的 provider 和完整请求
+    // 返回 (provider, model, 合并后的设置)
+    fn resolve_model(&self, overrides: &ModelSettings) -> AgentResult<(String, String, ModelSettings)> {
+        let settings = self.model_settings.merged_with(overrides);
+        let (provider, model) = settings.address();
+        let provider = provider
+            .or_else(|| self.llm_client.default_provider())
+            .ok_or_else(|| AgentError::InvalidConfig("No provider configured for the agent".to_string()))?;
+        let model = model
+            .or_else(|| self.llm_client.default_model(&provider))
+            .ok_or_else(|| AgentError::InvalidConfig(format!("No model configured for provider {}", provider)))?;
+        Ok((provider, model, settings))
+    }
+
+    // 立即压缩，不检查阈值；没有配置 compaction 时使用默认配置
+    pub async fn compact(
+        &self,
+        context: &mut Context,
+        overrides: &ModelSettings,
+    ) -> AgentResult<Option<CompactionRecord>> {
+        let (provider, model, _) = self.resolve_model(overrides)?;
+        let record = match &self.compactor {
+            Some(compactor) => compactor.compact(&*self.llm_client, &provider, &model, context).await?,
+            None => {
+                Compactor::new(CompactionConfig::default())
+                    .compact(&*self.llm_client, &provider, &model, context)
+                    .await?
+            }
+        };
+        if let Some(record) = &record {
+            self.events.publish(&context.id, &compacted_event(record));
+        }
+        Ok(record)
+    }
+
+    // 撤销最近一轮，没有 user 消息时返回 None
+    pub async fn undo_turn(&self, context: &mut Context) -> AgentResult<Option<Rewind>> {
+        let Some(rewind) = context.undo_turn() else {
+            return Ok(None);
+        };
+        self.finish_rewind(context, &rewind).await;
+        Ok(Some(rewind))
+    }
+
+    // 处理等待审批的 tool call（见 Context::pending_tool_calls），然后继续执行循环。
+    // decisions 以 tool call id 为键，没有给出决定的调用按拒绝处理
+    pub async fn resolve_pending(
+        &self,
+        context: &mut Context,
+        mut decisions: HashMap<String, ApprovalDecision>,
+        overrides: &ModelSettings,
+    ) -> AgentResult<ExecutionResult> {
+        let tool_calls = context.pending_tool_calls();
+        if tool_calls.is_empty() {
+            return Err(AgentError::Context("No tool calls are waiting for approval".to_string()));
+        }
+        let reviews = tool_calls
+            .iter()
+            .map(|tool_call| match decisions.remove(&tool_call.id) {
+                Some(decision) => self.apply_decision(&context.id, tool_call, Ok(decision)),
+                None => Review::Denied("no decision was given".to_string()),
+            })
+            .collect();
+
+        let events = EventSink {
+            bus: &self.events,
+            context_id: context.id.clone(),
+            stream: None,
+        };
+        let mut resolved = Vec::new();
+        let outcome = self.apply_reviews(context, &tool_calls, reviews, &mut resolved).await;
+        events.emit_tool_results(&resolved).await?;
+        if let Err(e) = outcome {
+            return Ok(ExecutionResult {
+                messages: context.get_messages(),
+                tool_calls: resolved,
+                iterations: 0,
+                success: false,
+                error: Some(e),
+            });
+        }
+
+        let mut result = self.execute_with(context, overrides).await?;
+        result.tool_calls.splice(0..0, resolved);
+        Ok(result)
+    }
+
+    // 丢弃最后一条 user 消息之后的回复，重新请求
+    pub async fn regenerate(&self, context: &mut Context, overrides: &ModelSettings) -> AgentResult<ExecutionResult> {
+        let rewind = context.pop_response()?;
+        self.finish_rewind(context, &rewind).await;
+        self.execute_with(context, overrides).await
+    }
+
+    // 修改第 index 条 user 消息，丢弃之后的内容并从这里重新执行
+    pub async fn edit_and_rerun(
+        &self,
+        context: &mut Context,
+        index: usize,
+        content: String,
+        overrides: &ModelSettings,
+    ) -> AgentResult<ExecutionResult> {
+        let rewind = context.edit_message(index, content)?;
+        self.finish_rewind(context, &rewind).await;
+        self.execute_with(context, overrides).await
+    }
+
+    // 按逆序回滚被撤销的工具调用，不检查 rollback_tools；被拒绝或没有执行的调用会跳过。
+    // 单个失败不影响其他调用，所有失败汇总成一个错误
+    pub async fn rollback(&self, rewind: &Rewind) -> AgentResult<usize> {
+        let (rolled_back, failures) = self.rollback_tool_calls(rewind).await;
+        if failures.is_empty() {
+            Ok(rolled_back)
+        } else {
+            Err(AgentError::ToolExecution(format!("Rollback failed: {}", failures.join("; "))))
+        }
+    }
+
+    async fn rollback_tool_calls(&self, rewind: &Rewind) -> (usize, Vec<String>) {
+        let mut rolled_back = 0;
+        let mut failures = Vec::new();
+        for rewound in rewind.tool_calls().into_iter().rev() {
+            let executed = rewound
+                .output
+                .as_deref()
+                .is_some_and(|output| !output.starts_with(DENIED_PREFIX) && output != NOT_EXECUTED);
+            if !executed {
+                continue;
+            }
+            let name = &rewound.call.function.name;
+            let Some(tool) = self.tool_registry.get(name).await else {
+                failures.push(format!("{}: tool is no longer registered", name));
+                continue;
+            };
+            let result = match serde_json::from_str(&rewound.call.function.arguments) {
+                Ok(arguments) => tool.rollback(arguments, rewound.output.as_deref()).await,
+                Err(e) => Err(AgentError::InvalidToolArguments(e.to_string())),
+            };
+            match result {
+                Ok(()) => rolled_back += 1,
+                Err(e) => failures.push(format!("{} ({}): {}", name, rewound.call.id, e)),
+            }
+        }
+        (rolled_back, failures)
+    }
+
+    // 回滚失败不会撤回已经移除的消息，只记录日志并在事件中给出
+    async fn finish_rewind(&self, context: &Context, rewind: &Rewind) {
+        if rewind.is_empty() {
+            return;
+        }
+        let (rolled_back, failures) = if self.config.rollback_tools {
+            self.rollback_tool_calls(rewind).await
+        } else {
+            (0, Vec::new())
+        };
+        for failure in &failures {
+            tracing::warn!("Tool rollback failed: {}", failure);
+        }
+        self.events.publish(
+            &context.id,
+            &AgentEvent::Rewound {
+                removed: rewind.removed.len(),
+                rolled_back,
+                failures,
+            },
+        );
+    }
+
+    async fn compact_if_needed(
+        &self,
+        context: &mut Context,
+        overrides: &ModelSettings,
+        events: &EventSink<'_>,
+    ) -> AgentResult<()> {
+        let Some(compactor) = &self.compactor else {
+            return Ok(());
+        };
+        let (provider, model, _) = self.resolve_model(overrides)?;
+        if !compactor.should_compact(&*self.llm_client, &provider, &model, context).await {
+            return Ok(());
+        }
+
+        match compactor.compact(&*self.llm_client, &provider, &model, context).await {
+            Ok(Some(record)) => events.emit(compacted_event(&record)).await,
+            Ok(None) => Ok(()),
+            // 压缩失败不影响本轮请求，消息过多时仍按窗口裁剪
+            Err(e) => {
+                tracing::warn!("Context compaction failed: {}", e);
+                Ok(())
+            }
+        }
+    }
+
+    async fn build_request(
+        &self,
+        context: &Context,
+        overrides: &ModelSettings,
+        stream: bool,
+    ) -> AgentResult<(String, pi_ai::models::ChatCompletionRequest)> {
+        let (provider, model, settings) = self.resolve_model(overrides)?;
+
         let request = pi_ai::models::ChatCompletionRequest {
-            model: "gpt-4".to_string(),
-            messages: messages.clone(),
+            model,
+            messages: context.get_messages(),
             tools: if self.config.enable_tools {
                 Some(self.tool_registry.get_tool_definitions().await)
             } else {
                 None
             },
             tool_choice: None,
-            temperature: None,
-            top_p: None,
-            max_tokens: None,
-            stream: Some(true),
+            temperature: settings.temperature,
+            top_p: settings.top_p,
+            max_tokens: settings.max_tokens,
+            stream: Some(stream),
             stop: None,
             presence_penalty: None,
             frequency_penalty: None,
             user: None,
+            previous_response_id: None,
+            n: None,
+            logprobs: None,
+            top_logprobs: None,
+            seed: None,
+            hosted_tools: Vec::new(),
         };
 
-        let provider_name = "openai";
-        let stream = self.llm_client.chat_stream(provider_name, request).await?;
-
-        let mapped_stream = stream.map(|result| {
-            result.map_err(|e| AgentError::Llm(e)).and_then(|event| match event {
-                pi_ai::stream::StreamEvent::Token(token) => Ok(StreamEvent::Token(token)),
-                pi_ai::stream::StreamEvent::ToolCall { id, name, arguments } => {
-                    Ok(StreamEvent::ToolCall { id, name, arguments })
-                }
-                pi_ai::stream::StreamEvent::Done => Ok(StreamEvent::Done),
-                pi_ai::stream::StreamEvent::Error(err) => Ok(StreamEvent::Error(err)),
-            })
-        });
-
-        Ok(Box::pin(mapped_stream))
+        Ok((provider, request))
     }
 
-    async fn execute_tool(&self, tool_call: &pi_ai::message::ToolCall) -> AgentResult<String> {
+    async fn execute_tool(&self, tool_call: &ToolCall) -> AgentResult<String> {
         let arguments: serde_json::Value = serde_json::from_str(&tool_call.function.arguments)
             .map_err(|e| AgentError::InvalidToolArguments(e.to_string()))?;
 
-        let result = self.tool_registry.execute(&tool_call.function.name, arguments).await?;
+        let name = &tool_call.function.name;
+        let timeout = match self.tool_registry.get(name).await {
+            Some(tool) => tool.timeout(),
+            None => return Err(AgentError::ToolNotFound(name.clone())),
+        }
+        .or(self.config.tool_timeout_secs.map(Duration::from_secs));
+
+        let execution = self.tool_registry.execute(name, arguments);
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, execution).await.map_err(|_| {
+                AgentError::ToolExecution(format!("Tool {} timed out after {:?}", name, timeout))
+            })??,
+            None => execution.await?,
+        };
 
         if result.success {
             Ok(result.output)
@@ -235,4 +1032,61 @@ impl Executor {
     pub fn config(&self) -> &ExecutorConfig {
         &self.config
     }
+
+    pub fn model_settings(&self) -> &ModelSettings {
+        &self.model_settings
+    }
+
+    pub fn llm_client(&self) -> &Arc<B> {
+        &self.llm_client
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+}
+
+fn compacted_event(record: &CompactionRecord) -> AgentEvent {
+    AgentEvent::ContextCompacted {
+        summarized: record.replaced_messages.len(),
+        tokens_before: record.tokens_before,
+        tokens_after: record.tokens_after,
+    }
+}
+
+// 执行过程中的事件都发往 EventBus，流式调用时同时写入事件通道
+struct EventSink<'a> {
+    bus: &'a EventBus,
+    context_id: String,
+    stream: Option<&'a mpsc::Sender<AgentResult<AgentEvent>>>,
+}
+
+impl EventSink<'_> {
+    async fn emit(&self, event: AgentEvent) -> AgentResult<()> {
+        self.bus.publish(&self.context_id, &event);
+        match self.stream {
+            Some(tx) => tx
+                .send(Ok(event))
+                .await
+                .map_err(|_| AgentError::Other("Event stream was dropped".to_string())),
+            None => Ok(()),
+        }
+    }
+
+    // 未执行（等待人工处理）的调用没有结果，不产生事件
+    async fn emit_tool_results(&self, tool_calls: &[ToolCallInfo]) -> AgentResult<()> {
+        for info in tool_calls.iter().filter(|info| info.result.is_some()) {
+            self.emit(AgentEvent::ToolResult(info.clone())).await?;
+        }
+        Ok(())
+    }
+
+    // trimmed_before 是本轮开始时的 Context::trimmed_count
+    async fn emit_trimmed(&self, context: &Context, trimmed_before: usize) -> AgentResult<()> {
+        let removed = context.trimmed_count() - trimmed_before;
+        if removed > 0 {
+            self.emit(AgentEvent::ContextTrimmed { removed }).await?;
+        }
+        Ok(())
+    }
 }
@@ -1,15 +1,34 @@
 pub mod agent;
+pub mod approval;
+pub mod branch;
+pub mod compaction;
 pub mod context;
 pub mod error;
 pub mod executor;
+pub mod observer;
+pub mod rewind;
+#[cfg(feature = "sqlite")]
+pub mod sqlite_store;
 pub mod state;
+pub mod store;
 pub mod tool;
 pub mod tool_registry;
 
+#[cfg(test)]
+mod tests;
+
 pub use agent::{Agent, AgentConfig};
-pub use context::{Context, ContextManager};
+pub use approval::{ApprovalDecision, ApprovalHandler, ApprovalPolicy, ApprovalRequest, ApprovalRule, PolicyAction};
+pub use branch::{Branch, BranchDiff, BranchInfo, Branches};
+pub use compaction::{CompactionConfig, CompactionRecord, Compactor};
+pub use context::{Context, ContextManager, WindowPolicy};
 pub use error::{AgentError, AgentResult};
-pub use executor::{Executor, ExecutorConfig};
+pub use executor::{AgentEvent, AgentEventStream, Executor, ExecutorConfig, ModelSettings};
+pub use observer::{AgentObserver, EventBus, MetricsObserver, MetricsSnapshot, ObservedEvent, TracingObserver};
+pub use rewind::{Rewind, RewoundToolCall};
 pub use state::{AgentState, StateStore};
+pub use store::{ContextStore, JsonlContextStore, SessionInfo};
+#[cfg(feature = "sqlite")]
+pub use sqlite_store::SqliteContextStore;
 pub use tool::{Tool, ToolExecutionResult, ToolHandler};
 pub use tool_registry::ToolRegistry;
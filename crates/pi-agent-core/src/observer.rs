@@ -0,0 +1,208 @@
+use crate::executor::AgentEvent;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+
+// 订阅者跟不上时最旧的事件会被丢弃，接收端会收到 Lagged
+const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone)]
+pub struct ObservedEvent {
+    pub context_id: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub event: AgentEvent,
+}
+
+// 在执行循环内同步回调，实现里不要做阻塞操作；耗时的处理请用 EventBus::subscribe
+pub trait AgentObserver: Send + Sync {
+    fn on_event(&self, event: &ObservedEvent);
+}
+
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ObservedEvent>,
+    observers: Arc<RwLock<Vec<Arc<dyn AgentObserver>>>>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self {
+            sender,
+            observers: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ObservedEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn add_observer(&self, observer: Arc<dyn AgentObserver>) {
+        self.observers.write().unwrap().push(observer);
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count() + self.observers.read().unwrap().len()
+    }
+
+    pub fn publish(&self, context_id: &str, event: &AgentEvent) {
+        let observers = self.observers.read().unwrap().clone();
+        if observers.is_empty() && self.sender.receiver_count() == 0 {
+            return;
+        }
+
+        let observed = ObservedEvent {
+            context_id: context_id.to_string(),
+            timestamp: chrono::Utc::now(),
+            event: event.clone(),
+        };
+        for observer in &observers {
+            observer.on_event(&observed);
+        }
+        // 没有订阅者时 send 会返回错误，可以忽略
+        let _ = self.sender.send(observed);
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+// 把事件写入 tracing 日志；文本和参数分片只在 trace 级别输出
+#[derive(Debug, Default, Clone)]
+pub struct TracingObserver;
+
+impl AgentObserver for TracingObserver {
+    fn on_event(&self, observed: &ObservedEvent) {
+        let context_id = observed.context_id.as_str();
+        match &observed.event {
+            AgentEvent::TurnStarted { iteration } => {
+                tracing::debug!(context_id, iteration, "turn started")
+            }
+            AgentEvent::TurnFinished { iteration, .. } => {
+                tracing::debug!(context_id, iteration, "turn finished")
+            }
+            AgentEvent::LlmRequest { provider, model, messages } => {
+                tracing::info!(context_id, provider, model, messages, "llm request")
+            }
+            AgentEvent::LlmResponse { provider, model, duration } => {
+                tracing::info!(context_id, provider, model, duration_ms = duration.as_millis() as u64, "llm response")
+            }
+            AgentEvent::ToolCallFinished { id, name, .. } => {
+                tracing::info!(context_id, id, name, "tool call")
+            }
+            AgentEvent::ToolResult(info) => {
+                tracing::info!(context_id, id = info.id, name = info.name, success = info.success, denied = info.denied, "tool result")
+            }
+            AgentEvent::ContextTrimmed { removed } => {
+                tracing::debug!(context_id, removed, "context trimmed")
+            }
+            AgentEvent::ContextCompacted { summarized, tokens_before, tokens_after } => {
+                tracing::info!(context_id, summarized, tokens_before, tokens_after, "context compacted")
+            }
+            AgentEvent::Rewound { removed, rolled_back, failures } => tracing::info!(
+                context_id,
+                removed,
+                rolled_back,
+                failures = failures.len(),
+                "context rewound"
+            ),
+            AgentEvent::Usage(usage) => tracing::debug!(
+                context_id,
+                prompt_tokens = usage.prompt_tokens,
+                completion_tokens = usage.completion_tokens,
+                "usage"
+            ),
+            AgentEvent::Error(err) => tracing::warn!(context_id, error = err, "agent error"),
+            AgentEvent::Finished(result) => tracing::info!(
+                context_id,
+                iterations = result.iterations,
+                success = result.success,
+                "agent finished"
+            ),
+            event => tracing::trace!(context_id, ?event),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub turns: u64,
+    pub llm_requests: u64,
+    pub llm_latency_ms: u64,
+    pub tool_calls: u64,
+    pub tool_failures: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub trimmed_messages: u64,
+    pub errors: u64,
+}
+
+// 累计计数，可以挂到多个 agent 上
+#[derive(Debug, Default)]
+pub struct MetricsObserver {
+    turns: AtomicU64,
+    llm_requests: AtomicU64,
+    llm_latency_ms: AtomicU64,
+    tool_calls: AtomicU64,
+    tool_failures: AtomicU64,
+    prompt_tokens: AtomicU64,
+    completion_tokens: AtomicU64,
+    trimmed_messages: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl MetricsObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            turns: self.turns.load(Ordering::Relaxed),
+            llm_requests: self.llm_requests.load(Ordering::Relaxed),
+            llm_latency_ms: self.llm_latency_ms.load(Ordering::Relaxed),
+            tool_calls: self.tool_calls.load(Ordering::Relaxed),
+            tool_failures: self.tool_failures.load(Ordering::Relaxed),
+            prompt_tokens: self.prompt_tokens.load(Ordering::Relaxed),
+            completion_tokens: self.completion_tokens.load(Ordering::Relaxed),
+            trimmed_messages: self.trimmed_messages.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl AgentObserver for MetricsObserver {
+    fn on_event(&self, observed: &ObservedEvent) {
+        match &observed.event {
+            AgentEvent::TurnStarted { .. } => {
+                self.turns.fetch_add(1, Ordering::Relaxed);
+            }
+            AgentEvent::LlmRequest { .. } => {
+                self.llm_requests.fetch_add(1, Ordering::Relaxed);
+            }
+            AgentEvent::LlmResponse { duration, .. } => {
+                self.llm_latency_ms.fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+            }
+            AgentEvent::ToolResult(info) => {
+                self.tool_calls.fetch_add(1, Ordering::Relaxed);
+                if !info.success {
+                    self.tool_failures.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            AgentEvent::Usage(usage) => {
+                self.prompt_tokens.fetch_add(usage.prompt_tokens as u64, Ordering::Relaxed);
+                self.completion_tokens.fetch_add(usage.completion_tokens as u64, Ordering::Relaxed);
+            }
+            AgentEvent::ContextTrimmed { removed } => {
+                self.trimmed_messages.fetch_add(*removed as u64, Ordering::Relaxed);
+            }
+            AgentEvent::Error(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use crate::context::Context;
+use crate::error::{AgentError, AgentResult};
+use pi_ai::{Message, MessageRole, ToolCall};
+
+// 被撤销的工具调用和它写入 context 的结果；调用后没有结果（被裁掉或等待人工处理）时 output 为空
+#[derive(Debug, Clone)]
+pub struct RewoundToolCall {
+    pub call: ToolCall,
+    pub output: Option<String>,
+}
+
+// 一次回退从活动分支末尾移除的消息
+#[derive(Debug, Clone, Default)]
+pub struct Rewind {
+    pub removed: Vec<Message>,
+}
+
+impl Rewind {
+    // 按调用顺序；回滚时应逆序处理
+    pub fn tool_calls(&self) -> Vec<RewoundToolCall> {
+        let mut calls = Vec::new();
+        for message in &self.removed {
+            for call in message.tool_calls.iter().flatten() {
+                let output = self
+                    .removed
+                    .iter()
+                    .find(|m| m.role == MessageRole::Tool && m.tool_call_id.as_deref() == Some(call.id.as_str()))
+                    .map(|m| m.content.clone());
+                calls.push(RewoundToolCall {
+                    call: call.clone(),
+                    output,
+                });
+            }
+        }
+        calls
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+    }
+}
+
+impl Context {
+    // 移除第 index 条及之后的所有消息
+    pub fn rewind_to(&mut self, index: usize) -> Rewind {
+        if index >= self.messages.len() {
+            return Rewind::default();
+        }
+        let removed = self.messages.split_off(index).into();
+        self.clamp_fork_points();
+        self.metadata.updated_at = chrono::Utc::now();
+        Rewind { removed }
+    }
+
+    // 撤销最近一轮：最后一条 user 消息以及之后的回复、工具调用和结果。没有 user 消息时返回 None
+    pub fn undo_turn(&mut self) -> Option<Rewind> {
+        let start = self.last_user_index()?;
+        Some(self.rewind_to(start))
+    }
+
+    // 移除最后一条 user 消息之后的回复，用来重新生成；user 消息本身保留
+    pub fn pop_response(&mut self) -> AgentResult<Rewind> {
+        let start = self
+            .last_user_index()
+            .ok_or_else(|| AgentError::Context("No user message to regenerate a response for".to_string()))?;
+        Ok(self.rewind_to(start + 1))
+    }
+
+    // 修改第 index 条 user 消息，并移除它之后的所有消息
+    pub fn edit_message(&mut self, index: usize, content: String) -> AgentResult<Rewind> {
+        match self.messages.get(index) {
+            Some(message) if message.role == MessageRole::User => {}
+            Some(message) => {
+                return Err(AgentError::Context(format!(
+                    "Message {} is a {:?} message, only user messages can be edited",
+                    index, message.role
+                )))
+            }
+            None => {
+                return Err(AgentError::Context(format!(
+                    "Message {} not found, context has {} messages",
+                    index,
+                    self.messages.len()
+                )))
+            }
+        }
+
+        let rewind = self.rewind_to(index + 1);
+        self.messages[index].content = content;
+        self.clamp_fork_points();
+        self.metadata.updated_at = chrono::Utc::now();
+        Ok(rewind)
+    }
+
+    fn last_user_index(&self) -> Option<usize> {
+        self.messages.iter().rposition(|m| m.role == MessageRole::User)
+    }
+}
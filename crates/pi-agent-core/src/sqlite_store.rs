@@ -0,0 +1,285 @@
+use crate::branch::Branches;
+use crate::context::{Context, ContextMetadata, WindowPolicy};
+use crate::error::{AgentError, AgentResult};
+use crate::store::{ContextStore, SessionInfo};
+use async_trait::async_trait;
+use pi_ai::Message;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// 第 1 版的表结构。之后新增的列放在 MIGRATIONS 里，PRAGMA user_version 记录当前版本
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id TEXT PRIMARY KEY,
+    title TEXT,
+    model TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    message_count INTEGER NOT NULL,
+    max_messages INTEGER NOT NULL,
+    metadata TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS messages (
+    session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    seq INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    PRIMARY KEY (session_id, seq)
+);
+";
+
+// sessions 表新增的 (列, 定义)，第 i 项把版本升到 i + 2
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("window", "TEXT NOT NULL DEFAULT '{}'"),
+    ("branches", "TEXT NOT NULL DEFAULT '{}'"),
+];
+
+// 会话列表只查 sessions 表，不读取消息
+#[derive(Clone)]
+pub struct SqliteContextStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteContextStore {
+    pub fn open(path: impl AsRef<Path>) -> AgentResult<Self> {
+        if let Some(parent) = path.as_ref().parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::from_connection(Connection::open(path).map_err(sqlite)?)
+    }
+
+    pub fn in_memory() -> AgentResult<Self> {
+        Self::from_connection(Connection::open_in_memory().map_err(sqlite)?)
+    }
+
+    fn from_connection(mut conn: Connection) -> AgentResult<Self> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;").map_err(sqlite)?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    // rusqlite 是同步接口，放到阻塞线程池里执行
+    async fn with_conn<T, F>(&self, f: F) -> AgentResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> AgentResult<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|_| AgentError::Context("SQLite connection lock poisoned".to_string()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| AgentError::Other(e.to_string()))?
+    }
+}
+
+#[async_trait]
+impl ContextStore for SqliteContextStore {
+    async fn load(&self, id: &str) -> AgentResult<Option<Context>> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            let session: Option<(usize, String, String, String)> = conn
+                .query_row(
+                    "SELECT max_messages, metadata, window, branches FROM sessions WHERE id = ?1",
+                    params![id],
+                    |row| Ok((row.get::<_, i64>(0)? as usize, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )
+                .optional()
+                .map_err(sqlite)?;
+            let Some((max_messages, metadata, window, branches)) = session else {
+                return Ok(None);
+            };
+
+            let mut context = Context::new(id.clone()).with_max_messages(max_messages);
+            context.metadata = serde_json::from_str::<ContextMetadata>(&metadata).map_err(serialization)?;
+            context.window = serde_json::from_str::<WindowPolicy>(&window).map_err(serialization)?;
+            context.branches = serde_json::from_str::<Branches>(&branches).map_err(serialization)?;
+
+            let mut stmt = conn
+                .prepare("SELECT message FROM messages WHERE session_id = ?1 ORDER BY seq")
+                .map_err(sqlite)?;
+            let rows = stmt.query_map(params![id], |row| row.get::<_, String>(0)).map_err(sqlite)?;
+            for row in rows {
+                let message: Message = serde_json::from_str(&row.map_err(sqlite)?).map_err(serialization)?;
+                context.messages.push_back(message);
+            }
+            Ok(Some(context))
+        })
+        .await
+    }
+
+    async fn save(&self, context: &Context) -> AgentResult<()> {
+        let info = SessionInfo::from_context(context);
+        let metadata = serde_json::to_string(&context.metadata).map_err(serialization)?;
+        let window = serde_json::to_string(&context.window).map_err(serialization)?;
+        let branches = serde_json::to_string(&context.branches).map_err(serialization)?;
+        let messages = context
+            .messages
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(serialization)?;
+        let max_messages = context.max_messages as i64;
+
+        self.with_conn(move |conn| {
+            let tx = conn.transaction().map_err(sqlite)?;
+            tx.execute(
+                "INSERT INTO sessions (id, title, model, created_at, updated_at, message_count, max_messages, metadata, window, branches)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                 ON CONFLICT(id) DO UPDATE SET
+                    title = excluded.title,
+                    model = excluded.model,
+                    updated_at = excluded.updated_at,
+                    message_count = excluded.message_count,
+                    max_messages = excluded.max_messages,
+                    metadata = excluded.metadata,
+                    window = excluded.window,
+                    branches = excluded.branches",
+                params![
+                    info.id,
+                    info.title,
+                    info.model,
+                    format_time(&info.created_at),
+                    format_time(&info.updated_at),
+                    info.message_count as i64,
+                    max_messages,
+                    metadata,
+                    window,
+                    branches,
+                ],
+            )
+            .map_err(sqlite)?;
+
+            // 和 JSONL 一样只追加新消息；前面的内容变了（裁剪、压缩、回退）时从第一处不同开始重写
+            let written = {
+                let mut stmt = tx
+                    .prepare("SELECT message FROM messages WHERE session_id = ?1 ORDER BY seq")
+                    .map_err(sqlite)?;
+                let rows = stmt.query_map(params![info.id], |row| row.get::<_, String>(0)).map_err(sqlite)?;
+                rows.collect::<Result<Vec<_>, _>>().map_err(sqlite)?
+            };
+            let start = written.iter().zip(&messages).take_while(|(written, now)| written == now).count();
+            if start < written.len() {
+                tx.execute(
+                    "DELETE FROM messages WHERE session_id = ?1 AND seq >= ?2",
+                    params![info.id, start as i64],
+                )
+                .map_err(sqlite)?;
+            }
+            {
+                let mut stmt = tx
+                    .prepare("INSERT INTO messages (session_id, seq, message) VALUES (?1, ?2, ?3)")
+                    .map_err(sqlite)?;
+                for (seq, message) in messages.iter().enumerate().skip(start) {
+                    stmt.execute(params![info.id, seq as i64, message]).map_err(sqlite)?;
+                }
+            }
+            tx.commit().map_err(sqlite)
+        })
+        .await
+    }
+
+    async fn delete(&self, id: &str) -> AgentResult<()> {
+        let id = id.to_string();
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM sessions WHERE id = ?1", params![id])
+                .map_err(sqlite)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list(&self) -> AgentResult<Vec<SessionInfo>> {
+        self.with_conn(|conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT id, title, model, created_at, updated_at, message_count
+                     FROM sessions ORDER BY updated_at DESC",
+                )
+                .map_err(sqlite)?;
+            let rows = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, String>(4)?,
+                        row.get::<_, i64>(5)?,
+                    ))
+                })
+                .map_err(sqlite)?;
+
+            let mut sessions = Vec::new();
+            for row in rows {
+                let (id, title, model, created_at, updated_at, message_count) = row.map_err(sqlite)?;
+                sessions.push(SessionInfo {
+                    id,
+                    title,
+                    model,
+                    created_at: parse_time(&created_at)?,
+                    updated_at: parse_time(&updated_at)?,
+                    message_count: message_count as usize,
+                });
+            }
+            Ok(sessions)
+        })
+        .await
+    }
+}
+
+fn migrate(conn: &mut Connection) -> AgentResult<()> {
+    let tx = conn.transaction().map_err(sqlite)?;
+    let version = tx
+        .query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))
+        .map_err(sqlite)? as usize;
+    let latest = MIGRATIONS.len() + 1;
+    if version > latest {
+        return Err(AgentError::Context(format!(
+            "SQLite session store has schema version {}, newer than the supported {}",
+            version, latest
+        )));
+    }
+
+    tx.execute_batch(SCHEMA).map_err(sqlite)?;
+    for (column, definition) in MIGRATIONS.iter().skip(version.saturating_sub(1)) {
+        // 有版本号之前建的库可能已经有这一列
+        let exists: bool = tx
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('sessions') WHERE name = ?1",
+                params![column],
+                |row| row.get(0),
+            )
+            .map_err(sqlite)?;
+        if !exists {
+            tx.execute_batch(&format!("ALTER TABLE sessions ADD COLUMN {} {}", column, definition))
+                .map_err(sqlite)?;
+        }
+    }
+    tx.pragma_update(None, "user_version", latest as i64).map_err(sqlite)?;
+    tx.commit().map_err(sqlite)
+}
+
+// 固定宽度，按字符串排序即按时间排序
+fn format_time(time: &chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+}
+
+fn parse_time(value: &str) -> AgentResult<chrono::DateTime<chrono::Utc>> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&chrono::Utc))
+        .map_err(|e| AgentError::Serialization(e.to_string()))
+}
+
+fn sqlite(e: rusqlite::Error) -> AgentError {
+    AgentError::Context(format!("SQLite error: {}", e))
+}
+
+fn serialization(e: serde_json::Error) -> AgentError {
+    AgentError::Serialization(e.to_string())
+}
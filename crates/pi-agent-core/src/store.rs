@@ -0,0 +1,388 @@
+use crate::branch::Branches;
+use crate::compaction::CompactionRecord;
+use crate::context::{Context, ContextMetadata, WindowPolicy};
+use crate::error::{AgentError, AgentResult};
+use async_trait::async_trait;
+use pi_ai::{Message, MessageRole};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+// 自动生成标题时最多保留的字符数
+const TITLE_MAX_CHARS: usize = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: String,
+    pub title: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    pub model: Option<String>,
+    pub message_count: usize,
+}
+
+impl SessionInfo {
+    pub fn from_context(context: &Context) -> Self {
+        let title = context.metadata.title.clone().or_else(|| {
+            context
+                .messages
+                .iter()
+                .find(|m| m.role == MessageRole::User)
+                .and_then(|m| m.content.lines().find(|line| !line.trim().is_empty()))
+                .map(|line| line.trim().chars().take(TITLE_MAX_CHARS).collect())
+        });
+
+        Self {
+            id: context.id.clone(),
+            title,
+            created_at: context.metadata.created_at,
+            updated_at: context.metadata.updated_at,
+            model: context.metadata.model.clone(),
+            message_count: context.message_count(),
+        }
+    }
+}
+
+// 会话的持久化后端。ContextManager 在第一次访问某个会话时才调用 load，
+// 之后每次写回都会调用 save
+#[async_trait]
+pub trait ContextStore: Send + Sync {
+    async fn load(&self, id: &str) -> AgentResult<Option<Context>>;
+
+    async fn save(&self, context: &Context) -> AgentResult<()>;
+
+    async fn delete(&self, id: &str) -> AgentResult<()>;
+
+    // 按 updated_at 从新到旧排列
+    async fn list(&self) -> AgentResult<Vec<SessionInfo>>;
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    // metadata 中不含 compactions，updated_at 等于 created_at，只在这些字段以外有变化时重写；
+    // 旧文件里的 Session 带完整的 metadata
+    Session {
+        id: String,
+        max_messages: usize,
+        metadata: ContextMetadata,
+        #[serde(default)]
+        window: WindowPolicy,
+    },
+    // 随其他记录一起追加的最近修改时间
+    Touched { updated_at: chrono::DateTime<chrono::Utc> },
+    // 每次压缩只写一次
+    Compaction { record: CompactionRecord },
+    // 撤销压缩后只保留前 keep 条
+    DropCompactions { keep: usize },
+    Message { message: Message },
+    // 从 start 开始丢弃 count 条消息（超出窗口）
+    Trim {
+        #[serde(default)]
+        start: usize,
+        count: usize,
+    },
+    // 历史被改写（压缩、回退、切换分支等）时整体替换
+    Snapshot { messages: Vec<Message> },
+    // 分支信息有变化时整体写入
+    Branches { branches: Branches },
+}
+
+// 已写入文件的内容，用来判断下次 save 只需追加哪些记录
+#[derive(Default)]
+struct Persisted {
+    messages: Vec<String>,
+    session: Option<String>,
+    branches: Option<String>,
+    updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    // 已写入的压缩记录的 created_at
+    compactions: Vec<chrono::DateTime<chrono::Utc>>,
+}
+
+// 每个会话一个只追加的 JSONL 文件，加载时按顺序重放记录。
+// 旁边的 .info 文件保存 SessionInfo，list 不需要重放整个会话
+pub struct JsonlContextStore {
+    dir: PathBuf,
+    persisted: Mutex<HashMap<String, Persisted>>,
+}
+
+impl JsonlContextStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            persisted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // id 里可能有 ':' 等不能出现在文件名中的字符
+    fn path(&self, id: &str) -> PathBuf {
+        let mut name = String::with_capacity(id.len());
+        for byte in id.bytes() {
+            match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => name.push(byte as char),
+                _ => name.push_str(&format!("%{:02X}", byte)),
+            }
+        }
+        self.dir.join(format!("{}.jsonl", name))
+    }
+
+    fn info_path(path: &Path) -> PathBuf {
+        path.with_extension("info")
+    }
+
+    // .info 比会话文件旧时（写完会话后进程退出）视为过期
+    async fn read_info(path: &Path) -> Option<SessionInfo> {
+        let info_path = Self::info_path(path);
+        let info_modified = tokio::fs::metadata(&info_path).await.ok()?.modified().ok()?;
+        let modified = tokio::fs::metadata(path).await.ok()?.modified().ok()?;
+        if info_modified < modified {
+            return None;
+        }
+        let content = tokio::fs::read_to_string(&info_path).await.ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn session_record(context: &Context) -> AgentResult<String> {
+        let mut metadata = context.metadata.clone();
+        metadata.updated_at = metadata.created_at;
+        metadata.compactions.clear();
+        serde_json::to_string(&Record::Session {
+            id: context.id.clone(),
+            max_messages: context.max_messages,
+            metadata,
+            window: context.window.clone(),
+        })
+        .map_err(serialization)
+    }
+
+    async fn read(path: &Path) -> AgentResult<Option<(Context, Persisted)>> {
+        let content = match tokio::fs::read_to_string(path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut context: Option<Context> = None;
+        let mut persisted = Persisted::default();
+        let mut messages = VecDeque::new();
+        let mut branches = None;
+        let mut compactions = Vec::new();
+        let mut updated_at = None;
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            // 进程中途退出可能留下写了一半的最后一行
+            let record: Record = match serde_json::from_str(line) {
+                Ok(record) => record,
+                Err(e) => {
+                    tracing::warn!("Skipping unreadable record in {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            match record {
+                Record::Session { id, max_messages, mut metadata, window } => {
+                    if !metadata.compactions.is_empty() {
+                        compactions = std::mem::take(&mut metadata.compactions);
+                    }
+                    updated_at = updated_at.max(Some(metadata.updated_at));
+                    let mut restored = Context::new(id).with_max_messages(max_messages);
+                    restored.metadata = metadata;
+                    restored.window = window;
+                    context = Some(restored);
+                    persisted.session = Some(line.to_string());
+                }
+                Record::Touched { updated_at: at } => updated_at = updated_at.max(Some(at)),
+                Record::Compaction { record } => compactions.push(record),
+                Record::DropCompactions { keep } => compactions.truncate(keep),
+                Record::Message { message } => {
+                    persisted.messages.push(serde_json::to_string(&message).map_err(serialization)?);
+                    messages.push_back(message);
+                }
+                Record::Trim { start, count } => {
+                    let start = start.min(messages.len());
+                    let end = (start + count).min(messages.len());
+                    messages.drain(start..end);
+                    persisted.messages.drain(start..end);
+                }
+                Record::Branches { branches: recorded } => {
+                    branches = Some(recorded);
+                    persisted.branches = Some(line.to_string());
+                }
+                Record::Snapshot { messages: snapshot } => {
+                    persisted.messages = snapshot
+                        .iter()
+                        .map(serde_json::to_string)
+                        .collect::<Result<_, _>>()
+                        .map_err(serialization)?;
+                    messages = snapshot.into();
+                }
+            }
+        }
+
+        persisted.updated_at = updated_at;
+        persisted.compactions = compactions.iter().map(|record| record.created_at).collect();
+        Ok(context.map(|mut context| {
+            context.messages = messages;
+            context.branches = branches.unwrap_or_default();
+            context.metadata.compactions = compactions;
+            if let Some(updated_at) = updated_at {
+                context.metadata.updated_at = updated_at;
+            }
+            (context, persisted)
+        }))
+    }
+}
+
+#[async_trait]
+impl ContextStore for JsonlContextStore {
+    async fn load(&self, id: &str) -> AgentResult<Option<Context>> {
+        let Some((context, persisted)) = Self::read(&self.path(id)).await? else {
+            return Ok(None);
+        };
+        self.persisted.lock().await.insert(id.to_string(), persisted);
+        Ok(Some(context))
+    }
+
+    async fn save(&self, context: &Context) -> AgentResult<()> {
+        let path = self.path(&context.id);
+        let mut all = self.persisted.lock().await;
+        if !all.contains_key(&context.id) {
+            let persisted = Self::read(&path).await?.map(|(_, persisted)| persisted).unwrap_or_default();
+            all.insert(context.id.clone(), persisted);
+        }
+        let persisted = all.get_mut(&context.id).expect("persisted state was just inserted");
+
+        let mut lines = Vec::new();
+        let session = Self::session_record(context)?;
+        if persisted.session.as_deref() != Some(session.as_str()) {
+            lines.push(session.clone());
+        }
+        let branches = serde_json::to_string(&Record::Branches {
+            branches: context.branches.clone(),
+        })
+        .map_err(serialization)?;
+        if persisted.branches.as_deref() != Some(branches.as_str()) {
+            lines.push(branches.clone());
+        }
+
+        let current: Vec<String> = context
+            .messages
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<_, _>>()
+            .map_err(serialization)?;
+        // 从两者第一处不同的位置开始，找到最少要丢弃多少条已写入的消息，
+        // 才能让剩下的内容成为当前消息的前缀；完全对不上时写入快照
+        let start = persisted
+            .messages
+            .iter()
+            .zip(&current)
+            .take_while(|(written, now)| written == now)
+            .count();
+        let written = &persisted.messages[start..];
+        let trimmed = (0..=written.len())
+            .find(|&k| current[start..].starts_with(&written[k..]))
+            .filter(|&count| start > 0 || count < written.len() || written.is_empty());
+        match trimmed {
+            Some(count) => {
+                if count > 0 {
+                    let record = Record::Trim { start, count };
+                    lines.push(serde_json::to_string(&record).map_err(serialization)?);
+                }
+                let kept = persisted.messages.len() - count;
+                for message in context.messages.iter().skip(kept) {
+                    let record = Record::Message { message: message.clone() };
+                    lines.push(serde_json::to_string(&record).map_err(serialization)?);
+                }
+            }
+            None => {
+                let snapshot = Record::Snapshot { messages: context.get_messages() };
+                lines.push(serde_json::to_string(&snapshot).map_err(serialization)?);
+            }
+        }
+
+        let compactions = &context.metadata.compactions;
+        let keep = persisted
+            .compactions
+            .iter()
+            .zip(compactions)
+            .take_while(|(written, record)| **written == record.created_at)
+            .count();
+        if keep < persisted.compactions.len() {
+            lines.push(serde_json::to_string(&Record::DropCompactions { keep }).map_err(serialization)?);
+        }
+        for record in &compactions[keep..] {
+            let record = Record::Compaction { record: record.clone() };
+            lines.push(serde_json::to_string(&record).map_err(serialization)?);
+        }
+
+        let updated_at = context.metadata.updated_at;
+        if persisted.updated_at != Some(updated_at) {
+            lines.push(serde_json::to_string(&Record::Touched { updated_at }).map_err(serialization)?);
+        }
+
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+        let mut buffer = lines.join("\n");
+        buffer.push('\n');
+        file.write_all(buffer.as_bytes()).await?;
+        file.flush().await?;
+
+        persisted.messages = current;
+        persisted.session = Some(session);
+        persisted.branches = Some(branches);
+        persisted.updated_at = Some(updated_at);
+        persisted.compactions = compactions.iter().map(|record| record.created_at).collect();
+
+        let info = serde_json::to_string(&SessionInfo::from_context(context)).map_err(serialization)?;
+        tokio::fs::write(Self::info_path(&path), info).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, id: &str) -> AgentResult<()> {
+        self.persisted.lock().await.remove(id);
+        let path = self.path(id);
+        for path in [Self::info_path(&path), path] {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn list(&self) -> AgentResult<Vec<SessionInfo>> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut sessions = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("jsonl") {
+                continue;
+            }
+            if let Some(info) = Self::read_info(&path).await {
+                sessions.push(info);
+            } else if let Some((context, _)) = Self::read(&path).await? {
+                sessions.push(SessionInfo::from_context(&context));
+            }
+        }
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.updated_at));
+        Ok(sessions)
+    }
+}
+
+fn serialization(e: serde_json::Error) -> AgentError {
+    AgentError::Serialization(e.to_string())
+}
@@ -0,0 +1,856 @@
+#[cfg(test)]
+#[allow(clippy::module_inception)]
+mod tests {
+    use crate::*;
+    use crate::tool::ToolParameters;
+    use async_trait::async_trait;
+    use futures::StreamExt;
+    use pi_ai::models::{ChatCompletionRequest, ChatCompletionResponse};
+    use pi_ai::{EventStream, LlmBackend, Message, MessageRole, StreamEvent};
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // 按顺序返回预先排好的回复；chat 把同样的事件拼成一条完整消息
+    struct MockBackend {
+        turns: Mutex<VecDeque<Vec<StreamEvent>>>,
+        requests: Mutex<Vec<(String, ChatCompletionRequest)>>,
+    }
+
+    impl MockBackend {
+        fn new(turns: Vec<Vec<StreamEvent>>) -> Arc<Self> {
+            Arc::new(Self {
+                turns: Mutex::new(turns.into()),
+                requests: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn next_turn(&self, provider: &str, request: ChatCompletionRequest) -> Vec<StreamEvent> {
+            self.requests.lock().unwrap().push((provider.to_string(), request));
+            self.turns.lock().unwrap().pop_front().expect("no more mock turns")
+        }
+
+        fn requests(&self) -> Vec<(String, ChatCompletionRequest)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl LlmBackend for MockBackend {
+        async fn chat(&self, provider: &str, request: ChatCompletionRequest) -> pi_ai::Result<ChatCompletionResponse> {
+            let mut content = String::new();
+            let mut tool_calls = Vec::new();
+            for event in self.next_turn(provider, request) {
+                match event {
+                    StreamEvent::Token { text, .. } => content.push_str(&text),
+                    StreamEvent::ToolCall { id, name, arguments } => tool_calls.push(serde_json::json!({
+                        "id": id,
+                        "type": "function",
+                        "function": { "name": name, "arguments": arguments },
+                    })),
+                    _ => {}
+                }
+            }
+            let mut message = serde_json::json!({ "role": "Assistant", "content": content });
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = serde_json::Value::Array(tool_calls);
+            }
+            Ok(serde_json::from_value(serde_json::json!({
+                "id": "mock",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "mock-model",
+                "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+                "usage": { "prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2 },
+                "system_fingerprint": null,
+            }))
+            .unwrap())
+        }
+
+        async fn chat_stream(&self, provider: &str, request: ChatCompletionRequest) -> pi_ai::Result<EventStream> {
+            let events = self.next_turn(provider, request);
+            Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+        }
+
+        fn default_provider(&self) -> Option<String> {
+            Some("openai".to_string())
+        }
+
+        fn default_model(&self, _provider: &str) -> Option<String> {
+            Some("mock-model".to_string())
+        }
+    }
+
+    // 参数 {"n": 编号, "ms": 耗时, "fail": 是否失败}；执行和回滚都记到 log 里
+    struct Probe {
+        name: &'static str,
+        parallel_safe: bool,
+        timeout: Option<Duration>,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Probe {
+        fn tool(name: &'static str, parallel_safe: bool, log: &Arc<Mutex<Vec<String>>>) -> Tool {
+            Tool::new(Arc::new(Probe {
+                name,
+                parallel_safe,
+                timeout: None,
+                log: log.clone(),
+            }))
+        }
+    }
+
+    #[async_trait]
+    impl ToolHandler for Probe {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn description(&self) -> &str {
+            "test tool"
+        }
+
+        fn parameters(&self) -> ToolParameters {
+            ToolParameters {
+                param_type: "object".to_string(),
+                properties: HashMap::new(),
+                required: None,
+            }
+        }
+
+        fn parallel_safe(&self) -> bool {
+            self.parallel_safe
+        }
+
+        fn timeout(&self) -> Option<Duration> {
+            self.timeout
+        }
+
+        async fn execute(&self, arguments: serde_json::Value) -> Result<ToolExecutionResult, AgentError> {
+            let n = arguments["n"].as_u64().unwrap_or_default();
+            self.log.lock().unwrap().push(format!("start {}", n));
+            tokio::time::sleep(Duration::from_millis(arguments["ms"].as_u64().unwrap_or_default())).await;
+            self.log.lock().unwrap().push(format!("end {}", n));
+            if arguments["fail"].as_bool().unwrap_or_default() {
+                return Ok(ToolExecutionResult::failure(format!("probe {} failed", n)));
+            }
+            Ok(ToolExecutionResult::success(format!("done {}", n)))
+        }
+
+        async fn rollback(&self, arguments: serde_json::Value, output: Option<&str>) -> Result<(), AgentError> {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("undo {} {}", arguments["n"], output.unwrap_or_default()));
+            Ok(())
+        }
+    }
+
+    fn call(id: &str, name: &str, arguments: serde_json::Value) -> StreamEvent {
+        StreamEvent::ToolCall {
+            id: id.to_string(),
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        }
+    }
+
+    fn text(content: &str) -> Vec<StreamEvent> {
+        vec![StreamEvent::Token { index: 0, text: content.to_string() }, StreamEvent::Done]
+    }
+
+    fn tool_call_message(id: &str) -> Message {
+        Message::assistant("").with_tool_calls(vec![pi_ai::ToolCall {
+            id: id.to_string(),
+            tool_type: "function".to_string(),
+            function: pi_ai::message::FunctionCall {
+                name: "probe".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }])
+    }
+
+    fn contents(messages: &[Message]) -> Vec<String> {
+        messages.iter().map(|m| m.content.clone()).collect()
+    }
+
+    fn executor(backend: Arc<MockBackend>, registry: Arc<ToolRegistry>, config: ExecutorConfig) -> Executor<MockBackend> {
+        Executor::new(config, backend, registry, Arc::new(StateStore::new()))
+    }
+
+    async fn registry(tools: Vec<Tool>) -> Arc<ToolRegistry> {
+        let registry = Arc::new(ToolRegistry::new());
+        for tool in tools {
+            registry.register(tool).await.unwrap();
+        }
+        registry
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pi-agent-core-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_stream_loop_runs_tools_and_publishes_events() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let backend = MockBackend::new(vec![
+            vec![
+                StreamEvent::Token { index: 0, text: "checking ".to_string() },
+                StreamEvent::ToolCallDelta {
+                    choice: 0,
+                    index: 0,
+                    id: Some("c1".to_string()),
+                    name: Some("probe".to_string()),
+                    arguments: String::new(),
+                },
+                StreamEvent::ToolCallDelta {
+                    choice: 0,
+                    index: 0,
+                    id: None,
+                    name: None,
+                    arguments: r#"{"n":1}"#.to_string(),
+                },
+                call("c1", "probe", serde_json::json!({ "n": 1 })),
+                StreamEvent::FinishReason("tool_calls".to_string()),
+                StreamEvent::Done,
+            ],
+            text("all done"),
+        ]);
+        let config = AgentConfig::new("a".to_string(), "A".to_string(), "sys".to_string())
+            .with_model("anthropic/claude-x".to_string())
+            .with_temperature(0.3);
+        let agent = Agent::new(config, backend.clone(), registry(vec![Probe::tool("probe", true, &log)]).await);
+        agent.initialize().await.unwrap();
+        let metrics = Arc::new(MetricsObserver::new());
+        agent.add_observer(metrics.clone());
+        let mut bus = agent.subscribe();
+
+        let events: Vec<_> = agent
+            .chat_events("go".to_string(), ModelSettings::default())
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        let Some(Ok(AgentEvent::Finished(result))) = events.last() else {
+            panic!("stream did not finish: {:?}", events.last());
+        };
+        assert!(result.success);
+        assert_eq!(result.iterations, 2);
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, Ok(AgentEvent::ToolCallDelta { id, arguments }) if id == "c1" && arguments == r#"{"n":1}"#)));
+
+        let requests = backend.requests();
+        assert_eq!(requests[0].0, "anthropic");
+        assert_eq!(requests[0].1.model, "claude-x");
+        assert_eq!(requests[0].1.temperature, Some(0.3));
+        // system、user、带调用的 assistant、tool 结果
+        assert_eq!(requests[1].1.messages.len(), 4);
+
+        // context 在后台任务里写回
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let context = agent.get_context("a:default").await.unwrap().unwrap();
+        assert_eq!(contents(&context.get_messages()), vec!["sys", "go", "checking ", "done 1", "all done"]);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.turns, 2);
+        assert_eq!(snapshot.llm_requests, 2);
+        assert_eq!(snapshot.tool_calls, 1);
+        let mut published = 0;
+        while let Ok(event) = bus.try_recv() {
+            assert_eq!(event.context_id, "a:default");
+            published += 1;
+        }
+        assert!(published >= events.len());
+    }
+
+    #[tokio::test]
+    async fn test_parallel_tools_keep_call_order_and_time_out() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let args = |n: u32, ms: u32| serde_json::json!({ "n": n, "ms": ms });
+        let backend = MockBackend::new(vec![
+            vec![
+                call("a", "read", args(1, 80)),
+                call("b", "read", args(2, 10)),
+                call("c", "write", args(3, 10)),
+                call("d", "read", args(4, 10)),
+                call("e", "hang", args(5, 1000)),
+                StreamEvent::Done,
+            ],
+            text("ok"),
+        ]);
+        let hang = Tool::new(Arc::new(Probe {
+            name: "hang",
+            parallel_safe: true,
+            timeout: Some(Duration::from_millis(50)),
+            log: log.clone(),
+        }));
+        let registry = registry(vec![
+            Probe::tool("read", true, &log),
+            Probe::tool("write", false, &log),
+            hang,
+        ])
+        .await;
+        let executor = executor(backend, registry, ExecutorConfig {
+            max_concurrent_tools: 2,
+            ..ExecutorConfig::default()
+        });
+        let mut context = Context::new("x".to_string());
+        context.add_user_message("go".to_string());
+
+        let events: Vec<_> = executor.execute_stream(&mut context).collect().await;
+
+        let Some(Ok(AgentEvent::Finished(result))) = events.last() else {
+            panic!("stream did not finish");
+        };
+        let answered: Vec<_> = context.get_messages().iter().filter_map(|m| m.tool_call_id.clone()).collect();
+        assert_eq!(answered, vec!["a", "b", "c", "d", "e"]);
+        let log = log.lock().unwrap().clone();
+        // a 和 b 并发执行，b 先结束；write 不和其他调用同时执行
+        assert_eq!(log[..4], ["start 1", "start 2", "end 2", "end 1"]);
+        assert_eq!(log[4..6], ["start 3", "end 3"]);
+        let timed_out = &result.tool_calls[4];
+        assert!(!timed_out.success);
+        assert!(timed_out.result.as_deref().unwrap().contains("timed out"));
+    }
+
+    struct ScriptedApproval(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl ApprovalHandler for ScriptedApproval {
+        async fn review(&self, request: &ApprovalRequest) -> AgentResult<ApprovalDecision> {
+            self.0.lock().unwrap().push(request.tool_call_id.clone());
+            Ok(match request.tool_name.as_str() {
+                "write" => ApprovalDecision::Deny {
+                    reason: "read only".to_string(),
+                },
+                "edit" => ApprovalDecision::Edit {
+                    arguments: r#"{"n":9}"#.to_string(),
+                },
+                _ => ApprovalDecision::ApproveAlways,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_policy_and_approval_decisions() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let n = |n: u32| serde_json::json!({ "n": n });
+        let backend = MockBackend::new(vec![
+            vec![
+                call("a", "read", n(1)),
+                call("b", "write", n(2)),
+                call("c", "edit", n(3)),
+                call("d", "read", n(4)),
+                call("e", "delete", n(5)),
+                StreamEvent::Done,
+            ],
+            vec![call("f", "read", n(6)), StreamEvent::Done],
+            text("finished"),
+        ]);
+        let registry = registry(vec![
+            Probe::tool("read", true, &log),
+            Probe::tool("write", false, &log),
+            Probe::tool("edit", false, &log),
+            Probe::tool("delete", false, &log),
+        ])
+        .await;
+        let handler = Arc::new(ScriptedApproval(Mutex::new(Vec::new())));
+        let policy = ApprovalPolicy::new()
+            .with_rule(ApprovalRule::new("del*", PolicyAction::Deny).with_arguments(r#"*"n":5*"#));
+        let executor = executor(backend, registry, ExecutorConfig::default())
+            .with_approval_handler(handler.clone())
+            .with_approval_policy(policy);
+        let mut context = Context::new("x".to_string());
+        context.add_user_message("go".to_string());
+
+        let result = executor.execute(&mut context).await.unwrap();
+
+        // ApproveAlways 之后同一个工具不再询问，策略拒绝的调用也不会到 handler
+        assert_eq!(*handler.0.lock().unwrap(), vec!["a", "b", "c"]);
+        assert_eq!(result.iterations, 3);
+        assert!(result.tool_calls[1].denied);
+        assert!(result.tool_calls[4].denied);
+        assert!(!result.tool_calls[3].denied);
+
+        // 编辑过的参数写回 assistant 消息，并在结果里告诉模型
+        let edited = &result.tool_calls[2];
+        assert_eq!(edited.arguments, r#"{"n":9}"#);
+        let messages = context.get_messages();
+        let stored = messages[1].tool_calls.as_ref().unwrap();
+        assert_eq!(stored[2].function.arguments, r#"{"n":9}"#);
+        let output = messages.iter().find(|m| m.tool_call_id.as_deref() == Some("c")).unwrap();
+        assert!(output.content.starts_with("done 9"));
+        assert!(output.content.contains(r#"Original arguments: {"n":3}"#));
+
+        // 回滚用实际执行的参数
+        let rewind = context.undo_turn().unwrap();
+        executor.rollback(&rewind).await.unwrap();
+        assert!(log.lock().unwrap().iter().any(|line| line.starts_with("undo 9")));
+        assert!(!log.lock().unwrap().iter().any(|line| line.starts_with("undo 2")));
+    }
+
+    #[tokio::test]
+    async fn test_pending_calls_stop_the_loop_until_resolved() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let backend = MockBackend::new(vec![
+            vec![
+                call("a", "probe", serde_json::json!({ "n": 1 })),
+                call("b", "probe", serde_json::json!({ "n": 2 })),
+                StreamEvent::Done,
+            ],
+            text("after approval"),
+        ]);
+        let registry = registry(vec![Probe::tool("probe", true, &log)]).await;
+        let executor = executor(backend.clone(), registry, ExecutorConfig {
+            auto_tool_execution: false,
+            ..ExecutorConfig::default()
+        });
+        let mut context = Context::new("x".to_string());
+        context.add_user_message("go".to_string());
+
+        let result = executor.execute(&mut context).await.unwrap();
+
+        assert_eq!(result.iterations, 1);
+        assert!(result.tool_calls.iter().all(|t| t.pending && t.result.is_none()));
+        assert!(log.lock().unwrap().is_empty());
+        let pending: Vec<_> = context.pending_tool_calls().into_iter().map(|c| c.id).collect();
+        assert_eq!(pending, vec!["a", "b"]);
+
+        let decisions = HashMap::from([("a".to_string(), ApprovalDecision::Approve)]);
+        let result = executor
+            .resolve_pending(&mut context, decisions, &ModelSettings::default())
+            .await
+            .unwrap();
+
+        assert!(result.tool_calls[0].success);
+        assert!(result.tool_calls[1].denied);
+        assert_eq!(result.messages.last().unwrap().content, "after approval");
+        assert!(context.pending_tool_calls().is_empty());
+        assert_eq!(*log.lock().unwrap(), vec!["start 1", "end 1"]);
+        assert!(executor
+            .resolve_pending(&mut context, HashMap::new(), &ModelSettings::default())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_approved_calls_continue_without_auto_execution() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let backend = MockBackend::new(vec![
+            vec![call("a", "probe", serde_json::json!({ "n": 1 })), StreamEvent::Done],
+            text("done"),
+        ]);
+        let registry = registry(vec![Probe::tool("probe", true, &log)]).await;
+        let executor = executor(backend.clone(), registry, ExecutorConfig {
+            auto_tool_execution: false,
+            ..ExecutorConfig::default()
+        })
+        .with_approval_handler(Arc::new(ScriptedApproval(Mutex::new(Vec::new()))));
+        let mut context = Context::new("x".to_string());
+        context.add_user_message("go".to_string());
+
+        let result = executor.execute(&mut context).await.unwrap();
+
+        assert_eq!(result.iterations, 2);
+        assert!(result.tool_calls[0].success);
+        assert_eq!(backend.requests().len(), 2);
+        assert_eq!(context.get_messages().last().unwrap().content, "done");
+    }
+
+    #[tokio::test]
+    async fn test_stop_on_tool_error_answers_every_call() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let backend = MockBackend::new(vec![
+            vec![
+                call("a", "read", serde_json::json!({ "n": 1, "ms": 20 })),
+                call("b", "read", serde_json::json!({ "n": 2, "fail": true })),
+                call("c", "write", serde_json::json!({ "n": 3 })),
+                StreamEvent::Done,
+            ],
+            text("unreachable"),
+        ]);
+        let registry = registry(vec![Probe::tool("read", true, &log), Probe::tool("write", false, &log)]).await;
+        let executor = executor(backend, registry, ExecutorConfig {
+            stop_on_tool_error: true,
+            ..ExecutorConfig::default()
+        });
+        let mut context = Context::new("x".to_string());
+        context.add_user_message("go".to_string());
+
+        let result = executor.execute(&mut context).await.unwrap();
+
+        assert!(!result.success);
+        assert_eq!(result.iterations, 1);
+        assert!(result.error.as_deref().unwrap().contains("probe 2 failed"));
+        let messages = context.get_messages();
+        let outputs: Vec<_> = messages.iter().filter(|m| m.role == MessageRole::Tool).collect();
+        assert_eq!(outputs.len(), 3);
+        assert_eq!(outputs[0].content, "done 1");
+        assert!(outputs[1].content.starts_with("Error:"));
+        assert_eq!(outputs[2].content, "Tool call was not executed.");
+        assert!(!log.lock().unwrap().contains(&"start 3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_loop_stops_when_only_earlier_rounds_succeeded() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let backend = MockBackend::new(vec![
+            vec![call("a", "probe", serde_json::json!({ "n": 1 })), StreamEvent::Done],
+            vec![call("b", "probe", serde_json::json!({ "n": 2, "fail": true })), StreamEvent::Done],
+            text("unreachable"),
+        ]);
+        let executor = executor(
+            backend,
+            registry(vec![Probe::tool("probe", true, &log)]).await,
+            ExecutorConfig::default(),
+        );
+        let mut context = Context::new("x".to_string());
+        context.add_user_message("go".to_string());
+
+        let events: Vec<_> = executor.execute_stream(&mut context).collect().await;
+
+        let Some(Ok(AgentEvent::Finished(result))) = events.last() else {
+            panic!("stream did not finish");
+        };
+        assert_eq!(result.iterations, 2);
+        assert_eq!(result.tool_calls.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_jsonl_store_replays_without_rewriting_headers() {
+        let dir = temp_dir("jsonl");
+        let store = Arc::new(JsonlContextStore::new(&dir));
+        let manager = ContextManager::with_store(store.clone());
+        let mut context = Context::new("a:default".to_string()).with_max_messages(4);
+        context.add_system_message("sys".to_string());
+        context.add_user_message("hello there\nsecond line".to_string());
+        context.metadata.compactions.push(CompactionRecord {
+            created_at: chrono::Utc::now(),
+            model: "openai/mock-model".to_string(),
+            summary: "summary".to_string(),
+            replaced_messages: vec![Message::user("old")],
+            tokens_before: 10,
+            tokens_after: 2,
+        });
+        manager.save(context.clone()).await.unwrap();
+        for i in 0..4 {
+            context.add_assistant_message(format!("reply {}", i));
+            manager.save(context.clone()).await.unwrap();
+        }
+        manager.save(context.clone()).await.unwrap();
+
+        let raw = std::fs::read_to_string(dir.join("a%3Adefault.jsonl")).unwrap();
+        assert_eq!(raw.matches(r#""type":"session""#).count(), 1);
+        assert_eq!(raw.matches(r#""type":"compaction""#).count(), 1);
+        assert!(raw.contains(r#""type":"trim","start":1"#));
+        assert!(!raw.contains("snapshot"));
+
+        let loaded = ContextManager::with_store(store.clone()).get("a:default").await.unwrap().unwrap();
+        assert_eq!(contents(&loaded.get_messages()), contents(&context.get_messages()));
+        assert_eq!(loaded.metadata.updated_at, context.metadata.updated_at);
+        assert_eq!(loaded.metadata.compactions.len(), 1);
+
+        context.metadata.compactions.clear();
+        context.messages.clear();
+        context.add_user_message("new start".to_string());
+        manager.save(context.clone()).await.unwrap();
+        let loaded = store.load("a:default").await.unwrap().unwrap();
+        assert!(loaded.metadata.compactions.is_empty());
+        assert_eq!(contents(&loaded.get_messages()), vec!["new start"]);
+
+        let sessions = store.list().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].title.as_deref(), Some("new start"));
+        assert_eq!(sessions[0].message_count, 1);
+
+        manager.delete("a:default").await.unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn long_conversation() -> Context {
+        let mut context = Context::new("c".to_string());
+        context.add_system_message("sys".to_string());
+        for i in 0..4 {
+            context.add_user_message(format!("q{} {}", i, "x".repeat(400)));
+            context.add_message(tool_call_message(&format!("t{}", i)));
+            context.add_tool_message("result".to_string(), format!("t{}", i));
+            context.add_assistant_message(format!("a{}", i));
+        }
+        context
+    }
+
+    #[tokio::test]
+    async fn test_compaction_summarizes_and_undo_restores() {
+        let backend = MockBackend::new(vec![text("## Goal\nstuff"), text("ok")]);
+        let config = ExecutorConfig {
+            compaction: Some(CompactionConfig::default().with_context_limit("mock-model", 500)),
+            ..ExecutorConfig::default()
+        };
+        let executor = executor(backend.clone(), registry(Vec::new()).await, config);
+        let mut context = long_conversation();
+        let before = context.get_messages();
+        let mut bus = executor.events().subscribe();
+
+        let events: Vec<_> = executor.execute_stream(&mut context).collect().await;
+
+        assert!(matches!(events.last(), Some(Ok(AgentEvent::Finished(_)))));
+        let mut summarized = None;
+        while let Ok(event) = bus.try_recv() {
+            if let AgentEvent::ContextCompacted { summarized: count, .. } = event.event {
+                summarized = Some(count);
+            }
+        }
+        assert_eq!(summarized, Some(8));
+        let messages = context.get_messages();
+        assert!(compaction::is_summary(&messages[1]));
+        assert!(messages[2].content.starts_with("q2"));
+        assert!(backend.requests()[0].1.messages[1].content.contains("q0"));
+
+        let record = context.undo_compaction().unwrap().unwrap();
+        assert_eq!(record.replaced_messages.len(), 8);
+        let restored = context.get_messages();
+        assert_eq!(contents(&restored[..before.len()]), contents(&before));
+        assert!(context.metadata.compactions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compaction_skipped_when_context_limit_unknown() {
+        let backend = MockBackend::new(vec![text("ok")]);
+        let config = ExecutorConfig {
+            compaction: Some(CompactionConfig::default()),
+            ..ExecutorConfig::default()
+        };
+        let executor = executor(backend.clone(), registry(Vec::new()).await, config);
+        let mut context = long_conversation();
+        let before = context.message_count();
+
+        executor.execute(&mut context).await.unwrap();
+
+        assert_eq!(backend.requests().len(), 1);
+        assert_eq!(context.message_count(), before + 1);
+        assert!(context.metadata.compactions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_window_keeps_tool_groups_together() {
+        let mut context = Context::new("w".to_string()).with_max_messages(5);
+        context.add_system_message("sys".to_string());
+        context.add_user_message("u1".to_string());
+        context.add_message(tool_call_message("a"));
+        context.add_tool_message("r1".to_string(), "a".to_string());
+        context.add_tool_message("r2".to_string(), "a".to_string());
+        context.add_assistant_message("x".to_string());
+        assert_eq!(contents(&context.get_messages()), vec!["sys", "", "r1", "r2", "x"]);
+        assert_eq!(context.trimmed_count(), 1);
+
+        // 调用和它的结果一起被丢弃
+        context.add_assistant_message("y".to_string());
+        assert_eq!(contents(&context.get_messages()), vec!["sys", "x", "y"]);
+        assert_eq!(context.trimmed_count(), 4);
+
+        let window = WindowPolicy::new().with_max_tokens(300).with_max_tool_output_chars(200);
+        let mut context = Context::new("t".to_string()).with_window(window);
+        context.add_system_message("sys".to_string());
+        context.add_user_message("u1".to_string());
+        context.add_message(tool_call_message("a"));
+        context.add_tool_message("y".repeat(2000), "a".to_string());
+        context.add_user_message("u2".to_string());
+        let messages = context.get_messages();
+        assert_eq!(messages.len(), 5);
+        assert!(messages[3].content.len() < 400);
+        assert_eq!(context.token_estimate(), compaction::estimate_tokens(&messages) as usize);
+        assert!(context.token_estimate() <= 300);
+
+        context.set_window(WindowPolicy::new().with_max_tokens(20));
+        context.add_user_message("u3".to_string());
+        assert_eq!(context.get_messages()[0].content, "sys");
+        assert_eq!(context.get_messages().last().unwrap().content, "u3");
+    }
+
+    #[tokio::test]
+    async fn test_branches_fork_switch_and_diff() {
+        let mut context = Context::new("s:1".to_string());
+        context.add_system_message("sys".to_string());
+        context.add_user_message("a".to_string());
+        context.add_assistant_message("b".to_string());
+        context.add_user_message("c".to_string());
+
+        context.fork("alt", 1).unwrap();
+        assert_eq!(context.active_branch(), "alt");
+        assert_eq!(context.message_count(), 2);
+        context.add_assistant_message("x".to_string());
+        let diff = context.diff_branches("main", "alt").unwrap();
+        assert_eq!(diff.common, 2);
+        assert_eq!(contents(&diff.left), vec!["b", "c"]);
+        assert_eq!(contents(&diff.right), vec!["x"]);
+        assert_eq!(context.list_branches().len(), 2);
+
+        let dir = temp_dir("branches");
+        let store = JsonlContextStore::new(&dir);
+        store.save(&context).await.unwrap();
+        context.switch_branch("main").unwrap();
+        assert_eq!(context.message_count(), 4);
+        store.save(&context).await.unwrap();
+
+        let loaded = JsonlContextStore::new(&dir).load("s:1").await.unwrap().unwrap();
+        assert_eq!(loaded.active_branch(), "main");
+        assert_eq!(loaded.message_count(), 4);
+        assert_eq!(contents(&loaded.branch_messages("alt").unwrap()), vec!["sys", "a", "x"]);
+
+        context.delete_branch("alt").unwrap();
+        assert!(context.switch_branch("alt").is_err());
+        assert!(context.delete_branch("main").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fork_point_follows_trimmed_parent() {
+        let mut context = Context::new("f".to_string()).with_max_messages(4);
+        context.add_system_message("sys".to_string());
+        for content in ["a", "b", "c"] {
+            context.add_user_message(content.to_string());
+        }
+        context.fork("alt", 2).unwrap();
+        context.switch_branch("main").unwrap();
+
+        // main 裁掉 "a" 之后，alt 和它只共享 system 消息
+        context.add_user_message("d".to_string());
+        assert_eq!(contents(&context.get_messages()), vec!["sys", "b", "c", "d"]);
+        let alt = context.list_branches().into_iter().find(|b| b.name == "alt").unwrap();
+        assert_eq!(alt.fork_point, 1);
+        assert_eq!(context.diff_branches("main", "alt").unwrap().common, alt.fork_point);
+        assert_eq!(contents(&context.branch_messages("alt").unwrap()), vec!["sys", "a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_rewind_rolls_back_tool_calls() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let backend = MockBackend::new(vec![
+            text("first"),
+            vec![
+                call("c0", "probe", serde_json::json!({ "n": 0 })),
+                call("c1", "probe", serde_json::json!({ "n": 1 })),
+                StreamEvent::Done,
+            ],
+            text("done"),
+            text("again"),
+            text("edited"),
+        ]);
+        let config = AgentConfig::new("a".to_string(), "A".to_string(), "sys".to_string()).with_executor_config(
+            ExecutorConfig {
+                rollback_tools: true,
+                ..ExecutorConfig::default()
+            },
+        );
+        let agent = Agent::new(config, backend, registry(vec![Probe::tool("probe", false, &log)]).await);
+        agent.initialize().await.unwrap();
+        let mut bus = agent.subscribe();
+        agent.chat("one".to_string()).await.unwrap();
+        agent.chat("two".to_string()).await.unwrap();
+
+        let rewind = agent.undo_last_turn().await.unwrap().unwrap();
+        assert_eq!(rewind.tool_calls().len(), 2);
+        // 逆序回滚
+        assert_eq!(log.lock().unwrap()[4..], ["undo 1 done 1", "undo 0 done 0"]);
+        let context = agent.get_context("a:default").await.unwrap().unwrap();
+        assert_eq!(contents(&context.get_messages()), vec!["sys", "one", "first"]);
+
+        let result = agent.regenerate().await.unwrap();
+        assert_eq!(result.messages.last().unwrap().content, "again");
+        assert!(agent.edit_message(2, "x".to_string()).await.is_err());
+        let result = agent.edit_message(1, "uno".to_string()).await.unwrap();
+        assert_eq!(contents(&result.messages), vec!["sys", "uno", "edited"]);
+
+        let mut rewinds = Vec::new();
+        while let Ok(event) = bus.try_recv() {
+            if let AgentEvent::Rewound { rolled_back, failures, .. } = event.event {
+                assert!(failures.is_empty());
+                rewinds.push(rolled_back);
+            }
+        }
+        assert_eq!(rewinds, vec![2, 0, 0]);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_store_migrates_old_schema() {
+        let dir = temp_dir("sqlite");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sessions.db");
+        // 加入 window 和 branches 列之前的表结构，没有 user_version
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE sessions (
+                    id TEXT PRIMARY KEY, title TEXT, model TEXT, created_at TEXT NOT NULL,
+                    updated_at TEXT NOT NULL, message_count INTEGER NOT NULL,
+                    max_messages INTEGER NOT NULL, metadata TEXT NOT NULL
+                );
+                CREATE TABLE messages (
+                    session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+                    seq INTEGER NOT NULL, message TEXT NOT NULL, PRIMARY KEY (session_id, seq)
+                );",
+            )
+            .unwrap();
+
+        let store = SqliteContextStore::open(&path).unwrap();
+        let mut context = Context::new("s".to_string()).with_window(WindowPolicy::new().with_max_tokens(99));
+        context.add_user_message("a".to_string());
+        context.fork("alt", 0).unwrap();
+        context.add_user_message("b".to_string());
+        store.save(&context).await.unwrap();
+        drop(store);
+
+        let store = SqliteContextStore::open(&path).unwrap();
+        let loaded = store.load("s").await.unwrap().unwrap();
+        assert_eq!(loaded.window.max_tokens, Some(99));
+        assert_eq!(loaded.active_branch(), "alt");
+        assert_eq!(contents(&loaded.branch_messages("main").unwrap()), vec!["a"]);
+        let version: i64 = rusqlite::Connection::open(&path)
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn test_sqlite_store_appends_only_new_messages() {
+        let dir = temp_dir("sqlite-append");
+        let path = dir.join("sessions.db");
+        let store = SqliteContextStore::open(&path).unwrap();
+        let rowids = || -> Vec<i64> {
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            let mut stmt = conn.prepare("SELECT rowid FROM messages ORDER BY seq").unwrap();
+            stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect()
+        };
+
+        let mut context = Context::new("s".to_string());
+        for content in ["a", "b", "c"] {
+            context.add_user_message(content.to_string());
+        }
+        store.save(&context).await.unwrap();
+        let before = rowids();
+
+        context.add_user_message("d".to_string());
+        store.save(&context).await.unwrap();
+        let after = rowids();
+        assert_eq!(after[..3], before[..]);
+        assert_eq!(after.len(), 4);
+
+        // 中间的消息被删掉后，只重写第一处不同之后的行
+        context.messages.remove(1);
+        store.save(&context).await.unwrap();
+        let rewritten = rowids();
+        assert_eq!(rewritten.len(), 3);
+        assert_eq!(rewritten[0], after[0]);
+        assert_eq!(contents(&store.load("s").await.unwrap().unwrap().get_messages()), vec!["a", "c", "d"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -3,6 +3,7 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolDefinition {
@@ -72,6 +73,22 @@ pub trait ToolHandler: Send + Sync {
     fn parameters(&self) -> ToolParameters;
 
     async fn execute(&self, arguments: Value) -> Result<ToolExecutionResult, crate::AgentError>;
+
+    // 有副作用、不能和同一轮其他调用并发的工具（写文件、执行命令等）返回 false
+    fn parallel_safe(&self) -> bool {
+        true
+    }
+
+    // 覆盖 ExecutorConfig.tool_timeout_secs
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    // 撤销对话轮次时调用，用来还原这次调用造成的修改；output 是当时写入 context 的结果。
+    // 同一轮的多个调用按逆序回滚，默认什么都不做
+    async fn rollback(&self, _arguments: Value, _output: Option<&str>) -> Result<(), crate::AgentError> {
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -96,10 +113,22 @@ impl Tool {
         self.handler.parameters()
     }
 
+    pub fn parallel_safe(&self) -> bool {
+        self.handler.parallel_safe()
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.handler.timeout()
+    }
+
     pub async fn execute(&self, arguments: Value) -> Result<ToolExecutionResult, crate::AgentError> {
         self.handler.execute(arguments).await
     }
 
+    pub async fn rollback(&self, arguments: Value, output: Option<&str>) -> Result<(), crate::AgentError> {
+        self.handler.rollback(arguments, output).await
+    }
+
     pub fn to_definition(&self) -> ToolDefinition {
         ToolDefinition {
             name: self.name().to_string(),
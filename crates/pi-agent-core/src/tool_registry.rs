@@ -1,4 +1,4 @@
-use crate::tool::{Tool, ToolHandler};
+use crate::tool::Tool;
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
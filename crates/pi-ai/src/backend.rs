@@ -0,0 +1,67 @@
+use std::pin::Pin;
+
+use async_trait::async_trait;
+use futures::stream::Stream;
+
+use crate::client::Client;
+use crate::embeddings::{EmbeddingRequest, EmbeddingResponse};
+use crate::error::{Error, Result};
+use crate::models::{ChatCompletionRequest, ChatCompletionResponse, ModelListing};
+use crate::stream::StreamEvent;
+
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>;
+
+// Client 之外的后端（进程内推理、测试替身、代理等）实现这个 trait 即可接入 agent
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    async fn chat(&self, provider: &str, request: ChatCompletionRequest) -> Result<ChatCompletionResponse>;
+
+    async fn chat_stream(&self, provider: &str, request: ChatCompletionRequest) -> Result<EventStream>;
+
+    async fn list_models(&self, provider: &str) -> Result<Vec<ModelListing>> {
+        Err(Error::UnsupportedProvider(format!("{}: listing models is not supported", provider)))
+    }
+
+    async fn embed(&self, provider: &str, _request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        Err(Error::UnsupportedProvider(format!("{}: embeddings are not supported", provider)))
+    }
+
+    // 调用方没有指定 provider / model 时使用
+    fn default_provider(&self) -> Option<String> {
+        None
+    }
+
+    fn default_model(&self, _provider: &str) -> Option<String> {
+        None
+    }
+}
+
+#[async_trait]
+impl LlmBackend for Client {
+    async fn chat(&self, provider: &str, request: ChatCompletionRequest) -> Result<ChatCompletionResponse> {
+        Client::chat(self, provider, request).await
+    }
+
+    async fn chat_stream(&self, provider: &str, request: ChatCompletionRequest) -> Result<EventStream> {
+        Client::chat_stream(self, provider, request).await
+    }
+
+    async fn list_models(&self, provider: &str) -> Result<Vec<ModelListing>> {
+        Client::list_models(self, provider).await
+    }
+
+    async fn embed(&self, provider: &str, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        Client::embed(self, provider, request).await
+    }
+
+    fn default_provider(&self) -> Option<String> {
+        Some(self.config().default_provider.clone())
+    }
+
+    fn default_model(&self, provider: &str) -> Option<String> {
+        self.providers()
+            .get(provider)
+            .map(|provider| provider.config.model.clone())
+            .filter(|model| !model.is_empty())
+    }
+}
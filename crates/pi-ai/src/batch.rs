@@ -0,0 +1,652 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::multipart::{Form, Part};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::client::{check_response, Client};
+use crate::conversation::{self, ConversationMessage};
+use crate::error::{Error, Result};
+use crate::hosted;
+use crate::message::Message;
+use crate::models::{ChatCompletionRequest, ChatCompletionResponse, CompletionChoice, Usage};
+use crate::provider::{Provider, ProviderType};
+use crate::tool_ids;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const INPUT_FILE: &str = "input.jsonl";
+const RESULTS_FILE: &str = "results.jsonl";
+const OPENAI_BATCH_ENDPOINT: &str = "/v1/chat/completions";
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchRequest {
+    pub custom_id: String,
+    pub request: ChatCompletionRequest,
+}
+
+impl BatchRequest {
+    pub fn new(custom_id: impl Into<String>, request: ChatCompletionRequest) -> Self {
+        Self {
+            custom_id: custom_id.into(),
+            request,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Pending,
+    Validating,
+    InProgress,
+    Finalizing,
+    Completed,
+    Failed,
+    Expired,
+    Cancelling,
+    Cancelled,
+}
+
+impl BatchStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            BatchStatus::Completed | BatchStatus::Failed | BatchStatus::Expired | BatchStatus::Cancelled
+        )
+    }
+
+    fn from_openai(status: &str) -> Self {
+        match status {
+            "validating" => BatchStatus::Validating,
+            "in_progress" => BatchStatus::InProgress,
+            "finalizing" => BatchStatus::Finalizing,
+            "completed" => BatchStatus::Completed,
+            "failed" => BatchStatus::Failed,
+            "expired" => BatchStatus::Expired,
+            "cancelling" => BatchStatus::Cancelling,
+            "cancelled" => BatchStatus::Cancelled,
+            _ => BatchStatus::InProgress,
+        }
+    }
+
+    fn from_anthropic(status: &str) -> Self {
+        match status {
+            "in_progress" => BatchStatus::InProgress,
+            "canceling" => BatchStatus::Cancelling,
+            "ended" => BatchStatus::Completed,
+            _ => BatchStatus::InProgress,
+        }
+    }
+}
+
+impl std::fmt::Display for BatchStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BatchStatus::Pending => write!(f, "pending"),
+            BatchStatus::Validating => write!(f, "validating"),
+            BatchStatus::InProgress => write!(f, "in_progress"),
+            BatchStatus::Finalizing => write!(f, "finalizing"),
+            BatchStatus::Completed => write!(f, "completed"),
+            BatchStatus::Failed => write!(f, "failed"),
+            BatchStatus::Expired => write!(f, "expired"),
+            BatchStatus::Cancelling => write!(f, "cancelling"),
+            BatchStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchItemResult {
+    Succeeded { response: ChatCompletionResponse },
+    Errored { code: Option<String>, message: String },
+    Cancelled,
+    Expired,
+}
+
+impl BatchItemResult {
+    pub fn response(&self) -> Option<&ChatCompletionResponse> {
+        match self {
+            BatchItemResult::Succeeded { response } => Some(response),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchManifest {
+    pub provider: String,
+    pub status: BatchStatus,
+    pub request_ids: Vec<String>,
+    pub batch_id: Option<String>,
+    pub input_file_id: Option<String>,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub results_url: Option<String>,
+    pub results_downloaded: bool,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchJob {
+    pub manifest: BatchManifest,
+    dir: PathBuf,
+}
+
+impl BatchJob {
+    pub fn create(
+        dir: impl AsRef<Path>,
+        provider: &Provider,
+        requests: &[BatchRequest],
+    ) -> Result<Self> {
+        let format = BatchFormat::from_provider_type(provider.provider_type)?;
+
+        if requests.is_empty() {
+            return Err(Error::InvalidConfig("Batch must contain at least one request".to_string()));
+        }
+
+        let mut seen = HashSet::new();
+        for request in requests {
+            if !seen.insert(request.custom_id.as_str()) {
+                return Err(Error::InvalidConfig(format!(
+                    "Duplicate batch custom_id: {}",
+                    request.custom_id
+                )));
+            }
+        }
+
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut input = String::new();
+        for request in requests {
+            input.push_str(&input_line(format, request)?);
+            input.push('\n');
+        }
+        std::fs::write(dir.join(INPUT_FILE), input)?;
+
+        let now = unix_now();
+        let job = Self {
+            manifest: BatchManifest {
+                provider: provider.name.clone(),
+                status: BatchStatus::Pending,
+                request_ids: requests.iter().map(|r| r.custom_id.clone()).collect(),
+                batch_id: None,
+                input_file_id: None,
+                output_file_id: None,
+                error_file_id: None,
+                results_url: None,
+                results_downloaded: false,
+                created_at: now,
+                updated_at: now,
+            },
+            dir,
+        };
+        job.save()?;
+
+        Ok(job)
+    }
+
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let content = std::fs::read_to_string(dir.join(MANIFEST_FILE))?;
+        let manifest: BatchManifest = serde_json::from_str(&content)?;
+        Ok(Self { manifest, dir })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let content = serde_json::to_string_pretty(&self.manifest)?;
+        let tmp_path = self.dir.join(format!("{}.tmp", MANIFEST_FILE));
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, self.manifest_path())?;
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn manifest_path(&self) -> PathBuf {
+        self.dir.join(MANIFEST_FILE)
+    }
+
+    pub fn input_path(&self) -> PathBuf {
+        self.dir.join(INPUT_FILE)
+    }
+
+    pub fn results_path(&self) -> PathBuf {
+        self.dir.join(RESULTS_FILE)
+    }
+
+    pub fn status(&self) -> BatchStatus {
+        self.manifest.status
+    }
+
+    pub fn is_submitted(&self) -> bool {
+        self.manifest.batch_id.is_some()
+    }
+
+    fn update_status(&mut self, status: BatchStatus) -> Result<()> {
+        self.manifest.status = status;
+        self.manifest.updated_at = unix_now();
+        self.save()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BatchFormat {
+    OpenAI,
+    Anthropic,
+}
+
+impl BatchFormat {
+    pub(crate) fn from_provider_type(provider_type: ProviderType) -> Result<Self> {
+        match provider_type {
+            ProviderType::OpenAI | ProviderType::Azure | ProviderType::Custom => Ok(BatchFormat::OpenAI),
+            ProviderType::Anthropic => Ok(BatchFormat::Anthropic),
+            _ => Err(Error::UnsupportedProviderType(provider_type.to_string())),
+        }
+    }
+}
+
+impl Client {
+    pub async fn create_batch(
+        &self,
+        provider_name: &str,
+        mut requests: Vec<BatchRequest>,
+        dir: impl AsRef<Path>,
+    ) -> Result<BatchJob> {
+        let provider = self.get_provider(provider_name)?;
+        for request in &mut requests {
+            tool_ids::prepare_messages(provider, &mut request.request.messages)?;
+        }
+        let mut job = BatchJob::create(dir, provider, &requests)?;
+        self.submit_batch(&mut job).await?;
+        Ok(job)
+    }
+
+    pub async fn submit_batch(&self, job: &mut BatchJob) -> Result<()> {
+        if job.is_submitted() {
+            return Ok(());
+        }
+
+        let provider = self.get_provider(&job.manifest.provider)?;
+        let input = std::fs::read_to_string(job.input_path())?;
+
+        match BatchFormat::from_provider_type(provider.provider_type)? {
+            BatchFormat::OpenAI => {
+                if job.manifest.input_file_id.is_none() {
+                    let mut headers = self.build_headers(provider)?;
+                    headers.remove(reqwest::header::CONTENT_TYPE);
+
+                    let part = Part::bytes(input.into_bytes())
+                        .file_name(INPUT_FILE)
+                        .mime_str("application/jsonl")
+                        .map_err(Error::Http)?;
+                    let form = Form::new().text("purpose", "batch").part("file", part);
+
+                    let response = self
+                        .http_client(provider)
+                        .post(provider.get_endpoint("files"))
+                        .headers(headers)
+                        .multipart(form)
+                        .send()
+                        .await
+                        .map_err(Error::Http)?;
+                    let file: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+                    job.manifest.input_file_id = Some(required_str(&file, "id")?.to_string());
+                    job.save()?;
+                }
+
+                let body = json!({
+                    "input_file_id": job.manifest.input_file_id,
+                    "endpoint": OPENAI_BATCH_ENDPOINT,
+                    "completion_window": "24h",
+                });
+
+                let response = self
+                    .http_client(provider)
+                    .post(provider.get_endpoint("batches"))
+                    .headers(self.build_headers(provider)?)
+                    .json(&body)
+                    .send()
+                    .await
+                    .map_err(Error::Http)?;
+                let batch: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+                job.manifest.batch_id = Some(required_str(&batch, "id")?.to_string());
+                job.update_status(BatchStatus::from_openai(batch["status"].as_str().unwrap_or_default()))?;
+            }
+            BatchFormat::Anthropic => {
+                let requests = input
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(serde_json::from_str::<Value>)
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+
+                let response = self
+                    .http_client(provider)
+                    .post(provider.get_endpoint("messages/batches"))
+                    .headers(self.build_headers(provider)?)
+                    .json(&json!({ "requests": requests }))
+                    .send()
+                    .await
+                    .map_err(Error::Http)?;
+                let batch: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+                job.manifest.batch_id = Some(required_str(&batch, "id")?.to_string());
+                job.update_status(BatchStatus::from_anthropic(
+                    batch["processing_status"].as_str().unwrap_or_default(),
+                ))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn poll_batch(&self, job: &mut BatchJob) -> Result<BatchStatus> {
+        let provider = self.get_provider(&job.manifest.provider)?;
+        let batch_id = job
+            .manifest
+            .batch_id
+            .clone()
+            .ok_or_else(|| Error::InvalidConfig("Batch has not been submitted".to_string()))?;
+
+        let format = BatchFormat::from_provider_type(provider.provider_type)?;
+        let path = match format {
+            BatchFormat::OpenAI => format!("batches/{}", batch_id),
+            BatchFormat::Anthropic => format!("messages/batches/{}", batch_id),
+        };
+
+        let response = self
+            .http_client(provider)
+            .get(provider.get_endpoint(&path))
+            .headers(self.build_headers(provider)?)
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        let batch: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+        let status = match format {
+            BatchFormat::OpenAI => {
+                job.manifest.output_file_id = batch["output_file_id"].as_str().map(String::from);
+                job.manifest.error_file_id = batch["error_file_id"].as_str().map(String::from);
+                BatchStatus::from_openai(batch["status"].as_str().unwrap_or_default())
+            }
+            BatchFormat::Anthropic => {
+                job.manifest.results_url = batch["results_url"].as_str().map(String::from);
+                BatchStatus::from_anthropic(batch["processing_status"].as_str().unwrap_or_default())
+            }
+        };
+
+        job.update_status(status)?;
+        Ok(status)
+    }
+
+    pub async fn wait_for_batch(&self, job: &mut BatchJob, poll_interval: Duration) -> Result<BatchStatus> {
+        loop {
+            let status = self.poll_batch(job).await?;
+            if status.is_terminal() {
+                return Ok(status);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    pub async fn cancel_batch(&self, job: &mut BatchJob) -> Result<BatchStatus> {
+        let provider = self.get_provider(&job.manifest.provider)?;
+        let Some(batch_id) = job.manifest.batch_id.clone() else {
+            job.update_status(BatchStatus::Cancelled)?;
+            return Ok(BatchStatus::Cancelled);
+        };
+
+        let format = BatchFormat::from_provider_type(provider.provider_type)?;
+        let path = match format {
+            BatchFormat::OpenAI => format!("batches/{}/cancel", batch_id),
+            BatchFormat::Anthropic => format!("messages/batches/{}/cancel", batch_id),
+        };
+
+        let response = self
+            .http_client(provider)
+            .post(provider.get_endpoint(&path))
+            .headers(self.build_headers(provider)?)
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        let batch: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+        let status = match format {
+            BatchFormat::OpenAI => BatchStatus::from_openai(batch["status"].as_str().unwrap_or_default()),
+            BatchFormat::Anthropic => {
+                BatchStatus::from_anthropic(batch["processing_status"].as_str().unwrap_or_default())
+            }
+        };
+
+        job.update_status(status)?;
+        Ok(status)
+    }
+
+    pub async fn batch_results(&self, job: &mut BatchJob) -> Result<HashMap<String, BatchItemResult>> {
+        let provider = self.get_provider(&job.manifest.provider)?;
+        let format = BatchFormat::from_provider_type(provider.provider_type)?;
+
+        if !job.manifest.results_downloaded {
+            if !job.status().is_terminal() {
+                return Err(Error::InvalidConfig(format!(
+                    "Batch is not finished (status: {})",
+                    job.status()
+                )));
+            }
+
+            let mut content = String::new();
+            match format {
+                BatchFormat::OpenAI => {
+                    let file_ids = [&job.manifest.output_file_id, &job.manifest.error_file_id];
+                    for file_id in file_ids.into_iter().flatten() {
+                        let url = provider.get_endpoint(&format!("files/{}/content", file_id));
+                        content.push_str(&self.download_batch_file(provider, &url).await?);
+                        if !content.ends_with('\n') {
+                            content.push('\n');
+                        }
+                    }
+                }
+                BatchFormat::Anthropic => {
+                    if let Some(url) = &job.manifest.results_url {
+                        content.push_str(&self.download_batch_file(provider, url).await?);
+                    }
+                }
+            }
+
+            std::fs::write(job.results_path(), content)?;
+            job.manifest.results_downloaded = true;
+            job.manifest.updated_at = unix_now();
+            job.save()?;
+        }
+
+        let content = std::fs::read_to_string(job.results_path())?;
+        let mut results = HashMap::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            let (custom_id, result) = parse_result_line(format, line)?;
+            results.insert(custom_id, result);
+        }
+
+        Ok(results)
+    }
+
+    async fn download_batch_file(&self, provider: &Provider, url: &str) -> Result<String> {
+        let response = self
+            .http_client(provider)
+            .get(url)
+            .headers(self.build_headers(provider)?)
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        check_response(provider, response).await?.text().await.map_err(Error::Http)
+    }
+}
+
+pub(crate) fn input_line(format: BatchFormat, request: &BatchRequest) -> Result<String> {
+    let line = match format {
+        BatchFormat::OpenAI => {
+            let mut body = serde_json::to_value(&request.request)?;
+            if let Some(body) = body.as_object_mut() {
+                body.retain(|_, v| !v.is_null());
+                body.remove("stream");
+            }
+            json!({
+                "custom_id": request.custom_id,
+                "method": "POST",
+                "url": OPENAI_BATCH_ENDPOINT,
+                "body": body,
+            })
+        }
+        BatchFormat::Anthropic => json!({
+            "custom_id": request.custom_id,
+            "params": anthropic_params(&request.request),
+        }),
+    };
+
+    Ok(serde_json::to_string(&line)?)
+}
+
+pub(crate) fn parse_result_line(format: BatchFormat, line: &str) -> Result<(String, BatchItemResult)> {
+    let value: Value = serde_json::from_str(line)?;
+    let custom_id = required_str(&value, "custom_id")?.to_string();
+
+    let result = match format {
+        BatchFormat::OpenAI => {
+            let status_code = value["response"]["status_code"].as_u64().unwrap_or(0);
+            if !value["error"].is_null() {
+                BatchItemResult::Errored {
+                    code: value["error"]["code"].as_str().map(String::from),
+                    message: value["error"]["message"].as_str().unwrap_or("Unknown error").to_string(),
+                }
+            } else if status_code != 200 {
+                let error = &value["response"]["body"]["error"];
+                BatchItemResult::Errored {
+                    code: error["code"].as_str().map(String::from).or_else(|| Some(status_code.to_string())),
+                    message: error["message"].as_str().unwrap_or("Unknown error").to_string(),
+                }
+            } else {
+                BatchItemResult::Succeeded {
+                    response: serde_json::from_value(value["response"]["body"].clone())?,
+                }
+            }
+        }
+        BatchFormat::Anthropic => {
+            let result = &value["result"];
+            match result["type"].as_str().unwrap_or_default() {
+                "succeeded" => BatchItemResult::Succeeded {
+                    response: anthropic_message_to_response(&result["message"])?,
+                },
+                "canceled" => BatchItemResult::Cancelled,
+                "expired" => BatchItemResult::Expired,
+                _ => BatchItemResult::Errored {
+                    code: result["error"]["error"]["type"].as_str().map(String::from),
+                    message: result["error"]["error"]["message"]
+                        .as_str()
+                        .unwrap_or("Unknown error")
+                        .to_string(),
+                },
+            }
+        }
+    };
+
+    Ok((custom_id, result))
+}
+
+fn anthropic_params(request: &ChatCompletionRequest) -> Value {
+    let conversation = ConversationMessage::from_messages(&request.messages);
+    let (system, messages) = conversation::anthropic::to_messages(&conversation);
+
+    let mut params = json!({
+        "model": request.model,
+        "max_tokens": request.max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+        "messages": messages,
+    });
+
+    if let Some(system) = system {
+        params["system"] = system;
+    }
+    if let Some(temperature) = request.temperature {
+        params["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = request.top_p {
+        params["top_p"] = json!(top_p);
+    }
+    if let Some(stop) = &request.stop {
+        params["stop_sequences"] = json!(stop);
+    }
+    let mut tools = request
+        .tools
+        .iter()
+        .flatten()
+        .map(|t| {
+            json!({
+                "name": t.function.name,
+                "description": t.function.description,
+                "input_schema": t.function.parameters,
+            })
+        })
+        .collect::<Vec<_>>();
+    tools.extend(request.hosted_tools.iter().filter_map(hosted::anthropic_tool_value));
+    if !tools.is_empty() {
+        params["tools"] = Value::Array(tools);
+    }
+
+    params
+}
+
+fn anthropic_message_to_response(message: &Value) -> Result<ChatCompletionResponse> {
+    let content = conversation::anthropic::from_messages(None, std::slice::from_ref(message));
+    let response_message = ConversationMessage::to_messages(&content)
+        .pop()
+        .unwrap_or_else(|| Message::assistant(""));
+
+    let finish_reason = message["stop_reason"].as_str().map(|reason| match reason {
+        "end_turn" | "stop_sequence" => "stop".to_string(),
+        "max_tokens" => "length".to_string(),
+        "tool_use" => "tool_calls".to_string(),
+        other => other.to_string(),
+    });
+
+    let prompt_tokens = message["usage"]["input_tokens"].as_u64().unwrap_or(0) as u32;
+    let completion_tokens = message["usage"]["output_tokens"].as_u64().unwrap_or(0) as u32;
+
+    Ok(ChatCompletionResponse {
+        id: required_str(message, "id")?.to_string(),
+        object: "chat.completion".to_string(),
+        created: 0,
+        model: message["model"].as_str().unwrap_or_default().to_string(),
+        choices: vec![CompletionChoice {
+            index: 0,
+            message: response_message,
+            finish_reason,
+            logprobs: None,
+        }],
+        usage: Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        },
+        system_fingerprint: None,
+    })
+}
+
+fn required_str<'a>(value: &'a Value, key: &str) -> Result<&'a str> {
+    value[key]
+        .as_str()
+        .ok_or_else(|| Error::InvalidResponse(format!("Expected '{}' string", key)))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
@@ -1,45 +1,63 @@
 use std::collections::HashMap;
+use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::stream::{self, Stream, StreamExt};
 use http::header::{HeaderMap, HeaderName, HeaderValue};
-use reqwest::Client as ReqwestClient;
+use reqwest::{Certificate, Client as ReqwestClient, Identity, NoProxy, Proxy, Response};
 use serde_json::{json, Value};
-
-use crate::config::{Config};
-use crate::error::{Error, Result};
-use crate::models::{ChatCompletionRequest, ChatCompletionResponse};
+use tracing::Instrument;
+
+use crate::config::{ApiMode, Config, NetworkConfig};
+use crate::conversation::openai;
+use crate::error::{Error, ProviderError, Result};
+use crate::health::{HealthTracker, ProviderHealth};
+use crate::hosted;
+use crate::models::{ChatCompletionRequest, ChatCompletionResponse, ModelListing};
 use crate::provider::{Provider, ProviderType};
-use crate::stream::{StreamChunk, StreamEvent};
+use crate::stream::{ChatStreamState, StreamEvent};
+use crate::telemetry::{self, InstrumentedStream};
+use crate::tool_ids;
+
+const MODEL_PAGE_SIZE: u32 = 1000;
+const AZURE_MODELS_API_VERSION: &str = "2024-10-21";
 
 #[derive(Debug, Clone)]
 pub struct Client {
     config: Arc<Config>,
     http_client: Arc<ReqwestClient>,
+    provider_http_clients: Arc<HashMap<String, ReqwestClient>>,
     providers: Arc<HashMap<String, Provider>>,
+    health: Arc<HealthTracker>,
 }
 
 impl Client {
     pub fn new(mut config: Config) -> Result<Self> {
-        let http_client = ReqwestClient::builder()
-            .timeout(Duration::from_secs(300))
-            .build()
-            .map_err(Error::Http)?;
+        let http_client = build_http_client(&NetworkConfig::default())?;
 
         let mut providers = HashMap::new();
+        let mut provider_http_clients = HashMap::new();
         let providers_iter = config.providers.drain();
         
         for (name, provider_config) in providers_iter {
+            // 只有需要代理、证书或超时设置的 provider 才单独建连接池
+            let network = provider_config.network.merged_with(&config.network);
+            if network != NetworkConfig::default() {
+                provider_http_clients.insert(name.clone(), build_http_client(&network)?);
+            }
+
             let provider_type = ProviderType::from(name.as_str());
             let provider = Provider::new(name.clone(), provider_type, provider_config);
             providers.insert(name, provider);
         }
 
         Ok(Self {
+            health: Arc::new(HealthTracker::new(config.circuit_breaker.clone())),
             config: Arc::new(config),
             http_client: Arc::new(http_client),
+            provider_http_clients: Arc::new(provider_http_clients),
             providers: Arc::new(providers),
         })
     }
@@ -54,13 +72,39 @@ impl Client {
             .get(provider_name)
             .ok_or_else(|| Error::UnsupportedProvider(provider_name.to_string()))?;
 
+        let telemetry = &self.config.telemetry;
+        let span = telemetry::chat_span(provider, &request, false);
+        telemetry::record_request(&span, provider, &request, telemetry);
+        let started = Instant::now();
+
+        let result = self
+            .guarded(provider, self.send_chat(provider, request))
+            .instrument(span.clone())
+            .await;
+        match &result {
+            Ok(response) => telemetry::record_response(&span, provider, response, started, telemetry),
+            Err(e) => telemetry::record_error(&span, e, started),
+        }
+        result
+    }
+
+    async fn send_chat(
+        &self,
+        provider: &Provider,
+        mut request: ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        tool_ids::prepare_messages(provider, &mut request.messages)?;
+        if provider.config.api_mode == ApiMode::Responses {
+            return self.responses_chat(provider, &request).await;
+        }
+
         let url = provider.get_endpoint("chat/completions");
         let headers = self.build_headers(provider)?;
 
         let body = self.build_request_body(&request)?;
 
         let response = self
-            .http_client
+            .http_client(provider)
             .post(&url)
             .headers(headers)
             .json(&body)
@@ -68,14 +112,16 @@ impl Client {
             .await
             .map_err(Error::Http)?;
 
-        if !response.status().is_success() {
-            return Err(Error::ApiError(
-                response.status().as_u16(),
-                response.text().await.unwrap_or_else(|_| "Unknown error".to_string()),
-            ));
+        let response = check_response(provider, response).await?;
+        let json: Value = response.json().await.map_err(Error::Http)?;
+        let mut response: ChatCompletionResponse = serde_json::from_value(json.clone())?;
+        for (choice, raw) in response.choices.iter_mut().zip(json["choices"].as_array().into_iter().flatten()) {
+            tool_ids::fill_missing_ids(&mut choice.message);
+            let citations = openai::citations_from_annotations(&raw["message"]["annotations"]);
+            if !citations.is_empty() {
+                choice.message.citations = Some(citations);
+            }
         }
-
-        let response: ChatCompletionResponse = response.json().await.map_err(Error::Http)?;
         Ok(response)
     }
 
@@ -89,14 +135,46 @@ impl Client {
             .get(provider_name)
             .ok_or_else(|| Error::UnsupportedProvider(provider_name.to_string()))?;
 
+        let telemetry = &self.config.telemetry;
+        let span = telemetry::chat_span(provider, &request, true);
+        telemetry::record_request(&span, provider, &request, telemetry);
+        let started = Instant::now();
+
+        // span 跟随返回的流一起结束，首个 token 的时间在流里记录
+        let result = self
+            .guarded(provider, self.send_chat_stream(provider, request))
+            .instrument(span.clone())
+            .await;
+        match result {
+            Ok(stream) => Ok(Box::pin(InstrumentedStream::new(stream, span, started, provider, telemetry))),
+            Err(e) => {
+                telemetry::record_error(&span, &e, started);
+                Err(e)
+            }
+        }
+    }
+
+    async fn send_chat_stream(
+        &self,
+        provider: &Provider,
+        mut request: ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>> {
+        tool_ids::prepare_messages(provider, &mut request.messages)?;
+        if provider.config.api_mode == ApiMode::Responses {
+            return self.responses_chat_stream(provider, &request).await;
+        }
+
         let url = provider.get_endpoint("chat/completions");
         let headers = self.build_headers(provider)?;
 
         let mut body = self.build_request_body(&request)?;
         body["stream"] = Value::Bool(true);
+        if matches!(provider.provider_type, ProviderType::OpenAI | ProviderType::Azure) {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
 
         let response = self
-            .http_client
+            .http_client(provider)
             .post(&url)
             .headers(headers)
             .json(&body)
@@ -104,54 +182,26 @@ impl Client {
             .await
             .map_err(Error::Http)?;
 
-        if !response.status().is_success() {
-            return Err(Error::ApiError(
-                response.status().as_u16(),
-                response.text().await.unwrap_or_else(|_| "Unknown error".to_string()),
-            ));
-        }
-
-        let stream = response.bytes_stream();
-        let stream = stream.flat_map(|chunk| {
-            match chunk {
-                Ok(bytes) => {
-                    let lines = std::str::from_utf8(&bytes)
-                        .unwrap_or_default()
-                        .lines()
-                        .filter(|line| !line.is_empty())
-                        .filter(|line| line.starts_with("data: "))
-                        .map(|line| line.trim_start_matches("data: "))
-                        .collect::<Vec<_>>();
-
-                    let events = lines.into_iter().flat_map(|line| {
-                        if line == "[DONE]" {
-                            vec![Ok(StreamEvent::Done)]
-                        } else {
-                            match serde_json::from_str::<StreamChunk>(line) {
-                                Ok(chunk) => {
-                                    // 解析 StreamChunk 为 StreamEvent
-                                    let chunk_events = crate::stream::chunk_to_event(&chunk);
-                                    chunk_events.into_iter().map(Ok).collect()
-                                }
-                                Err(e) => {
-                                    vec![Err(Error::Stream(e.to_string()))]
-                                }
-                            }
-                        }
-                    }).collect::<Vec<_>>();
-
-                    stream::iter(events)
-                }
-                Err(e) => {
-                    stream::iter(vec![Err(Error::Stream(e.to_string()))])
-                }
-            }
-        });
+        let response = check_response(provider, response).await?;
+        let stream = response
+            .bytes_stream()
+            .map(Some)
+            .chain(stream::once(async { None }))
+            .scan(ChatStreamState::default(), |state, chunk| {
+                let events = match chunk {
+                    Some(Ok(bytes)) => state.handle(&bytes),
+                    Some(Err(e)) => vec![Err(Error::Stream(e.to_string()))],
+                    // 有的兼容服务不发送 [DONE]
+                    None => state.finish(),
+                };
+                futures::future::ready(Some(events))
+            })
+            .flat_map(stream::iter);
 
         Ok(Box::pin(stream))
     }
 
-    fn build_headers(&self, provider: &Provider) -> Result<HeaderMap> {
+    pub(crate) fn build_headers(&self, provider: &Provider) -> Result<HeaderMap> {
         let mut headers = HeaderMap::new();
 
         // 添加 Content-Type 头
@@ -165,7 +215,7 @@ impl Client {
             ProviderType::OpenAI => {
                 headers.insert(
                     HeaderName::from_static("authorization"),
-                    HeaderValue::from_str(&format!("Bearer {}", provider.config.api_key))
+                    HeaderValue::from_str(&format!("Bearer {}", provider.config.api_key.expose_secret()))
                         .map_err(|e| Error::InvalidHeaderValue(e.to_string()))?,
                 );
                 if let Some(org) = &provider.config.organization {
@@ -179,7 +229,7 @@ impl Client {
             ProviderType::Anthropic => {
                 headers.insert(
                     HeaderName::from_static("x-api-key"),
-                    HeaderValue::from_str(&provider.config.api_key)
+                    HeaderValue::from_str(provider.config.api_key.expose_secret())
                         .map_err(|e| Error::InvalidHeaderValue(e.to_string()))?,
                 );
                 headers.insert(
@@ -190,21 +240,21 @@ impl Client {
             ProviderType::Google => {
                 headers.insert(
                     HeaderName::from_static("x-goog-api-key"),
-                    HeaderValue::from_str(&provider.config.api_key)
+                    HeaderValue::from_str(provider.config.api_key.expose_secret())
                         .map_err(|e| Error::InvalidHeaderValue(e.to_string()))?,
                 );
             }
             ProviderType::Azure => {
                 headers.insert(
                     HeaderName::from_static("api-key"),
-                    HeaderValue::from_str(&provider.config.api_key)
+                    HeaderValue::from_str(provider.config.api_key.expose_secret())
                         .map_err(|e| Error::InvalidHeaderValue(e.to_string()))?,
                 );
             }
             ProviderType::Custom => {
                 headers.insert(
                     HeaderName::from_static("authorization"),
-                    HeaderValue::from_str(&format!("Bearer {}", provider.config.api_key))
+                    HeaderValue::from_str(&format!("Bearer {}", provider.config.api_key.expose_secret()))
                         .map_err(|e| Error::InvalidHeaderValue(e.to_string()))?,
                 );
             }
@@ -222,11 +272,21 @@ impl Client {
         Ok(headers)
     }
 
-    fn build_request_body(
+    pub(crate) fn build_request_body(
         &self,
         request: &ChatCompletionRequest,
     ) -> Result<Value> {
-        let mut body = json!({"model": request.model,"messages": request.messages,"temperature": request.temperature,"top_p": request.top_p,"stream": false,"stop": request.stop,"max_tokens": request.max_tokens,"presence_penalty": request.presence_penalty,"frequency_penalty": request.frequency_penalty,"user": request.user,});
+        // 推理条目、引用和服务端工具结果只在本地保存，不回传给 Chat Completions
+        let mut messages = serde_json::to_value(&request.messages).map_err(Error::Json)?;
+        for message in messages.as_array_mut().into_iter().flatten() {
+            if let Some(message) = message.as_object_mut() {
+                message.remove("reasoning");
+                message.remove("citations");
+                message.remove("hosted_tool_calls");
+            }
+        }
+
+        let mut body = json!({"model": request.model,"messages": messages,"temperature": request.temperature,"top_p": request.top_p,"stream": false,"stop": request.stop,"max_tokens": request.max_tokens,"presence_penalty": request.presence_penalty,"frequency_penalty": request.frequency_penalty,"user": request.user,});
 
         if let Some(tools) = &request.tools {
             if !tools.is_empty() {
@@ -238,81 +298,78 @@ impl Client {
             body["tool_choice"] = serde_json::to_value(tool_choice).map_err(Error::Json)?;
         }
 
+        hosted::apply_chat_tools(&mut body, &request.hosted_tools)?;
+
+        if let Some(n) = request.n {
+            body["n"] = json!(n);
+        }
+        if let Some(logprobs) = request.logprobs {
+            body["logprobs"] = json!(logprobs);
+        }
+        if let Some(top_logprobs) = request.top_logprobs {
+            body["top_logprobs"] = json!(top_logprobs);
+        }
+        if let Some(seed) = request.seed {
+            body["seed"] = json!(seed);
+        }
+
         Ok(body)
     }
 
-    pub async fn list_models(&self, provider_name: &str) -> Result<Vec<String>> {
+    pub async fn list_models(&self, provider_name: &str) -> Result<Vec<ModelListing>> {
         let provider = self
             .providers
             .get(provider_name)
             .ok_or_else(|| Error::UnsupportedProvider(provider_name.to_string()))?;
 
-        let url = match provider.provider_type {
-            ProviderType::OpenAI => provider.get_endpoint("models"),
-            ProviderType::Anthropic => provider.get_endpoint("models"),
-            ProviderType::Google => provider.get_endpoint("models"),
-            _ => return Err(Error::UnsupportedProviderType(provider.provider_type.to_string())),
-        };
-
+        let url = provider.get_endpoint("models");
         let headers = self.build_headers(provider)?;
 
-        let response = self
-            .http_client
-            .get(&url)
-            .headers(headers)
-            .send()
-            .await
-            .map_err(Error::Http)?;
+        let mut models = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let mut query: Vec<(&str, String)> = Vec::new();
+            match provider.provider_type {
+                ProviderType::Anthropic => {
+                    query.push(("limit", MODEL_PAGE_SIZE.to_string()));
+                    if let Some(cursor) = &cursor {
+                        query.push(("after_id", cursor.clone()));
+                    }
+                }
+                ProviderType::Google => {
+                    query.push(("pageSize", MODEL_PAGE_SIZE.to_string()));
+                    if let Some(cursor) = &cursor {
+                        query.push(("pageToken", cursor.clone()));
+                    }
+                }
+                ProviderType::Azure => query.push(("api-version", AZURE_MODELS_API_VERSION.to_string())),
+                ProviderType::OpenAI | ProviderType::Custom => {}
+            }
 
-        if !response.status().is_success() {
-            return Err(Error::ApiError(
-                response.status().as_u16(),
-                response.text().await.unwrap_or_else(|_| "Unknown error".to_string()),
-            ));
+            let response = self
+                .http_client(provider)
+                .get(&url)
+                .headers(headers.clone())
+                .query(&query)
+                .send()
+                .await
+                .map_err(Error::Http)?;
+
+            let response = check_response(provider, response).await?;
+            let json: Value = response.json().await.map_err(Error::Http)?;
+            let (page, next) = parse_model_page(provider.provider_type, &json)?;
+            models.extend(page);
+
+            match next {
+                Some(next) if cursor.as_ref() != Some(&next) => cursor = Some(next),
+                _ => break,
+            }
         }
 
-        let json: Value = response.json().await.map_err(Error::Http)?;
-        let models = self.extract_models(&json, provider)?;
-
         Ok(models)
     }
 
-    fn extract_models(&self, json: &Value, provider: &Provider) -> Result<Vec<String>> {
-        match provider.provider_type {
-            ProviderType::OpenAI => {
-                let models = json["data"]
-                    .as_array()
-                    .ok_or_else(|| Error::InvalidResponse("Expected 'data' array".to_string()))?
-                    .iter()
-                    .filter_map(|item| item["id"].as_str())
-                    .map(|id| id.to_string())
-                    .collect::<Vec<String>>();
-                Ok(models)
-            }
-            ProviderType::Anthropic => {
-                let models = json["models"]
-                    .as_array()
-                    .ok_or_else(|| Error::InvalidResponse("Expected 'models' array".to_string()))?
-                    .iter()
-                    .filter_map(|item| item["id"].as_str())
-                    .map(|id| id.to_string())
-                    .collect::<Vec<String>>();
-                Ok(models)
-            }
-            ProviderType::Google => {
-                let models = json["models"]
-                    .as_array()
-                    .ok_or_else(|| Error::InvalidResponse("Expected 'models' array".to_string()))?
-                    .iter()
-                    .filter_map(|item| item["name"].as_str())
-                    .map(|name| name.to_string())
-                    .collect::<Vec<String>>();
-                Ok(models)
-            }
-            _ => Err(Error::UnsupportedProviderType(provider.provider_type.to_string())),
-        }
-    }
-
     pub fn config(&self) -> &Arc<Config> {
         &self.config
     }
@@ -320,4 +377,175 @@ impl Client {
     pub fn providers(&self) -> &Arc<HashMap<String, Provider>> {
         &self.providers
     }
+
+    pub fn provider_health(&self) -> HashMap<String, ProviderHealth> {
+        self.providers
+            .keys()
+            .map(|name| (name.clone(), self.health.snapshot(name)))
+            .collect()
+    }
+
+    // 熔断打开时不发请求直接失败，否则把结果计入该 provider 的健康统计
+    pub(crate) async fn guarded<T>(&self, provider: &Provider, call: impl Future<Output = Result<T>>) -> Result<T> {
+        self.health.check(&provider.name)?;
+        let started = Instant::now();
+        let result = call.await;
+        self.health.record(&provider.name, started.elapsed(), result.as_ref().err());
+        result
+    }
+
+    pub(crate) fn http_client(&self, provider: &Provider) -> &ReqwestClient {
+        self.provider_http_clients
+            .get(&provider.name)
+            .unwrap_or(&self.http_client)
+    }
+
+    pub(crate) fn get_provider(&self, provider_name: &str) -> Result<&Provider> {
+        self.providers
+            .get(provider_name)
+            .ok_or_else(|| Error::UnsupportedProvider(provider_name.to_string()))
+    }
+}
+
+pub(crate) fn build_http_client(network: &NetworkConfig) -> Result<ReqwestClient> {
+    let mut builder = ReqwestClient::builder().timeout(Duration::from_secs(300));
+
+    if let Some(proxy_config) = &network.proxy {
+        let mut proxy = Proxy::all(&proxy_config.url)
+            .map_err(|e| Error::InvalidConfig(format!("Invalid proxy URL {}: {}", proxy_config.url, e)))?;
+        if let Some(username) = &proxy_config.username {
+            proxy = proxy.basic_auth(username, proxy_config.password.as_deref().unwrap_or_default());
+        }
+        if !proxy_config.no_proxy.is_empty() {
+            proxy = proxy.no_proxy(NoProxy::from_string(&proxy_config.no_proxy.join(",")));
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    for path in &network.ca_certs {
+        let pem = read_file(path)?;
+        let certs = Certificate::from_pem_bundle(&pem)
+            .map_err(|e| Error::InvalidConfig(format!("Invalid CA bundle {}: {}", path.display(), e)))?;
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+
+    match (&network.client_cert, &network.client_key) {
+        (Some(cert_path), Some(key_path)) => {
+            let identity = Identity::from_pkcs8_pem(&read_file(cert_path)?, &read_file(key_path)?)
+                .map_err(|e| Error::InvalidConfig(format!("Invalid client certificate or key: {}", e)))?;
+            builder = builder.identity(identity);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(Error::InvalidConfig(
+                "client_cert and client_key must be set together".to_string(),
+            ))
+        }
+    }
+
+    if let Some(secs) = network.connect_timeout_secs {
+        builder = builder.connect_timeout(Duration::from_secs(secs));
+    }
+    if let Some(secs) = network.read_timeout_secs {
+        builder = builder.read_timeout(Duration::from_secs(secs));
+    }
+
+    builder.build().map_err(Error::Http)
+}
+
+fn read_file(path: &std::path::Path) -> Result<Vec<u8>> {
+    std::fs::read(path)
+        .map_err(|e| Error::InvalidConfig(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+pub(crate) async fn check_response(provider: &Provider, response: Response) -> Result<Response> {
+    if !response.status().is_success() {
+        let status = response.status().as_u16();
+        let headers = response.headers().clone();
+        let body = response.text().await.unwrap_or_default();
+        return Err(Error::ApiError(ProviderError::from_response(
+            provider.provider_type,
+            status,
+            &headers,
+            body,
+        )));
+    }
+    Ok(response)
+}
+
+// 返回当前页的模型和下一页的游标
+pub(crate) fn parse_model_page(
+    provider_type: ProviderType,
+    json: &Value,
+) -> Result<(Vec<ModelListing>, Option<String>)> {
+    let key = match provider_type {
+        ProviderType::Google => "models",
+        _ => "data",
+    };
+    let items = match &json[key] {
+        Value::Array(items) => items.as_slice(),
+        // Gemini 在最后一页为空时可能直接省略 models 字段
+        Value::Null if provider_type == ProviderType::Google => &[],
+        _ => return Err(Error::InvalidResponse(format!("Expected '{}' array", key))),
+    };
+
+    match provider_type {
+        ProviderType::Anthropic => {
+            let models = items
+                .iter()
+                .filter_map(|item| {
+                    let mut model = ModelListing::new(item["id"].as_str()?);
+                    model.display_name = item["display_name"].as_str().map(String::from);
+                    model.created = item["created_at"]
+                        .as_str()
+                        .and_then(|created| chrono::DateTime::parse_from_rfc3339(created).ok())
+                        .and_then(|created| u64::try_from(created.timestamp()).ok());
+                    model.owned_by = Some("anthropic".to_string());
+                    Some(model)
+                })
+                .collect();
+            let next = if json["has_more"].as_bool().unwrap_or(false) {
+                json["last_id"].as_str().map(String::from)
+            } else {
+                None
+            };
+            Ok((models, next))
+        }
+        ProviderType::Google => {
+            let models = items
+                .iter()
+                .filter_map(|item| {
+                    let name = item["name"].as_str()?;
+                    let mut model = ModelListing::new(name.trim_start_matches("models/"));
+                    model.display_name = item["displayName"].as_str().map(String::from);
+                    model.context_length = item["inputTokenLimit"].as_u64().map(|n| n as u32);
+                    model.max_output_tokens = item["outputTokenLimit"].as_u64().map(|n| n as u32);
+                    model.owned_by = Some("google".to_string());
+                    Some(model)
+                })
+                .collect();
+            let next = json["nextPageToken"].as_str().filter(|t| !t.is_empty()).map(String::from);
+            Ok((models, next))
+        }
+        // OpenAI 兼容格式：OpenAI、Azure 以及 vLLM / Ollama / LM Studio 等自建服务
+        _ => {
+            let models = items
+                .iter()
+                .filter_map(|item| {
+                    let mut model = ModelListing::new(item["id"].as_str()?);
+                    model.display_name = item["name"].as_str().map(String::from);
+                    model.context_length = ["context_length", "context_window", "max_model_len", "max_context_length"]
+                        .iter()
+                        .find_map(|key| item[*key].as_u64())
+                        .map(|n| n as u32);
+                    model.created = item["created"].as_u64().or_else(|| item["created_at"].as_u64());
+                    model.owned_by = item["owned_by"].as_str().map(String::from);
+                    Some(model)
+                })
+                .collect();
+            Ok((models, None))
+        }
+    }
 }
@@ -1,5 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::secret::SecretString;
+use crate::tool_ids::ToolCallIdFormat;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -7,6 +11,12 @@ pub struct Config {
     pub default_provider: String,
     pub timeout_secs: u64,
     pub max_retries: u32,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
 }
 
 impl Default for Config {
@@ -15,7 +25,7 @@ impl Default for Config {
         providers.insert(
             "openai".to_string(),
             ProviderConfig {
-                api_key: std::env::var("OPENAI_API_KEY").unwrap_or_default(),
+                api_key: SecretString::from_env("OPENAI_API_KEY").unwrap_or_default(),
                 base_url: "https://api.openai.com/v1".to_string(),
                 model: "gpt-4".to_string(),
                 ..Default::default()
@@ -27,13 +37,16 @@ impl Default for Config {
             default_provider: "openai".to_string(),
             timeout_secs: 120,
             max_retries: 3,
+            telemetry: TelemetryConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            network: NetworkConfig::default(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ProviderConfig {
-    pub api_key: String,
+    pub api_key: SecretString,
     pub base_url: String,
     pub model: String,
     pub organization: Option<String>,
@@ -41,19 +54,132 @@ pub struct ProviderConfig {
     pub temperature: Option<f32>,
     pub top_p: Option<f32>,
     pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub api_mode: ApiMode,
+    #[serde(default)]
+    pub responses: ResponsesConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    // 为空时按 provider 类型选择 tool call id 格式
+    #[serde(default)]
+    pub tool_call_ids: Option<ToolCallIdFormat>,
+}
+
+impl ProviderConfig {
+    // 已知 provider 的默认 base_url 和模型，未知的 provider 返回空配置
+    pub fn for_provider(name: &str) -> Self {
+        let (base_url, model) = match name {
+            "openai" => ("https://api.openai.com/v1", "gpt-4"),
+            "anthropic" => ("https://api.anthropic.com/v1", "claude-3-opus-20240229"),
+            "google" => ("https://generativelanguage.googleapis.com/v1beta", "gemini-pro"),
+            _ => return Self::default(),
+        };
+        Self {
+            base_url: base_url.to_string(),
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiMode {
+    #[default]
+    ChatCompletions,
+    Responses,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponsesConfig {
+    pub store: bool,
+    pub reasoning_effort: Option<String>,
+    pub reasoning_summary: Option<String>,
+    pub include_encrypted_reasoning: bool,
+}
+
+// 默认不记录 prompt/completion 内容，开启后按 redact 列表脱敏
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    pub capture_content: bool,
+    #[serde(default)]
+    pub redact: Vec<String>,
+    pub max_content_chars: Option<usize>,
+}
+
+// 连续失败 failure_threshold 次，或最近 window_size 次请求的错误率超过阈值时熔断
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    pub failure_threshold: u32,
+    pub window_size: usize,
+    pub min_requests: usize,
+    pub error_rate_threshold: f64,
+    pub open_secs: u64,
+    pub half_open_max_requests: u32,
 }
 
-impl Default for ProviderConfig {
+impl Default for CircuitBreakerConfig {
     fn default() -> Self {
         Self {
-            api_key: String::new(),
-            base_url: String::new(),
-            model: String::new(),
-            organization: None,
-            max_tokens: None,
-            temperature: None,
-            top_p: None,
-            headers: HashMap::new(),
+            enabled: true,
+            failure_threshold: 5,
+            window_size: 20,
+            min_requests: 10,
+            error_rate_threshold: 0.5,
+            open_secs: 30,
+            half_open_max_requests: 1,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn open_duration(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.open_secs)
+    }
+}
+
+// Config 里的是全局默认值，ProviderConfig 里设置的字段会覆盖它
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub proxy: Option<ProxyConfig>,
+    // PEM 格式，可以包含多个证书
+    pub ca_certs: Vec<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub connect_timeout_secs: Option<u64>,
+    pub read_timeout_secs: Option<u64>,
+}
+
+// url 支持 http://、https://、socks5:// 和 socks5h://
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    pub url: String,
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl NetworkConfig {
+    pub fn merged_with(&self, defaults: &NetworkConfig) -> NetworkConfig {
+        let (client_cert, client_key) = if self.client_cert.is_some() {
+            (self.client_cert.clone(), self.client_key.clone())
+        } else {
+            (defaults.client_cert.clone(), defaults.client_key.clone())
+        };
+
+        NetworkConfig {
+            proxy: self.proxy.clone().or_else(|| defaults.proxy.clone()),
+            ca_certs: defaults.ca_certs.iter().chain(&self.ca_certs).cloned().collect(),
+            client_cert,
+            client_key,
+            connect_timeout_secs: self.connect_timeout_secs.or(defaults.connect_timeout_secs),
+            read_timeout_secs: self.read_timeout_secs.or(defaults.read_timeout_secs),
         }
     }
 }
@@ -80,40 +206,35 @@ impl Config {
     pub fn from_env() -> Result<Self, crate::Error> {
         let mut config = Self::default();
 
-        if let Ok(openai_key) = std::env::var("OPENAI_API_KEY") {
+        if let Ok(openai_key) = SecretString::from_env("OPENAI_API_KEY") {
+            let defaults = ProviderConfig::for_provider("openai");
             config.providers.insert(
                 "openai".to_string(),
                 ProviderConfig {
                     api_key: openai_key,
-                    base_url: std::env::var("OPENAI_BASE_URL")
-                        .unwrap_or_else(|_| "https://api.openai.com/v1".to_string()),
-                    model: std::env::var("OPENAI_MODEL")
-                        .unwrap_or_else(|_| "gpt-4".to_string()),
+                    base_url: std::env::var("OPENAI_BASE_URL").unwrap_or(defaults.base_url),
+                    model: std::env::var("OPENAI_MODEL").unwrap_or(defaults.model),
                     ..Default::default()
                 },
             );
         }
 
-        if let Ok(anthropic_key) = std::env::var("ANTHROPIC_API_KEY") {
+        if let Ok(anthropic_key) = SecretString::from_env("ANTHROPIC_API_KEY") {
             config.providers.insert(
                 "anthropic".to_string(),
                 ProviderConfig {
                     api_key: anthropic_key,
-                    base_url: "https://api.anthropic.com/v1".to_string(),
-                    model: "claude-3-opus-20240229".to_string(),
-                    ..Default::default()
+                    ..ProviderConfig::for_provider("anthropic")
                 },
             );
         }
 
-        if let Ok(google_key) = std::env::var("GOOGLE_API_KEY") {
+        if let Ok(google_key) = SecretString::from_env("GOOGLE_API_KEY") {
             config.providers.insert(
                 "google".to_string(),
                 ProviderConfig {
                     api_key: google_key,
-                    base_url: "https://generativelanguage.googleapis.com/v1beta".to_string(),
-                    model: "gemini-pro".to_string(),
-                    ..Default::default()
+                    ..ProviderConfig::for_provider("google")
                 },
             );
         }
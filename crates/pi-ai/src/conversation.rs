@@ -0,0 +1,6 @@
+pub mod anthropic;
+pub mod content;
+pub mod gemini;
+pub mod openai;
+
+pub use content::{Citation, ContentBlock, ConversationMessage, MediaSource};
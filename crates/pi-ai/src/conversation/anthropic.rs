@@ -0,0 +1,343 @@
+use serde_json::{json, Value};
+
+use super::content::{parse_arguments, Citation, ContentBlock, ConversationMessage, MediaSource};
+use crate::message::MessageRole;
+
+// 返回 (system, messages)；Anthropic 要求 system 单独传，且 user/assistant 必须交替
+pub fn to_messages(conversation: &[ConversationMessage]) -> (Option<Value>, Vec<Value>) {
+    let mut system_blocks = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+
+    for message in conversation {
+        if message.role == MessageRole::System {
+            system_blocks.extend(message.content.iter().filter_map(block_value));
+            continue;
+        }
+
+        let role = match message.role {
+            MessageRole::Assistant => "assistant",
+            _ => "user",
+        };
+        let content = message.content.iter().filter_map(block_value).collect::<Vec<_>>();
+        if content.is_empty() {
+            continue;
+        }
+
+        match messages.last_mut() {
+            Some(last) if last["role"] == role => {
+                if let Some(existing) = last["content"].as_array_mut() {
+                    existing.extend(content);
+                }
+            }
+            _ => messages.push(json!({ "role": role, "content": content })),
+        }
+    }
+
+    // 单个纯文本块还原成字符串，保持请求体简洁
+    for message in &mut messages {
+        if let Some(text) = single_plain_text(&message["content"]) {
+            message["content"] = Value::String(text);
+        }
+    }
+
+    let system = if system_blocks.is_empty() {
+        None
+    } else {
+        let system = Value::Array(system_blocks);
+        Some(single_plain_text(&system).map(Value::String).unwrap_or(system))
+    };
+
+    (system, messages)
+}
+
+pub fn from_messages(system: Option<&Value>, messages: &[Value]) -> Vec<ConversationMessage> {
+    let mut conversation = Vec::new();
+
+    if let Some(system) = system {
+        let content = content_blocks(system);
+        if !content.is_empty() {
+            conversation.push(ConversationMessage::new(MessageRole::System, content));
+        }
+    }
+
+    for message in messages {
+        let blocks = content_blocks(&message["content"]);
+
+        if message["role"] == "assistant" {
+            conversation.push(ConversationMessage::new(MessageRole::Assistant, blocks));
+            continue;
+        }
+
+        // 一条 user 消息里可能混有 tool_result 和普通内容，按连续段拆开
+        let mut run: Vec<ContentBlock> = Vec::new();
+        for block in blocks {
+            let is_result = matches!(block, ContentBlock::ToolResult { .. });
+            let run_is_result = matches!(run.first(), Some(ContentBlock::ToolResult { .. }));
+            if !run.is_empty() && is_result != run_is_result {
+                conversation.push(user_or_tool(std::mem::take(&mut run)));
+            }
+            run.push(block);
+        }
+        if !run.is_empty() {
+            conversation.push(user_or_tool(run));
+        }
+    }
+
+    conversation
+}
+
+fn user_or_tool(content: Vec<ContentBlock>) -> ConversationMessage {
+    let role = match content.first() {
+        Some(ContentBlock::ToolResult { .. }) => MessageRole::Tool,
+        _ => MessageRole::User,
+    };
+    ConversationMessage::new(role, content)
+}
+
+fn single_plain_text(content: &Value) -> Option<String> {
+    match content.as_array()?.as_slice() {
+        [block] if block["type"] == "text" && block.get("citations").is_none() => {
+            block["text"].as_str().map(String::from)
+        }
+        _ => None,
+    }
+}
+
+fn block_value(block: &ContentBlock) -> Option<Value> {
+    let value = match block {
+        ContentBlock::Text { text, citations } => {
+            let mut value = json!({ "type": "text", "text": text });
+            if !citations.is_empty() {
+                value["citations"] = citations.iter().map(citation_value).collect();
+            }
+            value
+        }
+        ContentBlock::Image { source, .. } => json!({ "type": "image", "source": source_value(source) }),
+        ContentBlock::Document { source, title } => {
+            let mut value = json!({ "type": "document", "source": source_value(source) });
+            if let Some(title) = title {
+                value["title"] = json!(title);
+            }
+            value
+        }
+        ContentBlock::ToolUse { id, name, arguments } => json!({
+            "type": "tool_use",
+            "id": id,
+            "name": name,
+            "input": parse_arguments(arguments),
+        }),
+        ContentBlock::ServerToolUse { id, name, input } => json!({
+            "type": "server_tool_use",
+            "id": id,
+            "name": name,
+            "input": input,
+        }),
+        ContentBlock::ServerToolResult {
+            tool_use_id,
+            name,
+            content,
+        } => json!({
+            "type": format!("{}_tool_result", name),
+            "tool_use_id": tool_use_id,
+            "content": content,
+        }),
+        ContentBlock::ToolResult {
+            tool_use_id,
+            content,
+            is_error,
+            ..
+        } => {
+            let blocks = content.iter().filter_map(block_value).collect::<Vec<_>>();
+            let content = match single_plain_text(&Value::Array(blocks.clone())) {
+                Some(text) => Value::String(text),
+                None if blocks.is_empty() => Value::String(String::new()),
+                None => Value::Array(blocks),
+            };
+            let mut value = json!({
+                "type": "tool_result",
+                "tool_use_id": tool_use_id,
+                "content": content,
+            });
+            if *is_error {
+                value["is_error"] = json!(true);
+            }
+            value
+        }
+        ContentBlock::Reasoning {
+            text,
+            signature,
+            encrypted_content,
+            ..
+        } => match (text.is_empty(), encrypted_content) {
+            (true, Some(data)) => json!({ "type": "redacted_thinking", "data": data }),
+            // 没有签名的 thinking 块会被 API 拒绝，只能丢掉
+            _ => json!({
+                "type": "thinking",
+                "thinking": text,
+                "signature": signature.as_ref()?,
+            }),
+        },
+    };
+
+    Some(value)
+}
+
+fn source_value(source: &MediaSource) -> Value {
+    match source {
+        MediaSource::Base64 { media_type, data } => json!({
+            "type": "base64",
+            "media_type": media_type,
+            "data": data,
+        }),
+        MediaSource::Url { url, .. } => json!({ "type": "url", "url": url }),
+        MediaSource::FileId { file_id } => json!({ "type": "file", "file_id": file_id }),
+        MediaSource::PlainText { data } => json!({
+            "type": "text",
+            "media_type": "text/plain",
+            "data": data,
+        }),
+    }
+}
+
+fn citation_value(citation: &Citation) -> Value {
+    let mut value = json!({ "type": citation.kind });
+    if let Some(cited_text) = &citation.cited_text {
+        value["cited_text"] = json!(cited_text);
+    }
+
+    match citation.kind.as_str() {
+        "web_search_result_location" => {
+            value["url"] = json!(citation.url);
+            value["title"] = json!(citation.title);
+            value["encrypted_index"] = json!(citation.encrypted_index);
+        }
+        kind => {
+            let (start, end) = match kind {
+                "page_location" => ("start_page_number", "end_page_number"),
+                "content_block_location" => ("start_block_index", "end_block_index"),
+                _ => ("start_char_index", "end_char_index"),
+            };
+            value["document_index"] = json!(citation.document_index);
+            if let Some(title) = &citation.title {
+                value["document_title"] = json!(title);
+            }
+            value[start] = json!(citation.start_index);
+            value[end] = json!(citation.end_index);
+        }
+    }
+
+    value
+}
+
+fn content_blocks(content: &Value) -> Vec<ContentBlock> {
+    match content {
+        Value::String(text) => vec![ContentBlock::text(text.clone())],
+        Value::Array(blocks) => blocks.iter().filter_map(block_from_value).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn block_from_value(block: &Value) -> Option<ContentBlock> {
+    let block = match block["type"].as_str()? {
+        "text" => ContentBlock::Text {
+            text: block["text"].as_str().unwrap_or_default().to_string(),
+            citations: block["citations"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(citation_from_value)
+                .collect(),
+        },
+        "image" => ContentBlock::Image {
+            source: source_from_value(&block["source"])?,
+            detail: None,
+        },
+        "document" => ContentBlock::Document {
+            source: source_from_value(&block["source"])?,
+            title: block["title"].as_str().map(String::from),
+        },
+        "tool_use" => ContentBlock::ToolUse {
+            id: block["id"].as_str().unwrap_or_default().to_string(),
+            name: block["name"].as_str().unwrap_or_default().to_string(),
+            arguments: block["input"].to_string(),
+        },
+        "tool_result" => ContentBlock::ToolResult {
+            tool_use_id: block["tool_use_id"].as_str().unwrap_or_default().to_string(),
+            name: None,
+            content: content_blocks(&block["content"]),
+            is_error: block["is_error"].as_bool().unwrap_or(false),
+        },
+        "server_tool_use" => ContentBlock::ServerToolUse {
+            id: block["id"].as_str().unwrap_or_default().to_string(),
+            name: block["name"].as_str().unwrap_or_default().to_string(),
+            input: block["input"].clone(),
+        },
+        // web_search_tool_result、code_execution_tool_result 等
+        kind if kind.ends_with("_tool_result") => ContentBlock::ServerToolResult {
+            tool_use_id: block["tool_use_id"].as_str().unwrap_or_default().to_string(),
+            name: kind.trim_end_matches("_tool_result").to_string(),
+            content: block["content"].clone(),
+        },
+        "thinking" => ContentBlock::Reasoning {
+            id: None,
+            text: block["thinking"].as_str().unwrap_or_default().to_string(),
+            signature: block["signature"].as_str().map(String::from),
+            encrypted_content: None,
+        },
+        "redacted_thinking" => ContentBlock::Reasoning {
+            id: None,
+            text: String::new(),
+            signature: None,
+            encrypted_content: block["data"].as_str().map(String::from),
+        },
+        _ => return None,
+    };
+
+    Some(block)
+}
+
+fn source_from_value(source: &Value) -> Option<MediaSource> {
+    let source = match source["type"].as_str()? {
+        "base64" => MediaSource::Base64 {
+            media_type: source["media_type"].as_str().unwrap_or_default().to_string(),
+            data: source["data"].as_str().unwrap_or_default().to_string(),
+        },
+        "url" => MediaSource::Url {
+            url: source["url"].as_str().unwrap_or_default().to_string(),
+            media_type: None,
+        },
+        "file" => MediaSource::FileId {
+            file_id: source["file_id"].as_str().unwrap_or_default().to_string(),
+        },
+        "text" => MediaSource::PlainText {
+            data: source["data"].as_str().unwrap_or_default().to_string(),
+        },
+        _ => return None,
+    };
+
+    Some(source)
+}
+
+fn citation_from_value(citation: &Value) -> Citation {
+    let kind = citation["type"].as_str().unwrap_or_default().to_string();
+    let (start, end) = match kind.as_str() {
+        "page_location" => ("start_page_number", "end_page_number"),
+        "content_block_location" => ("start_block_index", "end_block_index"),
+        _ => ("start_char_index", "end_char_index"),
+    };
+
+    Citation {
+        cited_text: citation["cited_text"].as_str().map(String::from),
+        url: citation["url"].as_str().map(String::from),
+        title: citation["title"]
+            .as_str()
+            .or_else(|| citation["document_title"].as_str())
+            .map(String::from),
+        document_index: citation["document_index"].as_u64(),
+        start_index: citation[start].as_u64(),
+        end_index: citation[end].as_u64(),
+        encrypted_index: citation["encrypted_index"].as_str().map(String::from),
+        file_id: None,
+        kind,
+    }
+}
@@ -0,0 +1,322 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::hosted::HostedToolCall;
+use crate::message::{FunctionCall, Message, MessageRole, ReasoningItem, ToolCall};
+
+pub(crate) const SYNTHETIC_ID_PREFIX: &str = "pi_call_";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationMessage {
+    pub role: MessageRole,
+    pub content: Vec<ContentBlock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        citations: Vec<Citation>,
+    },
+    Image {
+        source: MediaSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        detail: Option<String>,
+    },
+    Document {
+        source: MediaSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        arguments: String,
+    },
+    ToolResult {
+        tool_use_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        content: Vec<ContentBlock>,
+        #[serde(default)]
+        is_error: bool,
+    },
+    // provider 服务端执行的工具（web search、code execution 等）
+    ServerToolUse {
+        id: String,
+        name: String,
+        #[serde(default)]
+        input: Value,
+    },
+    ServerToolResult {
+        tool_use_id: String,
+        name: String,
+        #[serde(default)]
+        content: Value,
+    },
+    Reasoning {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        encrypted_content: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MediaSource {
+    Base64 { media_type: String, data: String },
+    Url {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        media_type: Option<String>,
+    },
+    FileId { file_id: String },
+    PlainText { data: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cited_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_index: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_index: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_index: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_index: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<String>,
+}
+
+impl ContentBlock {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentBlock::Text {
+            text: text.into(),
+            citations: Vec::new(),
+        }
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ContentBlock::Text { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+}
+
+impl ConversationMessage {
+    pub fn new(role: MessageRole, content: Vec<ContentBlock>) -> Self {
+        Self {
+            role,
+            content,
+            name: None,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.content.iter().filter_map(ContentBlock::as_text).collect()
+    }
+
+    pub fn tool_uses(&self) -> impl Iterator<Item = (&str, &str, &str)> {
+        self.content.iter().filter_map(|block| match block {
+            ContentBlock::ToolUse { id, name, arguments } => Some((id.as_str(), name.as_str(), arguments.as_str())),
+            _ => None,
+        })
+    }
+
+    pub fn from_messages(messages: &[Message]) -> Vec<Self> {
+        messages.iter().map(Self::from).collect()
+    }
+
+    pub fn to_messages(conversation: &[Self]) -> Vec<Message> {
+        conversation.iter().flat_map(Self::to_message_list).collect()
+    }
+
+    // A tool message can hold several results; the flat `Message` model needs one message per result.
+    fn to_message_list(&self) -> Vec<Message> {
+        if self.role == MessageRole::Tool {
+            return self
+                .content
+                .iter()
+                .filter_map(|block| match block {
+                    ContentBlock::ToolResult { tool_use_id, content, .. } => {
+                        let text: String = content.iter().filter_map(ContentBlock::as_text).collect();
+                        Some(Message::tool(text, tool_use_id.clone()))
+                    }
+                    _ => None,
+                })
+                .collect();
+        }
+
+        let mut message = match self.role {
+            MessageRole::System => Message::system(self.text()),
+            MessageRole::User => Message::user(self.text()),
+            _ => Message::assistant(self.text()),
+        };
+
+        let tool_calls = self
+            .tool_uses()
+            .map(|(id, name, arguments)| ToolCall {
+                id: id.to_string(),
+                tool_type: "function".to_string(),
+                function: FunctionCall {
+                    name: name.to_string(),
+                    arguments: arguments.to_string(),
+                },
+            })
+            .collect::<Vec<_>>();
+        if !tool_calls.is_empty() {
+            message = message.with_tool_calls(tool_calls);
+        }
+
+        let reasoning = self
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Reasoning {
+                    id,
+                    text,
+                    encrypted_content,
+                    signature,
+                } => Some(ReasoningItem {
+                    id: id.clone(),
+                    summary: if text.is_empty() { Vec::new() } else { vec![text.clone()] },
+                    encrypted_content: encrypted_content.clone(),
+                    signature: signature.clone(),
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        if !reasoning.is_empty() {
+            message = message.with_reasoning(reasoning);
+        }
+
+        let citations = self
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { citations, .. } => Some(citations.iter().cloned()),
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        if !citations.is_empty() {
+            message = message.with_citations(citations);
+        }
+
+        let mut hosted_calls: Vec<HostedToolCall> = Vec::new();
+        for block in &self.content {
+            match block {
+                ContentBlock::ServerToolUse { id, name, input } => {
+                    hosted_calls.push(HostedToolCall::new(id.clone(), name.clone(), input.clone()))
+                }
+                ContentBlock::ServerToolResult { tool_use_id, content, .. } => {
+                    if let Some(call) = hosted_calls.iter_mut().find(|c| &c.id == tool_use_id) {
+                        *call = call.clone().with_output(content.clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !hosted_calls.is_empty() {
+            message = message.with_hosted_tool_calls(hosted_calls);
+        }
+
+        if let Some(name) = &self.name {
+            message = message.with_name(name.clone());
+        }
+
+        vec![message]
+    }
+}
+
+impl From<&Message> for ConversationMessage {
+    fn from(message: &Message) -> Self {
+        let mut content = Vec::new();
+
+        if message.role == MessageRole::Tool {
+            content.push(ContentBlock::ToolResult {
+                tool_use_id: message.tool_call_id.clone().unwrap_or_default(),
+                name: message.name.clone(),
+                content: vec![ContentBlock::text(message.content.clone())],
+                is_error: false,
+            });
+            return Self::new(MessageRole::Tool, content);
+        }
+
+        for reasoning in message.reasoning.iter().flatten() {
+            content.push(ContentBlock::Reasoning {
+                id: reasoning.id.clone(),
+                text: reasoning.summary.join("\n"),
+                signature: reasoning.signature.clone(),
+                encrypted_content: reasoning.encrypted_content.clone(),
+            });
+        }
+        // 服务端工具的调用和结果在正文之前
+        for call in message.hosted_tool_calls.iter().flatten() {
+            content.push(ContentBlock::ServerToolUse {
+                id: call.id.clone(),
+                name: call.name.clone(),
+                input: call.input.clone(),
+            });
+            if let Some(output) = &call.output {
+                content.push(ContentBlock::ServerToolResult {
+                    tool_use_id: call.id.clone(),
+                    name: call.name.clone(),
+                    content: output.clone(),
+                });
+            }
+        }
+        if !message.content.is_empty() {
+            content.push(ContentBlock::Text {
+                text: message.content.clone(),
+                citations: message.citations.clone().unwrap_or_default(),
+            });
+        }
+        for call in message.tool_calls.iter().flatten() {
+            content.push(ContentBlock::ToolUse {
+                id: call.id.clone(),
+                name: call.function.name.clone(),
+                arguments: call.function.arguments.clone(),
+            });
+        }
+
+        Self {
+            role: message.role.clone(),
+            content,
+            name: message.name.clone(),
+        }
+    }
+}
+
+pub(crate) fn parse_arguments(arguments: &str) -> Value {
+    if arguments.trim().is_empty() {
+        return Value::Object(Default::default());
+    }
+    serde_json::from_str(arguments).unwrap_or_else(|_| Value::String(arguments.to_string()))
+}
+
+pub(crate) fn synthetic_tool_id(index: usize) -> String {
+    format!("{}{}", SYNTHETIC_ID_PREFIX, index)
+}
+
+pub(crate) fn parse_data_url(url: &str) -> Option<(String, String)> {
+    let rest = url.strip_prefix("data:")?;
+    let (media_type, data) = rest.split_once(";base64,")?;
+    Some((media_type.to_string(), data.to_string()))
+}
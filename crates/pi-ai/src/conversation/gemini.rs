@@ -0,0 +1,230 @@
+use std::collections::{HashMap, VecDeque};
+
+use serde_json::{json, Value};
+
+use super::content::{
+    parse_arguments, synthetic_tool_id, ContentBlock, ConversationMessage, MediaSource, SYNTHETIC_ID_PREFIX,
+};
+use crate::message::MessageRole;
+
+// 返回 (systemInstruction, contents)
+pub fn to_contents(conversation: &[ConversationMessage]) -> (Option<Value>, Vec<Value>) {
+    let mut system_parts = Vec::new();
+    let mut contents: Vec<Value> = Vec::new();
+    // Gemini 的 functionResponse 按名字关联调用，需要从 tool_use id 反查
+    let mut call_names: HashMap<&str, &str> = HashMap::new();
+
+    for message in conversation {
+        if message.role == MessageRole::System {
+            system_parts.extend(message.content.iter().filter_map(|block| part_value(block, &call_names)));
+            continue;
+        }
+
+        for (id, name, _) in message.tool_uses() {
+            call_names.insert(id, name);
+        }
+
+        let role = match message.role {
+            MessageRole::Assistant => "model",
+            _ => "user",
+        };
+        let parts = message
+            .content
+            .iter()
+            .filter_map(|block| part_value(block, &call_names))
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            continue;
+        }
+
+        match contents.last_mut() {
+            Some(last) if last["role"] == role => {
+                if let Some(existing) = last["parts"].as_array_mut() {
+                    existing.extend(parts);
+                }
+            }
+            _ => contents.push(json!({ "role": role, "parts": parts })),
+        }
+    }
+
+    let system = if system_parts.is_empty() {
+        None
+    } else {
+        Some(json!({ "parts": system_parts }))
+    };
+
+    (system, contents)
+}
+
+pub fn from_contents(system: Option<&Value>, contents: &[Value]) -> Vec<ConversationMessage> {
+    let mut conversation = Vec::new();
+
+    if let Some(system) = system {
+        let content = system["parts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|part| part["text"].as_str())
+            .map(ContentBlock::text)
+            .collect::<Vec<_>>();
+        if !content.is_empty() {
+            conversation.push(ConversationMessage::new(MessageRole::System, content));
+        }
+    }
+
+    // Gemini 不一定返回调用 id，没有 id 时生成一个，并按名字顺序匹配结果
+    let mut next_id = 0;
+    let mut pending: HashMap<String, VecDeque<String>> = HashMap::new();
+
+    for content in contents {
+        let assistant = content["role"] == "model";
+        let mut blocks = Vec::new();
+        let mut results = Vec::new();
+
+        for part in content["parts"].as_array().into_iter().flatten() {
+            if let Some(call) = part.get("functionCall") {
+                let name = call["name"].as_str().unwrap_or_default().to_string();
+                let id = match call["id"].as_str() {
+                    Some(id) => id.to_string(),
+                    None => {
+                        next_id += 1;
+                        synthetic_tool_id(next_id)
+                    }
+                };
+                pending.entry(name.clone()).or_default().push_back(id.clone());
+                blocks.push(ContentBlock::ToolUse {
+                    id,
+                    name,
+                    arguments: call["args"].to_string(),
+                });
+            } else if let Some(response) = part.get("functionResponse") {
+                let name = response["name"].as_str().unwrap_or_default().to_string();
+                let queued = pending.get_mut(&name).and_then(VecDeque::pop_front);
+                let tool_use_id = match response["id"].as_str() {
+                    Some(id) => id.to_string(),
+                    None => queued.unwrap_or_default(),
+                };
+                let content = match &response["response"] {
+                    Value::Object(map) if map.len() == 1 && map.get("content").is_some_and(Value::is_string) => {
+                        map["content"].as_str().unwrap_or_default().to_string()
+                    }
+                    other => other.to_string(),
+                };
+                results.push(ContentBlock::ToolResult {
+                    tool_use_id,
+                    name: Some(name),
+                    content: vec![ContentBlock::text(content)],
+                    is_error: false,
+                });
+            } else if let Some(block) = block_from_part(part) {
+                blocks.push(block);
+            }
+        }
+
+        let role = if assistant { MessageRole::Assistant } else { MessageRole::User };
+        if !results.is_empty() {
+            conversation.push(ConversationMessage::new(MessageRole::Tool, results));
+        }
+        if !blocks.is_empty() {
+            conversation.push(ConversationMessage::new(role, blocks));
+        }
+    }
+
+    conversation
+}
+
+// 服务端工具的结果 Gemini 无法回放，直接丢弃
+fn part_value(block: &ContentBlock, call_names: &HashMap<&str, &str>) -> Option<Value> {
+    let part = match block {
+        ContentBlock::Text { text, .. } => json!({ "text": text }),
+        ContentBlock::Image { source, .. } | ContentBlock::Document { source, .. } => match source {
+            MediaSource::Base64 { media_type, data } => json!({
+                "inlineData": { "mimeType": media_type, "data": data },
+            }),
+            MediaSource::Url { url, media_type } => json!({
+                "fileData": {
+                    "mimeType": media_type.as_deref().unwrap_or("application/octet-stream"),
+                    "fileUri": url,
+                },
+            }),
+            MediaSource::FileId { file_id } => json!({ "fileData": { "fileUri": file_id } }),
+            MediaSource::PlainText { data } => json!({ "text": data }),
+        },
+        ContentBlock::ToolUse { id, name, arguments } => {
+            let mut call = json!({ "name": name, "args": parse_arguments(arguments) });
+            if !id.starts_with(SYNTHETIC_ID_PREFIX) {
+                call["id"] = json!(id);
+            }
+            json!({ "functionCall": call })
+        }
+        ContentBlock::ToolResult {
+            tool_use_id,
+            name,
+            content,
+            ..
+        } => {
+            let name = name
+                .as_deref()
+                .or_else(|| call_names.get(tool_use_id.as_str()).copied())
+                .unwrap_or_default();
+            let text: String = content.iter().filter_map(ContentBlock::as_text).collect();
+            let response = match serde_json::from_str::<Value>(&text) {
+                Ok(value @ Value::Object(_)) => value,
+                _ => json!({ "content": text }),
+            };
+            let mut function_response = json!({ "name": name, "response": response });
+            if !tool_use_id.starts_with(SYNTHETIC_ID_PREFIX) {
+                function_response["id"] = json!(tool_use_id);
+            }
+            json!({ "functionResponse": function_response })
+        }
+        ContentBlock::Reasoning { text, signature, .. } => {
+            let mut part = json!({ "text": text, "thought": true });
+            if let Some(signature) = signature {
+                part["thoughtSignature"] = json!(signature);
+            }
+            part
+        }
+        ContentBlock::ServerToolUse { .. } | ContentBlock::ServerToolResult { .. } => return None,
+    };
+
+    Some(part)
+}
+
+fn block_from_part(part: &Value) -> Option<ContentBlock> {
+    if let Some(text) = part["text"].as_str() {
+        if part["thought"].as_bool().unwrap_or(false) {
+            return Some(ContentBlock::Reasoning {
+                id: None,
+                text: text.to_string(),
+                signature: part["thoughtSignature"].as_str().map(String::from),
+                encrypted_content: None,
+            });
+        }
+        return Some(ContentBlock::text(text));
+    }
+
+    let (source, media_type) = if let Some(inline) = part.get("inlineData") {
+        let media_type = inline["mimeType"].as_str().unwrap_or_default().to_string();
+        let source = MediaSource::Base64 {
+            media_type: media_type.clone(),
+            data: inline["data"].as_str().unwrap_or_default().to_string(),
+        };
+        (source, media_type)
+    } else if let Some(file) = part.get("fileData") {
+        let media_type = file["mimeType"].as_str().map(String::from);
+        let source = MediaSource::Url {
+            url: file["fileUri"].as_str().unwrap_or_default().to_string(),
+            media_type: media_type.clone(),
+        };
+        (source, media_type.unwrap_or_default())
+    } else {
+        return None;
+    };
+
+    if media_type.starts_with("image/") {
+        Some(ContentBlock::Image { source, detail: None })
+    } else {
+        Some(ContentBlock::Document { source, title: None })
+    }
+}
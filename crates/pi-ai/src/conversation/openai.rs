@@ -0,0 +1,261 @@
+use serde_json::{json, Value};
+
+use super::content::{parse_data_url, Citation, ContentBlock, ConversationMessage, MediaSource};
+use crate::message::MessageRole;
+
+// 兼容接口之间的差异；OpenAI 会拒绝 assistant 输入上的 reasoning_content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dialect {
+    #[default]
+    OpenAI,
+    DeepSeek,
+}
+
+pub fn to_messages(conversation: &[ConversationMessage]) -> Vec<Value> {
+    to_messages_for(conversation, Dialect::OpenAI)
+}
+
+// 引用只在本地保存，不会作为 annotations 回传
+pub fn to_messages_for(conversation: &[ConversationMessage], dialect: Dialect) -> Vec<Value> {
+    let mut messages = Vec::new();
+
+    for message in conversation {
+        match message.role {
+            MessageRole::Tool => {
+                for block in &message.content {
+                    if let ContentBlock::ToolResult { tool_use_id, content, .. } = block {
+                        messages.push(json!({
+                            "role": "tool",
+                            "tool_call_id": tool_use_id,
+                            "content": content_value(content),
+                        }));
+                    }
+                }
+            }
+            MessageRole::Assistant => {
+                let text = message.text();
+                let mut value = json!({
+                    "role": "assistant",
+                    "content": if text.is_empty() { Value::Null } else { Value::String(text) },
+                });
+
+                let tool_calls = message
+                    .tool_uses()
+                    .map(|(id, name, arguments)| {
+                        json!({
+                            "id": id,
+                            "type": "function",
+                            "function": { "name": name, "arguments": arguments },
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                if !tool_calls.is_empty() {
+                    value["tool_calls"] = Value::Array(tool_calls);
+                }
+
+                // DeepSeek 的 reasoning_content 只有纯文本
+                let reasoning = message
+                    .content
+                    .iter()
+                    .filter_map(|block| match block {
+                        ContentBlock::Reasoning { text, .. } if !text.is_empty() => Some(text.as_str()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                if dialect == Dialect::DeepSeek && !reasoning.is_empty() {
+                    value["reasoning_content"] = json!(reasoning.join("\n"));
+                }
+
+                if let Some(name) = &message.name {
+                    value["name"] = json!(name);
+                }
+                messages.push(value);
+            }
+            MessageRole::System | MessageRole::User => {
+                let mut value = json!({
+                    "role": String::from(message.role.clone()),
+                    "content": content_value(&message.content),
+                });
+                if let Some(name) = &message.name {
+                    value["name"] = json!(name);
+                }
+                messages.push(value);
+            }
+        }
+    }
+
+    messages
+}
+
+pub fn from_messages(messages: &[Value]) -> Vec<ConversationMessage> {
+    messages.iter().map(from_message).collect()
+}
+
+fn from_message(message: &Value) -> ConversationMessage {
+    let role = match message["role"].as_str().unwrap_or_default() {
+        "system" | "developer" => MessageRole::System,
+        "assistant" => MessageRole::Assistant,
+        "tool" | "function" => MessageRole::Tool,
+        _ => MessageRole::User,
+    };
+
+    let mut content = Vec::new();
+
+    match role {
+        MessageRole::Tool => {
+            content.push(ContentBlock::ToolResult {
+                tool_use_id: message["tool_call_id"].as_str().unwrap_or_default().to_string(),
+                name: message["name"].as_str().map(String::from),
+                content: content_blocks(&message["content"]),
+                is_error: false,
+            });
+            return ConversationMessage::new(role, content);
+        }
+        MessageRole::Assistant => {
+            if let Some(reasoning) = message["reasoning_content"].as_str() {
+                content.push(ContentBlock::Reasoning {
+                    id: None,
+                    text: reasoning.to_string(),
+                    signature: None,
+                    encrypted_content: None,
+                });
+            }
+
+            let citations = citations_from_annotations(&message["annotations"]);
+
+            let mut blocks = content_blocks(&message["content"]);
+            if let Some(ContentBlock::Text { citations: target, .. }) = blocks.first_mut() {
+                *target = citations;
+            }
+            content.extend(blocks);
+
+            for call in message["tool_calls"].as_array().into_iter().flatten() {
+                content.push(ContentBlock::ToolUse {
+                    id: call["id"].as_str().unwrap_or_default().to_string(),
+                    name: call["function"]["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: call["function"]["arguments"].as_str().unwrap_or_default().to_string(),
+                });
+            }
+        }
+        _ => content.extend(content_blocks(&message["content"])),
+    }
+
+    ConversationMessage {
+        role,
+        content,
+        name: message["name"].as_str().map(String::from),
+    }
+}
+
+// Chat Completions 的 annotations，只有 url_citation 一种
+pub(crate) fn citations_from_annotations(annotations: &Value) -> Vec<Citation> {
+    annotations
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|a| a["type"] == "url_citation")
+        .map(|a| {
+            let c = &a["url_citation"];
+            Citation {
+                kind: "url_citation".to_string(),
+                url: c["url"].as_str().map(String::from),
+                title: c["title"].as_str().map(String::from),
+                start_index: c["start_index"].as_u64(),
+                end_index: c["end_index"].as_u64(),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn content_value(blocks: &[ContentBlock]) -> Value {
+    if let [ContentBlock::Text { text, citations }] = blocks {
+        if citations.is_empty() {
+            return Value::String(text.clone());
+        }
+    }
+    if blocks.is_empty() {
+        return Value::String(String::new());
+    }
+
+    Value::Array(blocks.iter().filter_map(part_value).collect())
+}
+
+fn part_value(block: &ContentBlock) -> Option<Value> {
+    match block {
+        ContentBlock::Text { text, .. } => Some(json!({ "type": "text", "text": text })),
+        ContentBlock::ServerToolUse { .. } | ContentBlock::ServerToolResult { .. } => None,
+        ContentBlock::Image { source, detail } => {
+            let url = match source {
+                MediaSource::Base64 { media_type, data } => format!("data:{};base64,{}", media_type, data),
+                MediaSource::Url { url, .. } => url.clone(),
+                MediaSource::FileId { file_id } => file_id.clone(),
+                MediaSource::PlainText { data } => return Some(json!({ "type": "text", "text": data })),
+            };
+            let mut image_url = json!({ "url": url });
+            if let Some(detail) = detail {
+                image_url["detail"] = json!(detail);
+            }
+            Some(json!({ "type": "image_url", "image_url": image_url }))
+        }
+        ContentBlock::Document { source, title } => {
+            let mut file = match source {
+                MediaSource::Base64 { media_type, data } => {
+                    json!({ "file_data": format!("data:{};base64,{}", media_type, data) })
+                }
+                MediaSource::FileId { file_id } => json!({ "file_id": file_id }),
+                MediaSource::Url { url, .. } => return Some(json!({ "type": "text", "text": url })),
+                MediaSource::PlainText { data } => return Some(json!({ "type": "text", "text": data })),
+            };
+            if let Some(title) = title {
+                file["filename"] = json!(title);
+            }
+            Some(json!({ "type": "file", "file": file }))
+        }
+        _ => None,
+    }
+}
+
+fn content_blocks(content: &Value) -> Vec<ContentBlock> {
+    match content {
+        Value::String(text) => vec![ContentBlock::text(text.clone())],
+        Value::Array(parts) => parts.iter().filter_map(block_from_part).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn block_from_part(part: &Value) -> Option<ContentBlock> {
+    match part["type"].as_str()? {
+        "text" => Some(ContentBlock::text(part["text"].as_str().unwrap_or_default())),
+        "image_url" => {
+            let url = part["image_url"]["url"].as_str().unwrap_or_default();
+            let source = match parse_data_url(url) {
+                Some((media_type, data)) => MediaSource::Base64 { media_type, data },
+                None => MediaSource::Url {
+                    url: url.to_string(),
+                    media_type: None,
+                },
+            };
+            Some(ContentBlock::Image {
+                source,
+                detail: part["image_url"]["detail"].as_str().map(String::from),
+            })
+        }
+        "file" => {
+            let file = &part["file"];
+            let source = if let Some(file_id) = file["file_id"].as_str() {
+                MediaSource::FileId {
+                    file_id: file_id.to_string(),
+                }
+            } else {
+                let (media_type, data) = parse_data_url(file["file_data"].as_str().unwrap_or_default())?;
+                MediaSource::Base64 { media_type, data }
+            };
+            Some(ContentBlock::Document {
+                source,
+                title: file["filename"].as_str().map(String::from),
+            })
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::client::{check_response, Client};
+use crate::error::{Error, Result};
+use crate::provider::{Provider, ProviderType};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl EmbeddingRequest {
+    pub fn new(model: impl Into<String>, input: Vec<String>) -> Self {
+        Self {
+            model: model.into(),
+            input,
+            dimensions: None,
+            user: None,
+        }
+    }
+
+    pub fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmbeddingResponse {
+    pub model: String,
+    pub data: Vec<Embedding>,
+    #[serde(default)]
+    pub usage: EmbeddingUsage,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Embedding {
+    pub index: u32,
+    pub embedding: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EmbeddingUsage {
+    #[serde(default)]
+    pub prompt_tokens: u32,
+    #[serde(default)]
+    pub total_tokens: u32,
+}
+
+impl EmbeddingResponse {
+    // 按输入顺序返回向量
+    pub fn vectors(&self) -> Vec<&[f32]> {
+        let mut data = self.data.iter().collect::<Vec<_>>();
+        data.sort_by_key(|e| e.index);
+        data.into_iter().map(|e| e.embedding.as_slice()).collect()
+    }
+}
+
+impl Client {
+    pub async fn embed(&self, provider_name: &str, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        let provider = self.get_provider(provider_name)?;
+        self.guarded(provider, self.send_embed(provider, request)).await
+    }
+
+    async fn send_embed(&self, provider: &Provider, request: EmbeddingRequest) -> Result<EmbeddingResponse> {
+        let (url, body) = match provider.provider_type {
+            ProviderType::OpenAI | ProviderType::Azure | ProviderType::Custom => {
+                let mut body = json!({ "model": request.model, "input": request.input });
+                if let Some(dimensions) = request.dimensions {
+                    body["dimensions"] = json!(dimensions);
+                }
+                if let Some(user) = &request.user {
+                    body["user"] = json!(user);
+                }
+                (provider.get_endpoint("embeddings"), body)
+            }
+            ProviderType::Google => {
+                let model = format!("models/{}", request.model.trim_start_matches("models/"));
+                let requests = request
+                    .input
+                    .iter()
+                    .map(|text| {
+                        let mut item = json!({ "model": model, "content": { "parts": [{ "text": text }] } });
+                        if let Some(dimensions) = request.dimensions {
+                            item["outputDimensionality"] = json!(dimensions);
+                        }
+                        item
+                    })
+                    .collect::<Vec<_>>();
+                (
+                    provider.get_endpoint(&format!("{}:batchEmbedContents", model)),
+                    json!({ "requests": requests }),
+                )
+            }
+            ProviderType::Anthropic => {
+                return Err(Error::UnsupportedProviderType(format!(
+                    "{} does not provide embeddings",
+                    provider.provider_type
+                )))
+            }
+        };
+
+        let response = self
+            .http_client(provider)
+            .post(&url)
+            .headers(self.build_headers(provider)?)
+            .json(&body)
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        let json: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+        match provider.provider_type {
+            ProviderType::Google => parse_google_embeddings(&request.model, &json),
+            _ => serde_json::from_value(json).map_err(Error::Json),
+        }
+    }
+}
+
+fn parse_google_embeddings(model: &str, json: &Value) -> Result<EmbeddingResponse> {
+    let embeddings = json["embeddings"]
+        .as_array()
+        .ok_or_else(|| Error::InvalidResponse("Expected 'embeddings' array".to_string()))?;
+
+    let data = embeddings
+        .iter()
+        .enumerate()
+        .map(|(index, embedding)| Embedding {
+            index: index as u32,
+            embedding: embedding["values"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_f64().map(|v| v as f32))
+                .collect(),
+        })
+        .collect();
+
+    Ok(EmbeddingResponse {
+        model: model.to_string(),
+        data,
+        usage: EmbeddingUsage::default(),
+    })
+}
@@ -1,9 +1,12 @@
 use std::fmt;
 use std::io;
+use std::time::Duration;
 
 use thiserror::Error;
 use reqwest::Error as ReqwestError;
-use serde_json::Error as SerdeJsonError;
+use serde_json::{Error as SerdeJsonError, Value};
+
+use crate::provider::ProviderType;
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -22,8 +25,8 @@ pub enum Error {
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
 
-    #[error("API error: {0} - {1}")]
-    ApiError(u16, String),
+    #[error("API error: {0}")]
+    ApiError(ProviderError),
 
     #[error("Invalid response: {0}")]
     InvalidResponse(String),
@@ -39,6 +42,298 @@ pub enum Error {
 
     #[error("Invalid header name: {0}")]
     InvalidHeaderName(String),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
+    #[error("Circuit open for provider {provider}, retry after {retry_after:?}")]
+    CircuitOpen { provider: String, retry_after: Duration },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    pub fn provider_error(&self) -> Option<&ProviderError> {
+        match self {
+            Error::ApiError(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> Option<ErrorKind> {
+        self.provider_error().map(|e| e.kind)
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::ApiError(e) => e.is_retryable(),
+            Error::Http(e) => e.is_timeout() || e.is_connect(),
+            Error::CircuitOpen { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_context_overflow(&self) -> bool {
+        self.provider_error().is_some_and(|e| e.is_context_overflow())
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::CircuitOpen { retry_after, .. } => Some(*retry_after),
+            _ => self.provider_error().and_then(|e| e.retry_after),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    RateLimit,
+    QuotaExceeded,
+    Authentication,
+    PermissionDenied,
+    NotFound,
+    InvalidRequest,
+    ContextLengthExceeded,
+    ContentFilter,
+    Overloaded,
+    ServerError,
+    Timeout,
+    Unknown,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::RateLimit => write!(f, "rate_limit"),
+            ErrorKind::QuotaExceeded => write!(f, "quota_exceeded"),
+            ErrorKind::Authentication => write!(f, "authentication"),
+            ErrorKind::PermissionDenied => write!(f, "permission_denied"),
+            ErrorKind::NotFound => write!(f, "not_found"),
+            ErrorKind::InvalidRequest => write!(f, "invalid_request"),
+            ErrorKind::ContextLengthExceeded => write!(f, "context_length_exceeded"),
+            ErrorKind::ContentFilter => write!(f, "content_filter"),
+            ErrorKind::Overloaded => write!(f, "overloaded"),
+            ErrorKind::ServerError => write!(f, "server_error"),
+            ErrorKind::Timeout => write!(f, "timeout"),
+            ErrorKind::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProviderError {
+    pub kind: ErrorKind,
+    pub status: u16,
+    pub message: String,
+    pub retry_after: Option<Duration>,
+    pub provider_code: Option<String>,
+    pub request_id: Option<String>,
+    pub body: String,
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}) - {}", self.status, self.kind, self.message)
+    }
+}
+
+const CONTEXT_OVERFLOW_PATTERNS: &[&str] = &[
+    "context_length_exceeded",
+    "maximum context length",
+    "context window",
+    "prompt is too long",
+    "input is too long",
+    "too many tokens",
+    "exceeds the maximum number of tokens",
+    "reduce the length of the messages",
+];
+
+impl ProviderError {
+    pub fn new(kind: ErrorKind, status: u16, message: impl Into<String>) -> Self {
+        let message = message.into();
+        Self {
+            kind,
+            status,
+            body: message.clone(),
+            message,
+            retry_after: None,
+            provider_code: None,
+            request_id: None,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind,
+            ErrorKind::RateLimit | ErrorKind::Overloaded | ErrorKind::ServerError | ErrorKind::Timeout
+        )
+    }
+
+    pub fn is_context_overflow(&self) -> bool {
+        self.kind == ErrorKind::ContextLengthExceeded
+    }
+
+    pub fn from_response(
+        provider_type: ProviderType,
+        status: u16,
+        headers: &reqwest::header::HeaderMap,
+        body: String,
+    ) -> Self {
+        let json: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+        let error = &json["error"];
+
+        let (message, provider_code, kind_hint) = match provider_type {
+            ProviderType::Anthropic => {
+                let error_type = error["type"].as_str();
+                (
+                    error["message"].as_str(),
+                    error_type.map(String::from),
+                    error_type.and_then(anthropic_kind),
+                )
+            }
+            ProviderType::Google => {
+                let error_status = error["status"].as_str();
+                (
+                    error["message"].as_str(),
+                    error_status.map(String::from),
+                    error_status.and_then(google_kind),
+                )
+            }
+            _ => {
+                // OpenAI-compatible servers put the useful discriminator in `code`, falling back to `type`.
+                let code = error["code"]
+                    .as_str()
+                    .map(String::from)
+                    .or_else(|| error["code"].as_i64().map(|c| c.to_string()))
+                    .or_else(|| error["type"].as_str().map(String::from));
+                let kind = code.as_deref().and_then(openai_kind);
+                (
+                    error["message"].as_str().or_else(|| error.as_str()),
+                    code,
+                    kind,
+                )
+            }
+        };
+
+        let message = message
+            .map(String::from)
+            .unwrap_or_else(|| if body.is_empty() { "Unknown error".to_string() } else { body.clone() });
+
+        let lower = message.to_lowercase();
+        let kind = if CONTEXT_OVERFLOW_PATTERNS.iter().any(|p| lower.contains(p)) {
+            ErrorKind::ContextLengthExceeded
+        } else {
+            match kind_hint {
+                Some(kind) if kind != ErrorKind::InvalidRequest => kind,
+                _ => status_kind(status).unwrap_or(kind_hint.unwrap_or(ErrorKind::Unknown)),
+            }
+        };
+
+        let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+        let retry_after = header("retry-after-ms")
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_millis)
+            .or_else(|| header("retry-after").and_then(parse_retry_after))
+            .or_else(|| google_retry_delay(error));
+        let request_id = ["x-request-id", "request-id", "apim-request-id", "x-goog-request-id"]
+            .into_iter()
+            .find_map(header)
+            .map(String::from);
+
+        Self {
+            kind,
+            status,
+            message,
+            retry_after,
+            provider_code,
+            request_id,
+            body,
+        }
+    }
+}
+
+fn status_kind(status: u16) -> Option<ErrorKind> {
+    match status {
+        400 | 422 => Some(ErrorKind::InvalidRequest),
+        401 => Some(ErrorKind::Authentication),
+        403 => Some(ErrorKind::PermissionDenied),
+        404 => Some(ErrorKind::NotFound),
+        408 | 504 => Some(ErrorKind::Timeout),
+        413 => Some(ErrorKind::InvalidRequest),
+        429 => Some(ErrorKind::RateLimit),
+        503 | 529 => Some(ErrorKind::Overloaded),
+        500..=599 => Some(ErrorKind::ServerError),
+        _ => None,
+    }
+}
+
+fn openai_kind(code: &str) -> Option<ErrorKind> {
+    match code {
+        "context_length_exceeded" | "string_above_max_length" => Some(ErrorKind::ContextLengthExceeded),
+        "rate_limit_exceeded" | "rate_limit_error" | "requests" | "tokens" => Some(ErrorKind::RateLimit),
+        "insufficient_quota" | "billing_hard_limit_reached" => Some(ErrorKind::QuotaExceeded),
+        "invalid_api_key" | "authentication_error" | "invalid_authentication" => Some(ErrorKind::Authentication),
+        "permission_denied" | "unsupported_country_region_territory" => Some(ErrorKind::PermissionDenied),
+        "model_not_found" | "not_found" | "DeploymentNotFound" => Some(ErrorKind::NotFound),
+        "content_filter" | "content_policy_violation" | "ResponsibleAIPolicyViolation" => {
+            Some(ErrorKind::ContentFilter)
+        }
+        "server_error" | "internal_error" => Some(ErrorKind::ServerError),
+        "engine_overloaded" | "overloaded" => Some(ErrorKind::Overloaded),
+        "timeout" => Some(ErrorKind::Timeout),
+        "invalid_request_error" => Some(ErrorKind::InvalidRequest),
+        _ => None,
+    }
+}
+
+fn anthropic_kind(error_type: &str) -> Option<ErrorKind> {
+    match error_type {
+        "invalid_request_error" | "request_too_large" => Some(ErrorKind::InvalidRequest),
+        "authentication_error" => Some(ErrorKind::Authentication),
+        "permission_error" => Some(ErrorKind::PermissionDenied),
+        "not_found_error" => Some(ErrorKind::NotFound),
+        "rate_limit_error" => Some(ErrorKind::RateLimit),
+        "api_error" => Some(ErrorKind::ServerError),
+        "overloaded_error" => Some(ErrorKind::Overloaded),
+        "timeout_error" => Some(ErrorKind::Timeout),
+        _ => None,
+    }
+}
+
+fn google_kind(status: &str) -> Option<ErrorKind> {
+    match status {
+        "INVALID_ARGUMENT" | "FAILED_PRECONDITION" | "OUT_OF_RANGE" => Some(ErrorKind::InvalidRequest),
+        "UNAUTHENTICATED" => Some(ErrorKind::Authentication),
+        "PERMISSION_DENIED" => Some(ErrorKind::PermissionDenied),
+        "NOT_FOUND" => Some(ErrorKind::NotFound),
+        "RESOURCE_EXHAUSTED" => Some(ErrorKind::RateLimit),
+        "INTERNAL" | "UNKNOWN" => Some(ErrorKind::ServerError),
+        "UNAVAILABLE" => Some(ErrorKind::Overloaded),
+        "DEADLINE_EXCEEDED" => Some(ErrorKind::Timeout),
+        _ => None,
+    }
+}
+
+fn google_retry_delay(error: &Value) -> Option<Duration> {
+    error["details"]
+        .as_array()?
+        .iter()
+        .find_map(|d| d["retryDelay"].as_str())
+        .and_then(|delay| delay.trim_end_matches('s').parse::<f64>().ok())
+        .and_then(seconds)
+}
+
+// Retry-After 可以是秒数，也可以是 HTTP 日期；已经过去的日期按立即重试处理
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<f64>() {
+        return seconds(secs);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
+
+// 负数、NaN、无穷大或超出范围的值一律忽略，不能让服务端的脏数据把客户端弄崩
+fn seconds(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs).ok()
+}
@@ -0,0 +1,503 @@
+use std::pin::Pin;
+use std::time::Instant;
+
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::Instrument;
+
+use crate::client::{check_response, Client};
+use crate::error::{Error, Result};
+use crate::models::Usage;
+use crate::provider::{Provider, ProviderType};
+use crate::stream::{SseBuffer, StreamEvent};
+use crate::telemetry::{self, InstrumentedStream};
+
+const DEFAULT_FIM_ENDPOINT: &str = "completions";
+const MAX_SERVER_STOP_SEQUENCES: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FimTemplate {
+    Native,
+    Codestral,
+    DeepSeekCoder,
+    StarCoder,
+    CodeLlama,
+    QwenCoder,
+    Custom {
+        prefix_token: String,
+        suffix_token: String,
+        middle_token: String,
+        suffix_first: bool,
+        stop: Vec<String>,
+    },
+}
+
+impl FimTemplate {
+    pub fn for_model(model: &str) -> Self {
+        let model = model.to_lowercase();
+        if model.contains("codestral") {
+            FimTemplate::Codestral
+        } else if model.contains("deepseek") {
+            FimTemplate::DeepSeekCoder
+        } else if model.contains("starcoder") {
+            FimTemplate::StarCoder
+        } else if model.contains("codellama") || model.contains("code-llama") {
+            FimTemplate::CodeLlama
+        } else if model.contains("qwen") && model.contains("coder") {
+            FimTemplate::QwenCoder
+        } else {
+            FimTemplate::Native
+        }
+    }
+
+    pub fn render(&self, prefix: &str, suffix: &str) -> String {
+        match self {
+            FimTemplate::Native => prefix.to_string(),
+            FimTemplate::Codestral => format!("[SUFFIX]{}[PREFIX]{}", suffix, prefix),
+            FimTemplate::DeepSeekCoder => {
+                format!("<｜fim▁begin｜>{}<｜fim▁hole｜>{}<｜fim▁end｜>", prefix, suffix)
+            }
+            FimTemplate::StarCoder => {
+                format!("<fim_prefix>{}<fim_suffix>{}<fim_middle>", prefix, suffix)
+            }
+            FimTemplate::CodeLlama => format!("<PRE> {} <SUF>{} <MID>", prefix, suffix),
+            FimTemplate::QwenCoder => {
+                format!("<|fim_prefix|>{}<|fim_suffix|>{}<|fim_middle|>", prefix, suffix)
+            }
+            FimTemplate::Custom {
+                prefix_token,
+                suffix_token,
+                middle_token,
+                suffix_first,
+                ..
+            } => {
+                if *suffix_first {
+                    format!("{}{}{}{}{}", suffix_token, suffix, prefix_token, prefix, middle_token)
+                } else {
+                    format!("{}{}{}{}{}", prefix_token, prefix, suffix_token, suffix, middle_token)
+                }
+            }
+        }
+    }
+
+    pub fn stop_sequences(&self) -> Vec<String> {
+        let stops: &[&str] = match self {
+            FimTemplate::Native => &[],
+            FimTemplate::Codestral => &["</s>", "[PREFIX]", "[SUFFIX]"],
+            FimTemplate::DeepSeekCoder => &["<｜end▁of▁sentence｜>", "<｜fim▁begin｜>", "<｜fim▁hole｜>", "<｜fim▁end｜>"],
+            FimTemplate::StarCoder => &["<|endoftext|>", "<fim_prefix>", "<fim_suffix>", "<fim_middle>", "<file_sep>"],
+            FimTemplate::CodeLlama => &["<EOT>", "<PRE>", "<SUF>", "<MID>"],
+            FimTemplate::QwenCoder => &["<|endoftext|>", "<|fim_prefix|>", "<|fim_suffix|>", "<|fim_middle|>", "<|fim_pad|>", "<|file_sep|>"],
+            FimTemplate::Custom { stop, .. } => return stop.clone(),
+        };
+        stops.iter().map(|s| s.to_string()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FimRequest {
+    pub model: String,
+    pub prefix: String,
+    pub suffix: Option<String>,
+    pub template: Option<FimTemplate>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub stop: Option<Vec<String>>,
+    pub endpoint: Option<String>,
+}
+
+impl FimRequest {
+    pub fn new(model: impl Into<String>, prefix: impl Into<String>) -> Self {
+        Self {
+            model: model.into(),
+            prefix: prefix.into(),
+            suffix: None,
+            template: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            stop: None,
+            endpoint: None,
+        }
+    }
+
+    pub fn with_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.suffix = Some(suffix.into());
+        self
+    }
+
+    pub fn with_template(mut self, template: FimTemplate) -> Self {
+        self.template = Some(template);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    pub fn resolved_template(&self) -> FimTemplate {
+        self.template
+            .clone()
+            .unwrap_or_else(|| FimTemplate::for_model(&self.model))
+    }
+
+    pub fn stop_sequences(&self) -> Vec<String> {
+        let mut stops = self.stop.clone().unwrap_or_default();
+        for stop in self.resolved_template().stop_sequences() {
+            if !stops.contains(&stop) {
+                stops.push(stop);
+            }
+        }
+        stops
+    }
+
+    pub(crate) fn build_body(&self, stream: bool) -> Value {
+        let template = self.resolved_template();
+        let suffix = self.suffix.as_deref().unwrap_or_default();
+        let stops = self.stop_sequences();
+
+        let mut body = json!({
+            "model": self.model,
+            "prompt": template.render(&self.prefix, suffix),
+            "stream": stream,
+        });
+
+        if template == FimTemplate::Native && self.suffix.is_some() {
+            body["suffix"] = Value::String(suffix.to_string());
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            body["max_tokens"] = json!(max_tokens);
+        }
+        if let Some(temperature) = self.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if !stops.is_empty() {
+            let server_stops = stops.iter().take(MAX_SERVER_STOP_SEQUENCES).collect::<Vec<_>>();
+            body["stop"] = json!(server_stops);
+        }
+
+        body
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FimResponse {
+    pub id: String,
+    pub model: String,
+    pub choices: Vec<FimChoice>,
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FimChoice {
+    pub index: u32,
+    pub text: String,
+    pub finish_reason: Option<String>,
+}
+
+impl FimResponse {
+    pub fn text(&self) -> &str {
+        self.choices.first().map(|c| c.text.as_str()).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StopSequenceFilter {
+    stops: Vec<String>,
+    pending: String,
+    stopped: bool,
+}
+
+impl StopSequenceFilter {
+    pub fn new(stops: Vec<String>) -> Self {
+        Self {
+            stops: stops.into_iter().filter(|s| !s.is_empty()).collect(),
+            pending: String::new(),
+            stopped: false,
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn push(&mut self, text: &str) -> String {
+        if self.stopped {
+            return String::new();
+        }
+
+        self.pending.push_str(text);
+
+        if let Some(pos) = self.stops.iter().filter_map(|s| self.pending.find(s.as_str())).min() {
+            self.stopped = true;
+            let emitted = self.pending[..pos].to_string();
+            self.pending.clear();
+            return emitted;
+        }
+
+        // Hold back any tail that could still grow into a stop sequence.
+        let hold = self
+            .stops
+            .iter()
+            .flat_map(|stop| {
+                (1..stop.len())
+                    .filter(|&n| stop.is_char_boundary(n) && self.pending.ends_with(&stop[..n]))
+                    .max()
+            })
+            .max()
+            .unwrap_or(0);
+
+        let split = self.pending.len() - hold;
+        let emitted = self.pending[..split].to_string();
+        self.pending.drain(..split);
+        emitted
+    }
+
+    pub fn finish(&mut self) -> String {
+        if self.stopped {
+            return String::new();
+        }
+        std::mem::take(&mut self.pending)
+    }
+}
+
+impl Client {
+    pub async fn complete_fim(&self, provider_name: &str, request: FimRequest) -> Result<FimResponse> {
+        let provider = self.get_provider(provider_name)?;
+        ensure_fim_supported(provider.provider_type)?;
+
+        let telemetry = &self.config().telemetry;
+        let span = telemetry::fim_span(provider, &request, false);
+        telemetry::record_fim_request(&span, provider, &request, telemetry);
+        let started = Instant::now();
+
+        let result = self
+            .guarded(provider, self.send_fim(provider, request))
+            .instrument(span.clone())
+            .await;
+        match &result {
+            Ok(response) => telemetry::record_fim_response(&span, provider, response, started, telemetry),
+            Err(e) => telemetry::record_error(&span, e, started),
+        }
+        result
+    }
+
+    async fn send_fim(&self, provider: &Provider, request: FimRequest) -> Result<FimResponse> {
+        let url = provider.get_endpoint(request.endpoint.as_deref().unwrap_or(DEFAULT_FIM_ENDPOINT));
+        let response = self
+            .http_client(provider)
+            .post(&url)
+            .headers(self.build_headers(provider)?)
+            .json(&request.build_body(false))
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        let json: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+        let mut response = parse_fim_response(&json)?;
+        let stops = request.stop_sequences();
+        for choice in &mut response.choices {
+            let mut filter = StopSequenceFilter::new(stops.clone());
+            let mut text = filter.push(&choice.text);
+            text.push_str(&filter.finish());
+            if filter.is_stopped() {
+                choice.finish_reason = Some("stop".to_string());
+            }
+            choice.text = text;
+        }
+
+        Ok(response)
+    }
+
+    pub async fn complete_fim_stream(
+        &self,
+        provider_name: &str,
+        request: FimRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>> {
+        let provider = self.get_provider(provider_name)?;
+        ensure_fim_supported(provider.provider_type)?;
+
+        let telemetry = &self.config().telemetry;
+        let span = telemetry::fim_span(provider, &request, true);
+        telemetry::record_fim_request(&span, provider, &request, telemetry);
+        let started = Instant::now();
+
+        let result = self
+            .guarded(provider, self.send_fim_stream(provider, request))
+            .instrument(span.clone())
+            .await;
+        match result {
+            Ok(stream) => Ok(Box::pin(InstrumentedStream::new(stream, span, started, provider, telemetry))),
+            Err(e) => {
+                telemetry::record_error(&span, &e, started);
+                Err(e)
+            }
+        }
+    }
+
+    async fn send_fim_stream(
+        &self,
+        provider: &Provider,
+        request: FimRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>> {
+        let url = provider.get_endpoint(request.endpoint.as_deref().unwrap_or(DEFAULT_FIM_ENDPOINT));
+        let response = self
+            .http_client(provider)
+            .post(&url)
+            .headers(self.build_headers(provider)?)
+            .json(&request.build_body(true))
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        let response = check_response(provider, response).await?;
+
+        let state = FimStreamState::new(request.stop_sequences());
+
+        let stream = response
+            .bytes_stream()
+            .map(|chunk| Some(chunk.map(|bytes| bytes.to_vec())))
+            .chain(stream::once(async { None }))
+            .scan(state, |state, chunk| {
+                let events = if state.done {
+                    None
+                } else {
+                    Some(state.handle(chunk))
+                };
+                futures::future::ready(events)
+            })
+            .flat_map(stream::iter);
+
+        Ok(Box::pin(stream))
+    }
+}
+
+pub(crate) struct FimStreamState {
+    filter: StopSequenceFilter,
+    sse: SseBuffer,
+    done: bool,
+}
+
+impl FimStreamState {
+    pub(crate) fn new(stops: Vec<String>) -> Self {
+        Self {
+            filter: StopSequenceFilter::new(stops),
+            sse: SseBuffer::default(),
+            done: false,
+        }
+    }
+
+    pub(crate) fn handle(&mut self, chunk: Option<reqwest::Result<Vec<u8>>>) -> Vec<Result<StreamEvent>> {
+        let mut events = Vec::new();
+
+        let bytes = match chunk {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(e)) => {
+                self.done = true;
+                return vec![Err(Error::Stream(e.to_string()))];
+            }
+            None => {
+                self.push_text("", true, &mut events);
+                return events;
+            }
+        };
+
+        for data in self.sse.push(&bytes) {
+            if data == "[DONE]" {
+                self.push_text("", true, &mut events);
+                break;
+            }
+
+            match serde_json::from_str::<Value>(&data) {
+                Ok(json) => {
+                    let choice = &json["choices"][0];
+                    // Mistral streams chat-shaped deltas instead of legacy `text`.
+                    let text = choice["text"]
+                        .as_str()
+                        .or_else(|| choice["delta"]["content"].as_str())
+                        .unwrap_or_default();
+                    self.push_text(text, !choice["finish_reason"].is_null(), &mut events);
+                }
+                Err(e) => events.push(Err(Error::Stream(e.to_string()))),
+            }
+
+            if self.done {
+                break;
+            }
+        }
+
+        events
+    }
+
+    fn push_text(&mut self, text: &str, finished: bool, events: &mut Vec<Result<StreamEvent>>) {
+        if self.done {
+            return;
+        }
+
+        let mut emitted = self.filter.push(text);
+        if finished && !self.filter.is_stopped() {
+            emitted.push_str(&self.filter.finish());
+        }
+        if !emitted.is_empty() {
+            events.push(Ok(StreamEvent::Token { index: 0, text: emitted }));
+        }
+        if finished || self.filter.is_stopped() {
+            self.done = true;
+            events.push(Ok(StreamEvent::Done));
+        }
+    }
+}
+
+fn ensure_fim_supported(provider_type: ProviderType) -> Result<()> {
+    match provider_type {
+        ProviderType::OpenAI | ProviderType::Azure | ProviderType::Custom => Ok(()),
+        _ => Err(Error::UnsupportedProviderType(provider_type.to_string())),
+    }
+}
+
+fn parse_fim_response(json: &Value) -> Result<FimResponse> {
+    let choices = json["choices"]
+        .as_array()
+        .ok_or_else(|| Error::InvalidResponse("Expected 'choices' array".to_string()))?
+        .iter()
+        .enumerate()
+        .map(|(i, choice)| FimChoice {
+            index: choice["index"].as_u64().unwrap_or(i as u64) as u32,
+            // Mistral's fim endpoint answers in chat shape rather than legacy `text`.
+            text: choice["text"]
+                .as_str()
+                .or_else(|| choice["message"]["content"].as_str())
+                .unwrap_or_default()
+                .to_string(),
+            finish_reason: choice["finish_reason"].as_str().map(String::from),
+        })
+        .collect();
+
+    Ok(FimResponse {
+        id: json["id"].as_str().unwrap_or_default().to_string(),
+        model: json["model"].as_str().unwrap_or_default().to_string(),
+        choices,
+        usage: serde_json::from_value(json["usage"].clone()).ok(),
+    })
+}
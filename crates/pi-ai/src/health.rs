@@ -0,0 +1,253 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::CircuitBreakerConfig;
+use crate::error::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderHealth {
+    pub state: CircuitState,
+    pub total_requests: u64,
+    pub total_failures: u64,
+    pub consecutive_failures: u32,
+    // 以下统计只基于最近 window_size 次请求
+    pub error_rate: f64,
+    pub avg_latency_ms: Option<u64>,
+    pub p95_latency_ms: Option<u64>,
+    pub last_error: Option<String>,
+    pub retry_after: Option<Duration>,
+}
+
+impl ProviderHealth {
+    pub fn is_available(&self) -> bool {
+        self.state != CircuitState::Open
+    }
+}
+
+#[derive(Debug)]
+struct Outcome {
+    success: bool,
+    latency: Duration,
+}
+
+#[derive(Debug)]
+struct ProviderStats {
+    state: CircuitState,
+    opened_at: Option<Instant>,
+    probes_in_flight: u32,
+    window: VecDeque<Outcome>,
+    total_requests: u64,
+    total_failures: u64,
+    consecutive_failures: u32,
+    last_error: Option<String>,
+}
+
+impl Default for ProviderStats {
+    fn default() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            opened_at: None,
+            probes_in_flight: 0,
+            window: VecDeque::new(),
+            total_requests: 0,
+            total_failures: 0,
+            consecutive_failures: 0,
+            last_error: None,
+        }
+    }
+}
+
+impl ProviderStats {
+    fn error_rate(&self) -> f64 {
+        if self.window.is_empty() {
+            return 0.0;
+        }
+        let failures = self.window.iter().filter(|o| !o.success).count();
+        failures as f64 / self.window.len() as f64
+    }
+
+    fn retry_after(&self, open_duration: Duration) -> Option<Duration> {
+        let opened_at = self.opened_at?;
+        Some(open_duration.saturating_sub(opened_at.elapsed()))
+    }
+
+    fn open(&mut self) {
+        self.state = CircuitState::Open;
+        self.opened_at = Some(Instant::now());
+        self.probes_in_flight = 0;
+    }
+
+    fn close(&mut self) {
+        self.state = CircuitState::Closed;
+        self.opened_at = None;
+        self.probes_in_flight = 0;
+        self.consecutive_failures = 0;
+        // 恢复后重新开始统计，避免旧的失败立刻再次触发熔断
+        self.window.clear();
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct HealthTracker {
+    config: CircuitBreakerConfig,
+    providers: Mutex<HashMap<String, ProviderStats>>,
+}
+
+impl HealthTracker {
+    pub(crate) fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            providers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // 熔断打开时直接返回错误；冷却期结束后放行有限个探测请求
+    pub(crate) fn check(&self, provider: &str) -> Result<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        let mut providers = self.providers.lock().unwrap();
+        let stats = providers.entry(provider.to_string()).or_default();
+        let open_duration = self.config.open_duration();
+
+        if stats.state == CircuitState::Open {
+            match stats.retry_after(open_duration) {
+                Some(remaining) if !remaining.is_zero() => {
+                    return Err(Error::CircuitOpen {
+                        provider: provider.to_string(),
+                        retry_after: remaining,
+                    });
+                }
+                _ => {
+                    stats.state = CircuitState::HalfOpen;
+                    stats.opened_at = Some(Instant::now());
+                }
+            }
+        }
+
+        if stats.state == CircuitState::HalfOpen {
+            // 探测请求被调用方丢弃时不会回报结果，超过冷却期就允许重新探测
+            if stats.retry_after(open_duration).is_some_and(|r| r.is_zero()) {
+                stats.probes_in_flight = 0;
+                stats.opened_at = Some(Instant::now());
+            }
+            if stats.probes_in_flight >= self.config.half_open_max_requests.max(1) {
+                return Err(Error::CircuitOpen {
+                    provider: provider.to_string(),
+                    retry_after: Duration::ZERO,
+                });
+            }
+            stats.probes_in_flight += 1;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn record(&self, provider: &str, latency: Duration, error: Option<&Error>) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let mut providers = self.providers.lock().unwrap();
+        let stats = providers.entry(provider.to_string()).or_default();
+        let failed = error.is_some_and(is_provider_failure);
+
+        if !failed && stats.state == CircuitState::HalfOpen {
+            stats.close();
+        }
+
+        stats.total_requests += 1;
+        stats.window.push_back(Outcome {
+            success: !failed,
+            latency,
+        });
+        while stats.window.len() > self.config.window_size.max(1) {
+            stats.window.pop_front();
+        }
+
+        if !failed {
+            stats.consecutive_failures = 0;
+            return;
+        }
+
+        stats.total_failures += 1;
+        stats.consecutive_failures += 1;
+        stats.last_error = error.map(|e| e.to_string());
+
+        let should_open = match stats.state {
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+            CircuitState::Closed => {
+                stats.consecutive_failures >= self.config.failure_threshold
+                    || (stats.window.len() >= self.config.min_requests
+                        && stats.error_rate() >= self.config.error_rate_threshold)
+            }
+        };
+        if should_open {
+            stats.open();
+            tracing::warn!(
+                "Circuit opened for provider {} after {} consecutive failures",
+                provider,
+                stats.consecutive_failures
+            );
+        }
+    }
+
+    pub(crate) fn snapshot(&self, provider: &str) -> ProviderHealth {
+        let providers = self.providers.lock().unwrap();
+        let default = ProviderStats::default();
+        let stats = providers.get(provider).unwrap_or(&default);
+
+        let mut latencies = stats.window.iter().map(|o| o.latency).collect::<Vec<_>>();
+        latencies.sort();
+        let avg_latency_ms = (!latencies.is_empty())
+            .then(|| (latencies.iter().sum::<Duration>() / latencies.len() as u32).as_millis() as u64);
+        let p95_latency_ms = latencies
+            .get((latencies.len() * 95).div_ceil(100).saturating_sub(1))
+            .map(|d| d.as_millis() as u64);
+
+        // 冷却期已过但还没有请求进来时，对外显示为 half-open
+        let retry_after = match stats.state {
+            CircuitState::Open => stats.retry_after(self.config.open_duration()),
+            _ => None,
+        };
+        let state = match retry_after {
+            Some(remaining) if remaining.is_zero() => CircuitState::HalfOpen,
+            _ => stats.state,
+        };
+
+        ProviderHealth {
+            state,
+            total_requests: stats.total_requests,
+            total_failures: stats.total_failures,
+            consecutive_failures: stats.consecutive_failures,
+            error_rate: stats.error_rate(),
+            avg_latency_ms,
+            p95_latency_ms,
+            last_error: stats.last_error.clone(),
+            retry_after: retry_after.filter(|d| !d.is_zero()),
+        }
+    }
+}
+
+// 只有说明服务端不可用的错误才计入熔断，参数错误、限流等不算
+fn is_provider_failure(error: &Error) -> bool {
+    match error {
+        Error::Http(e) => !e.is_status() && !e.is_decode(),
+        Error::ApiError(e) => matches!(e.kind, ErrorKind::Overloaded | ErrorKind::ServerError | ErrorKind::Timeout),
+        Error::Stream(_) => true,
+        _ => false,
+    }
+}
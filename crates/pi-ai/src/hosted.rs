@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::error::{Error, Result};
+
+// provider 在服务端执行的工具，结果直接出现在回复里
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostedTool {
+    WebSearch(WebSearchOptions),
+    CodeExecution {
+        // Responses API 的 container id，为空时自动创建
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        container: Option<String>,
+    },
+    FileSearch {
+        vector_store_ids: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_num_results: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WebSearchOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_domains: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blocked_domains: Vec<String>,
+    // low / medium / high，仅 OpenAI 支持
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub search_context_size: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_location: Option<UserLocation>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserLocation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub city: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+// 一次服务端工具调用及其结果；name 统一为 web_search / code_execution / file_search
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostedToolCall {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub input: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<WebSource>,
+    // provider 返回的原始结果，回放历史时原样发回
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct WebSource {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_age: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_content: Option<String>,
+}
+
+impl HostedTool {
+    pub fn web_search() -> Self {
+        HostedTool::WebSearch(WebSearchOptions::default())
+    }
+
+    pub fn code_execution() -> Self {
+        HostedTool::CodeExecution { container: None }
+    }
+
+    pub fn file_search(vector_store_ids: Vec<String>) -> Self {
+        HostedTool::FileSearch {
+            vector_store_ids,
+            max_num_results: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            HostedTool::WebSearch(_) => "web_search",
+            HostedTool::CodeExecution { .. } => "code_execution",
+            HostedTool::FileSearch { .. } => "file_search",
+        }
+    }
+}
+
+impl HostedToolCall {
+    pub fn new(id: impl Into<String>, name: impl Into<String>, input: Value) -> Self {
+        Self {
+            id: id.into(),
+            name: name.into(),
+            input,
+            status: None,
+            sources: Vec::new(),
+            output: None,
+        }
+    }
+
+    // 从 Anthropic 的 *_tool_result 内容里取出搜索结果
+    pub(crate) fn with_output(mut self, output: Value) -> Self {
+        self.sources = output
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|item| item["type"] == "web_search_result")
+            .map(|item| WebSource {
+                url: item["url"].as_str().unwrap_or_default().to_string(),
+                title: item["title"].as_str().map(String::from),
+                page_age: item["page_age"].as_str().map(String::from),
+                encrypted_content: item["encrypted_content"].as_str().map(String::from),
+            })
+            .collect();
+        self.output = Some(output);
+        self
+    }
+}
+
+// Chat Completions 只支持搜索，通过 web_search_options 开启
+pub(crate) fn apply_chat_tools(body: &mut Value, tools: &[HostedTool]) -> Result<()> {
+    for tool in tools {
+        match tool {
+            HostedTool::WebSearch(options) => {
+                let mut value = json!({});
+                if let Some(size) = &options.search_context_size {
+                    value["search_context_size"] = json!(size);
+                }
+                if let Some(location) = &options.user_location {
+                    value["user_location"] = json!({ "type": "approximate", "approximate": location });
+                }
+                body["web_search_options"] = value;
+            }
+            other => {
+                return Err(Error::InvalidRequest(format!(
+                    "Hosted tool {} requires the Responses API",
+                    other.name()
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn responses_tool_value(tool: &HostedTool) -> Value {
+    match tool {
+        HostedTool::WebSearch(options) => {
+            let mut value = json!({ "type": "web_search" });
+            if !options.allowed_domains.is_empty() {
+                value["filters"] = json!({ "allowed_domains": options.allowed_domains });
+            }
+            if let Some(size) = &options.search_context_size {
+                value["search_context_size"] = json!(size);
+            }
+            if let Some(location) = &options.user_location {
+                let mut location = json!(location);
+                location["type"] = json!("approximate");
+                value["user_location"] = location;
+            }
+            value
+        }
+        HostedTool::CodeExecution { container } => json!({
+            "type": "code_interpreter",
+            "container": match container {
+                Some(id) => json!(id),
+                None => json!({ "type": "auto" }),
+            },
+        }),
+        HostedTool::FileSearch {
+            vector_store_ids,
+            max_num_results,
+        } => {
+            let mut value = json!({ "type": "file_search", "vector_store_ids": vector_store_ids });
+            if let Some(max) = max_num_results {
+                value["max_num_results"] = json!(max);
+            }
+            value
+        }
+    }
+}
+
+pub(crate) fn anthropic_tool_value(tool: &HostedTool) -> Option<Value> {
+    match tool {
+        HostedTool::WebSearch(options) => {
+            let mut value = json!({ "type": "web_search_20250305", "name": "web_search" });
+            if let Some(max_uses) = options.max_uses {
+                value["max_uses"] = json!(max_uses);
+            }
+            if !options.allowed_domains.is_empty() {
+                value["allowed_domains"] = json!(options.allowed_domains);
+            }
+            if !options.blocked_domains.is_empty() {
+                value["blocked_domains"] = json!(options.blocked_domains);
+            }
+            if let Some(location) = &options.user_location {
+                let mut location = json!(location);
+                location["type"] = json!("approximate");
+                value["user_location"] = location;
+            }
+            Some(value)
+        }
+        HostedTool::CodeExecution { .. } => Some(json!({ "type": "code_execution_20250522", "name": "code_execution" })),
+        // Anthropic 没有托管的文件检索
+        HostedTool::FileSearch { .. } => None,
+    }
+}
@@ -1,20 +1,52 @@
+pub mod backend;
+pub mod batch;
 pub mod client;
 pub mod config;
+pub mod conversation;
+pub mod embeddings;
 pub mod error;
+pub mod fim;
+pub mod health;
+pub mod hosted;
 pub mod message;
 pub mod models;
+pub mod overflow;
 pub mod provider;
+pub mod responses;
+pub mod secret;
 pub mod stream;
+pub mod telemetry;
 pub mod tool;
+pub mod tool_ids;
 
 #[cfg(test)]
 mod tests;
 
+pub use backend::{EventStream, LlmBackend};
+pub use batch::{BatchItemResult, BatchJob, BatchRequest, BatchStatus};
 pub use client::Client;
-pub use config::{Config, ProviderConfig};
-pub use error::{Error, Result};
-pub use message::{Message, MessageRole, ToolCall, ToolResult};
-pub use models::{ChatCompletionRequest, ChatCompletionResponse, CompletionChoice, ToolDefinition, FunctionDefinition};
+pub use config::{
+    ApiMode, CircuitBreakerConfig, Config, NetworkConfig, ProviderConfig, ProxyConfig, ResponsesConfig, TelemetryConfig,
+};
+pub use conversation::{Citation, ContentBlock, ConversationMessage, MediaSource};
+pub use embeddings::{Embedding, EmbeddingRequest, EmbeddingResponse, EmbeddingUsage};
+pub use error::{Error, ErrorKind, ProviderError, Result};
+pub use fim::{FimRequest, FimResponse, FimTemplate};
+pub use health::{CircuitState, ProviderHealth};
+pub use hosted::{HostedTool, HostedToolCall, UserLocation, WebSearchOptions, WebSource};
+pub use overflow::{
+    DropOldestTurns, OverflowContext, OverflowRecovery, RecoveredResponse, ReduceMaxTokens, Reduction, ReductionStrategy,
+    TruncateToolOutputs,
+};
+pub use message::{Message, MessageRole, ReasoningItem, ToolCall, ToolResult};
+pub use models::{
+    ChatCompletionRequest, ChatCompletionResponse, ChoiceLogprobs, CompletionChoice, FunctionDefinition, ModelListing,
+    TokenLogprob, ToolDefinition, TopLogprob,
+};
 pub use provider::{Provider, ProviderType};
+pub use secret::{SecretSource, SecretString};
 pub use stream::{StreamChunk, StreamEvent};
 pub use tool::{Tool, ToolInputSchema};
+pub use tool_ids::{
+    fill_missing_tool_results, generate_tool_call_id, normalize_tool_call_ids, validate_tool_calls, ToolCallIdFormat,
+};
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+use crate::conversation::Citation;
+use crate::hosted::HostedToolCall;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum MessageRole {
     System,
@@ -38,6 +41,12 @@ pub struct Message {
     pub name: Option<String>,
     pub tool_calls: Option<Vec<ToolCall>>,
     pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<Vec<ReasoningItem>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub citations: Option<Vec<Citation>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hosted_tool_calls: Option<Vec<HostedToolCall>>,
 }
 
 impl Message {
@@ -48,6 +57,9 @@ impl Message {
             name: None,
             tool_calls: None,
             tool_call_id: None,
+            reasoning: None,
+            citations: None,
+            hosted_tool_calls: None,
         }
     }
 
@@ -58,6 +70,9 @@ impl Message {
             name: None,
             tool_calls: None,
             tool_call_id: None,
+            reasoning: None,
+            citations: None,
+            hosted_tool_calls: None,
         }
     }
 
@@ -68,6 +83,9 @@ impl Message {
             name: None,
             tool_calls: None,
             tool_call_id: None,
+            reasoning: None,
+            citations: None,
+            hosted_tool_calls: None,
         }
     }
 
@@ -78,6 +96,9 @@ impl Message {
             name: None,
             tool_calls: None,
             tool_call_id: Some(tool_call_id.into()),
+            reasoning: None,
+            citations: None,
+            hosted_tool_calls: None,
         }
     }
 
@@ -86,6 +107,21 @@ impl Message {
         self
     }
 
+    pub fn with_reasoning(mut self, reasoning: Vec<ReasoningItem>) -> Self {
+        self.reasoning = Some(reasoning);
+        self
+    }
+
+    pub fn with_citations(mut self, citations: Vec<Citation>) -> Self {
+        self.citations = Some(citations);
+        self
+    }
+
+    pub fn with_hosted_tool_calls(mut self, calls: Vec<HostedToolCall>) -> Self {
+        self.hosted_tool_calls = Some(calls);
+        self
+    }
+
     pub fn with_name(mut self, name: impl Into<String>) -> Self {
         self.name = Some(name.into());
         self
@@ -94,6 +130,8 @@ impl Message {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolCall {
+    // 部分本地服务不返回 id
+    #[serde(default)]
     pub id: String,
     #[serde(rename = "type")]
     pub tool_type: String,
@@ -111,3 +149,12 @@ pub struct ToolResult {
     pub tool_call_id: String,
     pub content: String,
 }
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReasoningItem {
+    pub id: Option<String>,
+    pub summary: Vec<String>,
+    pub encrypted_content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
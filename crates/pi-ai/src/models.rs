@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+
+use crate::hosted::HostedTool;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
@@ -15,6 +16,18 @@ pub struct ChatCompletionRequest {
     pub presence_penalty: Option<f32>,
     pub frequency_penalty: Option<f32>,
     pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosted_tools: Vec<HostedTool>,
 }
 
 impl ChatCompletionRequest {
@@ -32,6 +45,12 @@ impl ChatCompletionRequest {
             presence_penalty: None,
             frequency_penalty: None,
             user: None,
+            previous_response_id: None,
+            n: None,
+            logprobs: None,
+            top_logprobs: None,
+            seed: None,
+            hosted_tools: Vec::new(),
         }
     }
 
@@ -59,6 +78,33 @@ impl ChatCompletionRequest {
         self.stream = Some(stream);
         self
     }
+
+    pub fn with_previous_response_id(mut self, id: impl Into<String>) -> Self {
+        self.previous_response_id = Some(id.into());
+        self
+    }
+
+    pub fn with_hosted_tool(mut self, tool: HostedTool) -> Self {
+        self.hosted_tools.push(tool);
+        self
+    }
+
+    pub fn with_n(mut self, n: u32) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    // top_logprobs 为 0 时只返回所选 token 的 logprob
+    pub fn with_logprobs(mut self, top_logprobs: u32) -> Self {
+        self.logprobs = Some(true);
+        self.top_logprobs = (top_logprobs > 0).then_some(top_logprobs);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -108,21 +154,121 @@ pub struct ChatCompletionResponse {
     pub system_fingerprint: Option<String>,
 }
 
+impl ChatCompletionResponse {
+    // 按平均 logprob 选出最可信的候选；没有 logprobs 时退回第一个
+    pub fn best_choice(&self) -> Option<&CompletionChoice> {
+        self.best_choice_by(|choice| choice.logprobs.as_ref().and_then(ChoiceLogprobs::mean_logprob))
+    }
+
+    pub fn best_choice_by<F>(&self, score: F) -> Option<&CompletionChoice>
+    where
+        F: Fn(&CompletionChoice) -> Option<f64>,
+    {
+        self.choices
+            .iter()
+            .filter_map(|choice| score(choice).map(|s| (choice, s)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(choice, _)| choice)
+            .or_else(|| self.choices.first())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompletionChoice {
     pub index: u32,
     pub message: super::Message,
     pub finish_reason: Option<String>,
-    pub logprobs: Option<serde_json::Value>,
+    #[serde(default)]
+    pub logprobs: Option<ChoiceLogprobs>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChoiceLogprobs {
+    #[serde(default)]
+    pub content: Option<Vec<TokenLogprob>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    #[serde(default)]
+    pub bytes: Option<Vec<u8>>,
+}
+
+impl TokenLogprob {
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
+}
+
+impl ChoiceLogprobs {
+    pub fn tokens(&self) -> &[TokenLogprob] {
+        self.content.as_deref().unwrap_or_default()
+    }
+
+    pub fn total_logprob(&self) -> f64 {
+        self.tokens().iter().map(|t| t.logprob).sum()
+    }
+
+    pub fn mean_logprob(&self) -> Option<f64> {
+        let tokens = self.tokens();
+        (!tokens.is_empty()).then(|| self.total_logprob() / tokens.len() as f64)
+    }
+
+    // 几何平均概率，0..=1，用作置信度
+    pub fn confidence(&self) -> Option<f64> {
+        self.mean_logprob().map(f64::exp)
+    }
+
+    pub fn perplexity(&self) -> Option<f64> {
+        self.mean_logprob().map(|mean| (-mean).exp())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelListing {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub context_length: Option<u32>,
+    pub max_output_tokens: Option<u32>,
+    // Unix 时间戳（秒）
+    pub created: Option<u64>,
+    pub owned_by: Option<String>,
+}
+
+impl ModelListing {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.id)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolDefinition {
     #[serde(rename = "type")]
@@ -0,0 +1,363 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::client::Client;
+use crate::error::{ProviderError, Result};
+use crate::message::{Message, MessageRole};
+use crate::models::{ChatCompletionRequest, ChatCompletionResponse};
+
+const TRUNCATION_MARKER: &str = "characters truncated ...]";
+
+#[derive(Debug, Clone)]
+pub enum Reduction {
+    DroppedMessages(Vec<Message>),
+    TruncatedToolOutputs { count: usize, chars_removed: usize },
+    ReducedMaxTokens { from: Option<u32>, to: u32 },
+    Other(String),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OverflowContext {
+    pub context_limit: Option<u32>,
+    // provider 报告的请求 token 数（prompt + completion），拿不到时为 None
+    pub requested_tokens: Option<u32>,
+    pub estimated_prompt_tokens: u32,
+    pub attempt: u32,
+}
+
+impl OverflowContext {
+    // 需要削减的 token 数，未知时返回 None
+    pub fn excess_tokens(&self, request: &ChatCompletionRequest) -> Option<u32> {
+        let limit = self.context_limit?;
+        let requested = self
+            .requested_tokens
+            .unwrap_or(self.estimated_prompt_tokens + request.max_tokens.unwrap_or(0));
+        Some(requested.saturating_sub(limit))
+    }
+}
+
+pub trait ReductionStrategy: Send + Sync {
+    fn name(&self) -> &str;
+
+    // 无法继续削减时返回 None，交给下一个策略
+    fn reduce(&self, request: &mut ChatCompletionRequest, context: &OverflowContext) -> Option<Reduction>;
+}
+
+#[derive(Debug, Clone)]
+pub struct TruncateToolOutputs {
+    pub max_chars: usize,
+}
+
+impl Default for TruncateToolOutputs {
+    fn default() -> Self {
+        Self { max_chars: 8000 }
+    }
+}
+
+impl ReductionStrategy for TruncateToolOutputs {
+    fn name(&self) -> &str {
+        "truncate_tool_outputs"
+    }
+
+    fn reduce(&self, request: &mut ChatCompletionRequest, _context: &OverflowContext) -> Option<Reduction> {
+        let mut count = 0;
+        let mut chars_removed = 0;
+
+        for message in request.messages.iter_mut().filter(|m| m.role == MessageRole::Tool) {
+            if let Some(removed) = truncate_middle(&mut message.content, self.max_chars) {
+                count += 1;
+                chars_removed += removed;
+            }
+        }
+
+        (count > 0).then_some(Reduction::TruncatedToolOutputs { count, chars_removed })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DropOldestTurns {
+    // 至少保留最近的几轮对话（以 user 消息开始算一轮）
+    pub keep_recent_turns: usize,
+}
+
+impl Default for DropOldestTurns {
+    fn default() -> Self {
+        Self { keep_recent_turns: 1 }
+    }
+}
+
+impl ReductionStrategy for DropOldestTurns {
+    fn name(&self) -> &str {
+        "drop_oldest_turns"
+    }
+
+    fn reduce(&self, request: &mut ChatCompletionRequest, context: &OverflowContext) -> Option<Reduction> {
+        let turn_starts = request
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.role == MessageRole::User)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let droppable_turns = turn_starts.len().saturating_sub(self.keep_recent_turns.max(1));
+        if droppable_turns == 0 {
+            return None;
+        }
+
+        // 知道超出多少时按需丢弃，否则每次丢一轮
+        let target = context.excess_tokens(request);
+        let mut turns = 0;
+        let mut freed = 0;
+        while turns < droppable_turns {
+            let start = turn_starts[turns];
+            let end = turn_starts[turns + 1];
+            freed += request.messages[start..end]
+                .iter()
+                .filter(|m| m.role != MessageRole::System)
+                .map(estimate_message_tokens)
+                .sum::<u32>();
+            turns += 1;
+            match target {
+                Some(target) if freed < target => continue,
+                _ => break,
+            }
+        }
+
+        // 一轮里的 tool 调用和结果整体丢弃，system 消息始终保留
+        let cutoff = turn_starts[turns];
+        let mut dropped = Vec::new();
+        let mut kept = Vec::new();
+        for (i, message) in request.messages.drain(..).enumerate() {
+            if i >= turn_starts[0] && i < cutoff && message.role != MessageRole::System {
+                dropped.push(message);
+            } else {
+                kept.push(message);
+            }
+        }
+        request.messages = kept;
+
+        Some(Reduction::DroppedMessages(dropped))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReduceMaxTokens {
+    pub min_tokens: u32,
+}
+
+impl Default for ReduceMaxTokens {
+    fn default() -> Self {
+        Self { min_tokens: 256 }
+    }
+}
+
+impl ReductionStrategy for ReduceMaxTokens {
+    fn name(&self) -> &str {
+        "reduce_max_tokens"
+    }
+
+    fn reduce(&self, request: &mut ChatCompletionRequest, context: &OverflowContext) -> Option<Reduction> {
+        let from = request.max_tokens;
+        let available = context
+            .context_limit
+            .map(|limit| limit.saturating_sub(context.estimated_prompt_tokens));
+
+        let to = match (from, available) {
+            (Some(current), Some(available)) => current.min(available),
+            (Some(current), None) => current / 2,
+            (None, Some(available)) => available,
+            (None, None) => return None,
+        }
+        .max(self.min_tokens);
+
+        if from.is_some_and(|current| to >= current) {
+            return None;
+        }
+        request.max_tokens = Some(to);
+        Some(Reduction::ReducedMaxTokens { from, to })
+    }
+}
+
+#[derive(Clone)]
+pub struct OverflowRecovery {
+    strategies: Vec<Arc<dyn ReductionStrategy>>,
+    context_limits: HashMap<String, u32>,
+    max_attempts: u32,
+}
+
+impl Default for OverflowRecovery {
+    fn default() -> Self {
+        Self {
+            strategies: vec![
+                Arc::new(TruncateToolOutputs::default()),
+                Arc::new(DropOldestTurns::default()),
+                Arc::new(ReduceMaxTokens::default()),
+            ],
+            context_limits: HashMap::new(),
+            max_attempts: 4,
+        }
+    }
+}
+
+impl OverflowRecovery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_strategies(mut self, strategies: Vec<Arc<dyn ReductionStrategy>>) -> Self {
+        self.strategies = strategies;
+        self
+    }
+
+    pub fn with_strategy(mut self, strategy: Arc<dyn ReductionStrategy>) -> Self {
+        self.strategies.push(strategy);
+        self
+    }
+
+    pub fn with_context_limit(mut self, model: impl Into<String>, limit: u32) -> Self {
+        self.context_limits.insert(model.into(), limit);
+        self
+    }
+
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecoveredResponse {
+    pub response: ChatCompletionResponse,
+    // 实际发送成功的请求，调用方可以用它替换自己的历史
+    pub request: ChatCompletionRequest,
+    pub reductions: Vec<Reduction>,
+}
+
+impl Client {
+    pub async fn chat_with_recovery(
+        &self,
+        provider_name: &str,
+        mut request: ChatCompletionRequest,
+        recovery: &OverflowRecovery,
+    ) -> Result<RecoveredResponse> {
+        let mut reductions = Vec::new();
+        let mut listed_limit: Option<Option<u32>> = None;
+        let mut attempt = 0;
+
+        loop {
+            let error = match self.chat(provider_name, request.clone()).await {
+                Ok(response) => {
+                    return Ok(RecoveredResponse {
+                        response,
+                        request,
+                        reductions,
+                    })
+                }
+                Err(e) if e.is_context_overflow() && attempt < recovery.max_attempts => e,
+                Err(e) => return Err(e),
+            };
+            attempt += 1;
+
+            let (reported_limit, requested_tokens) = error.provider_error().map(parse_token_counts).unwrap_or_default();
+            let mut context_limit = recovery.context_limits.get(&request.model).copied().or(reported_limit);
+            if context_limit.is_none() {
+                if listed_limit.is_none() {
+                    listed_limit = Some(self.listed_context_limit(provider_name, &request.model).await);
+                }
+                context_limit = listed_limit.flatten();
+            }
+
+            let context = OverflowContext {
+                context_limit,
+                requested_tokens,
+                estimated_prompt_tokens: request.messages.iter().map(estimate_message_tokens).sum(),
+                attempt,
+            };
+
+            let reduction = recovery
+                .strategies
+                .iter()
+                .find_map(|strategy| strategy.reduce(&mut request, &context).map(|r| (strategy.name(), r)));
+            match reduction {
+                Some((name, reduction)) => {
+                    tracing::info!("Context overflow on attempt {}, applied {}: {:?}", attempt, name, reduction);
+                    reductions.push(reduction);
+                }
+                None => return Err(error),
+            }
+        }
+    }
+
+    async fn listed_context_limit(&self, provider_name: &str, model: &str) -> Option<u32> {
+        let models = self.list_models(provider_name).await.ok()?;
+        models.into_iter().find(|m| m.id == model)?.context_length
+    }
+}
+
+pub fn estimate_message_tokens(message: &Message) -> u32 {
+    let arguments = message
+        .tool_calls
+        .iter()
+        .flatten()
+        .map(|c| c.function.name.len() + c.function.arguments.len())
+        .sum::<usize>();
+    ((message.content.len() + arguments) / 4) as u32 + 4
+}
+
+// 解析 provider 错误信息里的上下文上限和实际请求的 token 数
+pub(crate) fn parse_token_counts(error: &ProviderError) -> (Option<u32>, Option<u32>) {
+    let message = error.message.to_lowercase();
+
+    // OpenAI: "maximum context length is 8192 tokens. However, you requested 9000 tokens"
+    let limit = number_after(&message, "maximum context length is ");
+    let requested = number_after(&message, "you requested ").or_else(|| number_after(&message, "resulted in "));
+    if limit.is_some() {
+        return (limit, requested);
+    }
+
+    // Anthropic: "prompt is too long: 210000 tokens > 200000 maximum"
+    if let Some((left, right)) = message.split_once(" tokens > ") {
+        let requested = left.rsplit(|c: char| !c.is_ascii_digit()).next().and_then(|n| n.parse().ok());
+        let limit = leading_number(right);
+        return (limit, requested);
+    }
+
+    (None, None)
+}
+
+fn number_after(text: &str, prefix: &str) -> Option<u32> {
+    let start = text.find(prefix)? + prefix.len();
+    leading_number(&text[start..])
+}
+
+fn leading_number(text: &str) -> Option<u32> {
+    let digits = text
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == ',')
+        .filter(char::is_ascii_digit)
+        .collect::<String>();
+    digits.parse().ok()
+}
+
+// 保留头尾，中间替换为说明；返回删除的字符数
+pub fn truncate_middle(content: &mut String, max_chars: usize) -> Option<usize> {
+    let total = content.chars().count();
+    if total <= max_chars || content.contains(TRUNCATION_MARKER) {
+        return None;
+    }
+
+    let removed = total - max_chars;
+    let head = max_chars / 2;
+    let tail = max_chars - head;
+    let head_end = content.char_indices().nth(head).map(|(i, _)| i).unwrap_or(content.len());
+    let tail_start = content.char_indices().nth(total - tail).map(|(i, _)| i).unwrap_or(content.len());
+
+    *content = format!(
+        "{}\n[... {} {}\n{}",
+        &content[..head_end],
+        removed,
+        TRUNCATION_MARKER,
+        &content[tail_start..]
+    );
+    Some(removed)
+}
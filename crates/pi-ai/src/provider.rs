@@ -1,4 +1,3 @@
-use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -61,23 +60,23 @@ impl Provider {
         
         match self.provider_type {
             ProviderType::OpenAI => {
-                headers.insert("Authorization".to_string(), format!("Bearer {}", self.config.api_key));
+                headers.insert("Authorization".to_string(), format!("Bearer {}", self.config.api_key.expose_secret()));
                 if let Some(org) = &self.config.organization {
                     headers.insert("OpenAI-Organization".to_string(), org.clone());
                 }
             }
             ProviderType::Anthropic => {
-                headers.insert("x-api-key".to_string(), self.config.api_key.clone());
+                headers.insert("x-api-key".to_string(), self.config.api_key.expose_secret().to_string());
                 headers.insert("anthropic-version".to_string(), "2023-06-01".to_string());
             }
             ProviderType::Google => {
-                headers.insert("x-goog-api-key".to_string(), self.config.api_key.clone());
+                headers.insert("x-goog-api-key".to_string(), self.config.api_key.expose_secret().to_string());
             }
             ProviderType::Azure => {
-                headers.insert("api-key".to_string(), self.config.api_key.clone());
+                headers.insert("api-key".to_string(), self.config.api_key.expose_secret().to_string());
             }
             ProviderType::Custom => {
-                headers.insert("Authorization".to_string(), format!("Bearer {}", self.config.api_key));
+                headers.insert("Authorization".to_string(), format!("Bearer {}", self.config.api_key.expose_secret()));
             }
         }
 
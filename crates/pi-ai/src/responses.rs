@@ -0,0 +1,505 @@
+use std::pin::Pin;
+
+use futures::stream::{self, Stream, StreamExt};
+use serde_json::{json, Value};
+
+use crate::client::{check_response, Client};
+use crate::config::ResponsesConfig;
+use crate::conversation::Citation;
+use crate::error::{Error, Result};
+use crate::hosted::{self, HostedTool, HostedToolCall, WebSource};
+use crate::message::{FunctionCall, Message, MessageRole, ReasoningItem, ToolCall};
+use crate::models::{ChatCompletionRequest, ChatCompletionResponse, CompletionChoice, ToolChoice, Usage};
+use crate::provider::Provider;
+use crate::stream::{SseBuffer, StreamEvent};
+
+impl Client {
+    pub(crate) async fn responses_chat(
+        &self,
+        provider: &Provider,
+        request: &ChatCompletionRequest,
+    ) -> Result<ChatCompletionResponse> {
+        let body = build_responses_body(request, &provider.config.responses, false)?;
+
+        let response = self
+            .http_client(provider)
+            .post(provider.get_endpoint("responses"))
+            .headers(self.build_headers(provider)?)
+            .json(&body)
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        let json: Value = check_response(provider, response).await?.json().await.map_err(Error::Http)?;
+
+        parse_responses_output(&json)
+    }
+
+    pub(crate) async fn responses_chat_stream(
+        &self,
+        provider: &Provider,
+        request: &ChatCompletionRequest,
+    ) -> Result<Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>> {
+        let body = build_responses_body(request, &provider.config.responses, true)?;
+
+        let response = self
+            .http_client(provider)
+            .post(provider.get_endpoint("responses"))
+            .headers(self.build_headers(provider)?)
+            .json(&body)
+            .send()
+            .await
+            .map_err(Error::Http)?;
+        let response = check_response(provider, response).await?;
+
+        let stream = response
+            .bytes_stream()
+            .scan(ResponsesStreamState::default(), |state, chunk| {
+                let events = match chunk {
+                    Ok(bytes) => state.handle(&bytes),
+                    Err(e) => vec![Err(Error::Stream(e.to_string()))],
+                };
+                futures::future::ready(Some(events))
+            })
+            .flat_map(stream::iter);
+
+        Ok(Box::pin(stream))
+    }
+}
+
+pub(crate) fn build_responses_body(
+    request: &ChatCompletionRequest,
+    config: &ResponsesConfig,
+    stream: bool,
+) -> Result<Value> {
+    let mut input = Vec::new();
+
+    // When chaining, the server already holds everything up to the previous response,
+    // so only the items after the last assistant turn are sent.
+    let start = match request.previous_response_id {
+        Some(_) => request
+            .messages
+            .iter()
+            .rposition(|m| m.role == MessageRole::Assistant)
+            .map_or(0, |i| i + 1),
+        None => 0,
+    };
+
+    for message in &request.messages[start..] {
+        match message.role {
+            MessageRole::System => input.push(json!({
+                "type": "message",
+                "role": "system",
+                "content": message.content,
+            })),
+            MessageRole::User => input.push(json!({
+                "type": "message",
+                "role": "user",
+                "content": [{ "type": "input_text", "text": message.content }],
+            })),
+            MessageRole::Assistant => {
+                for reasoning in message.reasoning.iter().flatten() {
+                    let mut item = json!({
+                        "type": "reasoning",
+                        "summary": reasoning
+                            .summary
+                            .iter()
+                            .map(|text| json!({ "type": "summary_text", "text": text }))
+                            .collect::<Vec<_>>(),
+                    });
+                    if let Some(id) = &reasoning.id {
+                        item["id"] = json!(id);
+                    }
+                    if let Some(encrypted) = &reasoning.encrypted_content {
+                        item["encrypted_content"] = json!(encrypted);
+                    }
+                    input.push(item);
+                }
+                if !message.content.is_empty() {
+                    input.push(json!({
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [{ "type": "output_text", "text": message.content }],
+                    }));
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    input.push(json!({
+                        "type": "function_call",
+                        "call_id": call.id,
+                        "name": call.function.name,
+                        "arguments": call.function.arguments,
+                    }));
+                }
+            }
+            MessageRole::Tool => input.push(json!({
+                "type": "function_call_output",
+                "call_id": message.tool_call_id,
+                "output": message.content,
+            })),
+        }
+    }
+
+    let mut body = json!({
+        "model": request.model,
+        "input": input,
+        "stream": stream,
+        "store": config.store || request.previous_response_id.is_some(),
+    });
+
+    if let Some(previous) = &request.previous_response_id {
+        body["previous_response_id"] = json!(previous);
+    }
+    if let Some(temperature) = request.temperature {
+        body["temperature"] = json!(temperature);
+    }
+    if let Some(top_p) = request.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(max_tokens) = request.max_tokens {
+        body["max_output_tokens"] = json!(max_tokens);
+    }
+    if let Some(user) = &request.user {
+        body["user"] = json!(user);
+    }
+
+    let mut tools = request
+        .tools
+        .iter()
+        .flatten()
+        .map(|t| {
+            json!({
+                "type": "function",
+                "name": t.function.name,
+                "description": t.function.description,
+                "parameters": t.function.parameters,
+            })
+        })
+        .collect::<Vec<_>>();
+    tools.extend(request.hosted_tools.iter().map(hosted::responses_tool_value));
+    if !tools.is_empty() {
+        body["tools"] = Value::Array(tools);
+    }
+
+    if let Some(tool_choice) = &request.tool_choice {
+        body["tool_choice"] = match tool_choice {
+            ToolChoice::Function { function } => json!({ "type": "function", "name": function.name }),
+            other => serde_json::to_value(other)?,
+        };
+    }
+
+    if config.reasoning_effort.is_some() || config.reasoning_summary.is_some() {
+        let mut reasoning = json!({});
+        if let Some(effort) = &config.reasoning_effort {
+            reasoning["effort"] = json!(effort);
+        }
+        if let Some(summary) = &config.reasoning_summary {
+            reasoning["summary"] = json!(summary);
+        }
+        body["reasoning"] = reasoning;
+    }
+    let mut include = Vec::new();
+    if config.include_encrypted_reasoning {
+        include.push("reasoning.encrypted_content");
+    }
+    // 默认只返回引用，不返回搜索到的全部来源
+    if request.hosted_tools.iter().any(|t| matches!(t, HostedTool::WebSearch(_))) {
+        include.push("web_search_call.action.sources");
+    }
+    if !include.is_empty() {
+        body["include"] = json!(include);
+    }
+
+    Ok(body)
+}
+
+pub(crate) fn parse_responses_output(json: &Value) -> Result<ChatCompletionResponse> {
+    let output = json["output"]
+        .as_array()
+        .ok_or_else(|| Error::InvalidResponse("Expected 'output' array".to_string()))?;
+
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+    let mut reasoning = Vec::new();
+    let mut citations = Vec::new();
+    let mut hosted_calls = Vec::new();
+
+    for item in output {
+        match item["type"].as_str() {
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten() {
+                    if part["type"] == "output_text" {
+                        // annotation 的下标相对于单个 part，拼接后需要平移
+                        let offset = text.chars().count() as u64;
+                        citations.extend(
+                            part["annotations"]
+                                .as_array()
+                                .into_iter()
+                                .flatten()
+                                .map(citation_from_annotation)
+                                .map(|c| Citation {
+                                    start_index: c.start_index.map(|i| i + offset),
+                                    end_index: c.end_index.map(|i| i + offset),
+                                    ..c
+                                }),
+                        );
+                        text.push_str(part["text"].as_str().unwrap_or_default());
+                    }
+                }
+            }
+            Some("function_call") => tool_calls.push(function_call_from_item(item)),
+            Some("reasoning") => reasoning.push(reasoning_from_item(item)),
+            _ => hosted_calls.extend(hosted_call_from_item(item)),
+        }
+    }
+
+    let finish_reason = finish_reason(json);
+
+    let mut message = Message::assistant(text);
+    if !tool_calls.is_empty() {
+        message = message.with_tool_calls(tool_calls);
+    }
+    if !reasoning.is_empty() {
+        message = message.with_reasoning(reasoning);
+    }
+    if !citations.is_empty() {
+        message = message.with_citations(citations);
+    }
+    if !hosted_calls.is_empty() {
+        message = message.with_hosted_tool_calls(hosted_calls);
+    }
+
+    Ok(ChatCompletionResponse {
+        id: json["id"].as_str().unwrap_or_default().to_string(),
+        object: json["object"].as_str().unwrap_or("response").to_string(),
+        created: json["created_at"].as_u64().unwrap_or(0),
+        model: json["model"].as_str().unwrap_or_default().to_string(),
+        choices: vec![CompletionChoice {
+            index: 0,
+            message,
+            finish_reason,
+            logprobs: None,
+        }],
+        usage: Usage {
+            prompt_tokens: json["usage"]["input_tokens"].as_u64().unwrap_or(0) as u32,
+            completion_tokens: json["usage"]["output_tokens"].as_u64().unwrap_or(0) as u32,
+            total_tokens: json["usage"]["total_tokens"].as_u64().unwrap_or(0) as u32,
+        },
+        system_fingerprint: None,
+    })
+}
+
+// Maps the response status onto chat-completions finish reasons.
+fn finish_reason(response: &Value) -> Option<String> {
+    let has_tool_calls = response["output"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|item| item["type"] == "function_call");
+    match response["status"].as_str() {
+        Some("incomplete") => match response["incomplete_details"]["reason"].as_str() {
+            Some("max_output_tokens") => Some("length".to_string()),
+            Some(reason) => Some(reason.to_string()),
+            None => None,
+        },
+        Some("completed") if has_tool_calls => Some("tool_calls".to_string()),
+        Some("completed") => Some("stop".to_string()),
+        other => other.map(String::from),
+    }
+}
+
+fn function_call_from_item(item: &Value) -> ToolCall {
+    ToolCall {
+        id: item["call_id"].as_str().unwrap_or_default().to_string(),
+        tool_type: "function".to_string(),
+        function: FunctionCall {
+            name: item["name"].as_str().unwrap_or_default().to_string(),
+            arguments: item["arguments"].as_str().unwrap_or_default().to_string(),
+        },
+    }
+}
+
+// web_search_call、file_search_call、code_interpreter_call
+fn hosted_call_from_item(item: &Value) -> Option<HostedToolCall> {
+    let id = item["id"].as_str().unwrap_or_default();
+    let mut call = match item["type"].as_str()? {
+        "web_search_call" => {
+            let mut call = HostedToolCall::new(id, "web_search", item["action"].clone());
+            call.sources = item["action"]["sources"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|source| {
+                    Some(WebSource {
+                        url: source["url"].as_str()?.to_string(),
+                        title: source["title"].as_str().map(String::from),
+                        ..Default::default()
+                    })
+                })
+                .collect();
+            call
+        }
+        "file_search_call" => {
+            let mut call = HostedToolCall::new(id, "file_search", json!({ "queries": item["queries"] }));
+            call.output = Some(item["results"].clone()).filter(|r| !r.is_null());
+            call
+        }
+        "code_interpreter_call" => {
+            let mut call = HostedToolCall::new(
+                id,
+                "code_execution",
+                json!({ "code": item["code"], "container_id": item["container_id"] }),
+            );
+            call.output = Some(item["outputs"].clone()).filter(|o| !o.is_null());
+            call
+        }
+        _ => return None,
+    };
+    call.status = item["status"].as_str().map(String::from);
+    Some(call)
+}
+
+fn citation_from_annotation(annotation: &Value) -> Citation {
+    let kind = annotation["type"].as_str().unwrap_or_default().to_string();
+    match kind.as_str() {
+        // file_citation 只给出位置 index
+        "file_citation" => Citation {
+            title: annotation["filename"].as_str().map(String::from),
+            file_id: annotation["file_id"].as_str().map(String::from),
+            start_index: annotation["index"].as_u64(),
+            end_index: annotation["index"].as_u64(),
+            kind,
+            ..Default::default()
+        },
+        _ => Citation {
+            url: annotation["url"].as_str().map(String::from),
+            title: annotation["title"].as_str().map(String::from),
+            file_id: annotation["file_id"].as_str().map(String::from),
+            start_index: annotation["start_index"].as_u64(),
+            end_index: annotation["end_index"].as_u64(),
+            kind,
+            ..Default::default()
+        },
+    }
+}
+
+fn reasoning_from_item(item: &Value) -> ReasoningItem {
+    ReasoningItem {
+        id: item["id"].as_str().map(String::from),
+        summary: item["summary"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|s| s["text"].as_str().map(String::from))
+            .collect(),
+        encrypted_content: item["encrypted_content"].as_str().map(String::from),
+        signature: None,
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct ResponsesStreamState {
+    sse: SseBuffer,
+}
+
+impl ResponsesStreamState {
+    pub(crate) fn handle(&mut self, bytes: &[u8]) -> Vec<Result<StreamEvent>> {
+        let mut events = Vec::new();
+
+        for data in self.sse.push(bytes) {
+            let event: Value = match serde_json::from_str(&data) {
+                Ok(event) => event,
+                Err(e) => {
+                    events.push(Err(Error::Stream(e.to_string())));
+                    continue;
+                }
+            };
+
+            match event["type"].as_str().unwrap_or_default() {
+                "response.created" => {
+                    if let Some(id) = event["response"]["id"].as_str() {
+                        events.push(Ok(StreamEvent::ResponseId(id.to_string())));
+                    }
+                }
+                "response.output_text.delta" => {
+                    let delta = event["delta"].as_str().unwrap_or_default();
+                    if !delta.is_empty() {
+                        events.push(Ok(StreamEvent::Token {
+                            index: 0,
+                            text: delta.to_string(),
+                        }));
+                    }
+                }
+                "response.output_item.added" if event["item"]["type"] == "function_call" => {
+                    let item = &event["item"];
+                    events.push(Ok(StreamEvent::ToolCallDelta {
+                        choice: 0,
+                        index: event["output_index"].as_u64().unwrap_or_default() as u32,
+                        id: item["call_id"].as_str().map(String::from),
+                        name: item["name"].as_str().map(String::from),
+                        arguments: item["arguments"].as_str().unwrap_or_default().to_string(),
+                    }));
+                }
+                "response.function_call_arguments.delta" => {
+                    events.push(Ok(StreamEvent::ToolCallDelta {
+                        choice: 0,
+                        index: event["output_index"].as_u64().unwrap_or_default() as u32,
+                        id: None,
+                        name: None,
+                        arguments: event["delta"].as_str().unwrap_or_default().to_string(),
+                    }));
+                }
+                "response.output_item.done" => {
+                    let item = &event["item"];
+                    if item["type"] == "function_call" {
+                        let call = function_call_from_item(item);
+                        events.push(Ok(StreamEvent::ToolCall {
+                            id: call.id,
+                            name: call.function.name,
+                            arguments: call.function.arguments,
+                        }));
+                    } else if item["type"] == "reasoning" {
+                        events.push(Ok(StreamEvent::Reasoning(reasoning_from_item(item))));
+                    } else if let Some(call) = hosted_call_from_item(item) {
+                        events.push(Ok(StreamEvent::HostedToolCall(call)));
+                    }
+                }
+                "response.output_text.annotation.added" => {
+                    events.push(Ok(StreamEvent::Citation(citation_from_annotation(&event["annotation"]))));
+                }
+                "response.completed" | "response.incomplete" => {
+                    let usage = &event["response"]["usage"];
+                    if usage.is_object() {
+                        events.push(Ok(StreamEvent::Usage(Usage {
+                            prompt_tokens: usage["input_tokens"].as_u64().unwrap_or(0) as u32,
+                            completion_tokens: usage["output_tokens"].as_u64().unwrap_or(0) as u32,
+                            total_tokens: usage["total_tokens"].as_u64().unwrap_or(0) as u32,
+                        })));
+                    }
+                    // incomplete 没给原因时也要让调用方知道输出被截断了
+                    let reason = finish_reason(&event["response"]).or_else(|| {
+                        (event["type"] == "response.incomplete").then(|| "incomplete".to_string())
+                    });
+                    if let Some(reason) = reason {
+                        events.push(Ok(StreamEvent::FinishReason(reason)));
+                    }
+                    events.push(Ok(StreamEvent::Done));
+                }
+                "response.failed" => {
+                    let error = &event["response"]["error"];
+                    let message = error["message"].as_str().unwrap_or("Response failed");
+                    let message = match error["code"].as_str() {
+                        Some(code) => format!("{}: {}", code, message),
+                        None => message.to_string(),
+                    };
+                    events.push(Ok(StreamEvent::Error(message)));
+                }
+                "error" => {
+                    let message = event["message"].as_str().unwrap_or("Unknown error").to_string();
+                    events.push(Ok(StreamEvent::Error(message)));
+                }
+                _ => {}
+            }
+        }
+
+        events
+    }
+}
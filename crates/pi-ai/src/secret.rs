@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+use crate::error::{Error, Result};
+
+const REDACTED: &str = "[REDACTED]";
+
+// 密钥的来源；序列化时只写来源，不写明文
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretSource {
+    Inline,
+    Env(String),
+    File(PathBuf),
+    Keyring { service: String, user: String },
+    // 通过 shell 执行，取 stdout，例如 "pass show openai"
+    Command(String),
+}
+
+// Debug / Display 不会包含明文，drop 时清零。直接写入的密钥在序列化时会报错，
+// 需要写回配置文件的字段用 serialize_exposed / serialize_exposed_map
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString {
+    value: String,
+    source: Option<SecretSource>,
+}
+
+impl SecretString {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self {
+            value: value.into(),
+            source: None,
+        }
+    }
+
+    pub fn from_env(name: &str) -> Result<Self> {
+        Self::resolve(SecretSource::Env(name.to_string()))
+    }
+
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::resolve(SecretSource::File(path.into()))
+    }
+
+    pub fn from_keyring(service: &str, user: &str) -> Result<Self> {
+        Self::resolve(SecretSource::Keyring {
+            service: service.to_string(),
+            user: user.to_string(),
+        })
+    }
+
+    pub fn from_command(command: &str) -> Result<Self> {
+        Self::resolve(SecretSource::Command(command.to_string()))
+    }
+
+    pub fn resolve(source: SecretSource) -> Result<Self> {
+        let value = match &source {
+            SecretSource::Inline => return Ok(Self::default()),
+            SecretSource::Env(name) => std::env::var(name)
+                .map_err(|_| Error::InvalidConfig(format!("Environment variable {} is not set", name)))?,
+            SecretSource::File(path) => std::fs::read_to_string(path)?.trim_end().to_string(),
+            SecretSource::Keyring { service, user } => read_keyring(service, user)?,
+            SecretSource::Command(command) => run_command(command)?,
+        };
+
+        Ok(Self {
+            value,
+            source: Some(source),
+        })
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.value
+    }
+
+    pub fn source(&self) -> &SecretSource {
+        self.source.as_ref().unwrap_or(&SecretSource::Inline)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.is_empty()
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "SecretString({:?})", source),
+            None => write!(f, "SecretString({})", REDACTED),
+        }
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match &self.source {
+            Some(source) => source.serialize(serializer),
+            None if self.value.is_empty() => serializer.serialize_str(""),
+            // 写成占位符之后就再也读不回来了，宁可失败
+            None => Err(serde::ser::Error::custom(
+                "refusing to serialize an inline secret; use secret::serialize_exposed or a secret source",
+            )),
+        }
+    }
+}
+
+// 有来源的密钥仍然只写来源，直接写入的密钥写明文。用于
+// #[serde(serialize_with = "pi_ai::secret::serialize_exposed")]
+pub fn serialize_exposed<S: Serializer>(secret: &SecretString, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match &secret.source {
+        Some(source) => source.serialize(serializer),
+        None => serializer.serialize_str(&secret.value),
+    }
+}
+
+pub fn serialize_exposed_map<S: Serializer>(
+    secrets: &HashMap<String, SecretString>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    struct Exposed<'a>(&'a SecretString);
+
+    impl Serialize for Exposed<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+            serialize_exposed(self.0, serializer)
+        }
+    }
+
+    serializer.collect_map(secrets.iter().map(|(name, secret)| (name, Exposed(secret))))
+}
+
+// 字符串按明文读取（兼容旧配置），对象按来源解析
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Plain(String),
+            Source(SecretSource),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Plain(value) if value == REDACTED => Err(D::Error::custom(
+                "secret was redacted when this config was written; provide the value or a source",
+            )),
+            Repr::Plain(value) => Ok(Self::new(value)),
+            Repr::Source(source) => Self::resolve(source).map_err(D::Error::custom),
+        }
+    }
+}
+
+fn run_command(command: &str) -> Result<String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()?
+    } else {
+        Command::new("sh").args(["-c", command]).output()?
+    };
+
+    if !output.status.success() {
+        return Err(Error::InvalidConfig(format!(
+            "Secret command exited with {}",
+            output.status
+        )));
+    }
+    let mut stdout = String::from_utf8(output.stdout)
+        .map_err(|_| Error::InvalidConfig("Secret command produced invalid UTF-8".to_string()))?;
+    let value = stdout.trim_end().to_string();
+    stdout.zeroize();
+    Ok(value)
+}
+
+#[cfg(feature = "keyring")]
+fn read_keyring(service: &str, user: &str) -> Result<String> {
+    keyring::Entry::new(service, user)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| Error::InvalidConfig(format!("Failed to read {}/{} from keyring: {}", service, user, e)))
+}
+
+#[cfg(not(feature = "keyring"))]
+fn read_keyring(service: &str, user: &str) -> Result<String> {
+    Err(Error::InvalidConfig(format!(
+        "Cannot read {}/{} from keyring: pi-ai was built without the keyring feature",
+        service, user
+    )))
+}
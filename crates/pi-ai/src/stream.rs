@@ -1,7 +1,15 @@
-use bytes::Bytes;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
+use crate::conversation::openai::citations_from_annotations;
+use crate::conversation::Citation;
+use crate::hosted::HostedToolCall;
+use crate::error::{self, Error};
+use crate::message::{FunctionCall, ReasoningItem, ToolCall};
+use crate::models::{ChoiceLogprobs, TokenLogprob, Usage};
+use crate::tool_ids::generate_tool_call_id;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StreamChunk {
     pub id: String,
@@ -9,6 +17,9 @@ pub struct StreamChunk {
     pub created: u64,
     pub model: String,
     pub choices: Vec<StreamChoice>,
+    // 开启 stream_options.include_usage 后最后一个 chunk 才带
+    #[serde(default)]
+    pub usage: Option<Usage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +27,8 @@ pub struct StreamChoice {
     pub index: u32,
     pub delta: StreamDelta,
     pub finish_reason: Option<String>,
+    #[serde(default)]
+    pub logprobs: Option<ChoiceLogprobs>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,6 +36,8 @@ pub struct StreamDelta {
     pub role: Option<String>,
     pub content: Option<String>,
     pub tool_calls: Option<Vec<StreamToolCall>>,
+    #[serde(default)]
+    pub annotations: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,10 +55,22 @@ pub struct StreamFunction {
     pub arguments: Option<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum StreamEvent {
-    Token(String),
+    // index 是 choice 的下标，n > 1 时各个 choice 的分片会交错到达
+    Token { index: u32, text: String },
     ToolCall { id: String, name: String, arguments: String },
+    // 参数分片；同一个 (choice, index) 的第一个分片带 id 和 name，完整的调用随后以 ToolCall 给出
+    ToolCallDelta { choice: u32, index: u32, id: Option<String>, name: Option<String>, arguments: String },
+    Usage(Usage),
+    // provider 给出的结束原因（stop、length、tool_calls 等），在对应的 Done 之前
+    FinishReason(String),
+    Logprobs { index: u32, tokens: Vec<TokenLogprob> },
+    Citation(Citation),
+    HostedToolCall(HostedToolCall),
+    // Responses API 的推理条目和响应 id，流式调用也能据此用 previous_response_id 续接
+    Reasoning(ReasoningItem),
+    ResponseId(String),
     Done,
     Error(String),
 }
@@ -51,16 +78,50 @@ pub enum StreamEvent {
 impl fmt::Display for StreamEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            StreamEvent::Token(token) => write!(f, "{}", token),
+            StreamEvent::Token { text, .. } => write!(f, "{}", text),
             StreamEvent::ToolCall { id, name, arguments } => {
                 write!(f, "[ToolCall: {}({}) args={}]", name, id, arguments)
             }
+            StreamEvent::ToolCallDelta { index, arguments, .. } => {
+                write!(f, "[ToolCallDelta: #{} {}]", index, arguments)
+            }
+            StreamEvent::Usage(usage) => write!(f, "[Usage: {} tokens]", usage.total_tokens),
+            StreamEvent::FinishReason(reason) => write!(f, "[FinishReason: {}]", reason),
+            StreamEvent::Logprobs { tokens, .. } => write!(f, "[Logprobs: {} tokens]", tokens.len()),
+            StreamEvent::Citation(citation) => {
+                write!(f, "[Citation: {}]", citation.url.as_deref().or(citation.title.as_deref()).unwrap_or(&citation.kind))
+            }
+            StreamEvent::HostedToolCall(call) => write!(f, "[HostedToolCall: {}]", call.name),
+            StreamEvent::Reasoning(item) => write!(f, "[Reasoning: {}]", item.summary.join(" ")),
+            StreamEvent::ResponseId(id) => write!(f, "[ResponseId: {}]", id),
             StreamEvent::Done => write!(f, "[Done]"),
             StreamEvent::Error(err) => write!(f, "[Error: {}]", err),
         }
     }
 }
 
+#[derive(Debug, Default)]
+pub(crate) struct SseBuffer {
+    buffer: Vec<u8>,
+}
+
+impl SseBuffer {
+    // Returns the `data:` payloads of every complete line, keeping partial lines for the next chunk.
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+
+        let mut data = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line = String::from_utf8_lossy(&self.buffer[..pos]).trim().to_string();
+            self.buffer.drain(..=pos);
+            if let Some(payload) = line.strip_prefix("data:") {
+                data.push(payload.trim().to_string());
+            }
+        }
+        data
+    }
+}
+
 pub fn parse_sse_line(line: &str) -> Result<Option<StreamChunk>, String> {
     let line = line.trim();
     
@@ -87,21 +148,17 @@ pub fn chunk_to_event(chunk: &StreamChunk) -> Vec<StreamEvent> {
     let mut events = Vec::new();
 
     for choice in &chunk.choices {
-        if let Some(content) = &choice.delta.content {
-            if !content.is_empty() {
-                events.push(StreamEvent::Token(content.clone()));
-            }
-        }
+        content_events(choice, &mut events);
 
         if let Some(tool_calls) = &choice.delta.tool_calls {
             for tool_call in tool_calls {
-                if let (Some(id), Some(name), Some(args)) = (
-                    &tool_call.id,
+                if let (Some(name), Some(args)) = (
                     tool_call.function.as_ref().and_then(|f| f.name.as_ref()),
                     tool_call.function.as_ref().and_then(|f| f.arguments.as_ref()),
                 ) {
+                    let id = tool_call.id.clone().filter(|id| !id.is_empty());
                     events.push(StreamEvent::ToolCall {
-                        id: id.clone(),
+                        id: id.unwrap_or_else(generate_tool_call_id),
                         name: name.clone(),
                         arguments: args.clone(),
                     });
@@ -109,10 +166,147 @@ pub fn chunk_to_event(chunk: &StreamChunk) -> Vec<StreamEvent> {
             }
         }
 
-        if choice.finish_reason.is_some() {
+        if let Some(reason) = &choice.finish_reason {
+            events.push(StreamEvent::FinishReason(reason.clone()));
             events.push(StreamEvent::Done);
         }
     }
 
     events
 }
+
+// chat completions 的流式状态：按行缓冲 SSE，并把 tool call 分片拼成完整调用。
+// Done 等到 [DONE] 或流结束才给出，这样 finish_reason 之后才到的 usage 也在 Done 之前
+#[derive(Debug, Default)]
+pub(crate) struct ChatStreamState {
+    sse: SseBuffer,
+    // 按 (choice, index) 拼接，n > 1 时不同 choice 的同号调用互不干扰
+    tool_calls: BTreeMap<(u32, u32), ToolCall>,
+    done: bool,
+}
+
+impl ChatStreamState {
+    pub(crate) fn handle(&mut self, bytes: &[u8]) -> Vec<error::Result<StreamEvent>> {
+        let mut events = Vec::new();
+
+        for data in self.sse.push(bytes) {
+            if data == "[DONE]" {
+                events.extend(self.finish());
+                continue;
+            }
+            match serde_json::from_str::<StreamChunk>(&data) {
+                Ok(chunk) => events.extend(self.chunk_events(&chunk).into_iter().map(Ok)),
+                Err(e) => events.push(Err(Error::Stream(e.to_string()))),
+            }
+        }
+
+        events
+    }
+
+    fn chunk_events(&mut self, chunk: &StreamChunk) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+
+        for choice in &chunk.choices {
+            content_events(choice, &mut events);
+
+            for fragment in choice.delta.tool_calls.iter().flatten() {
+                let function = fragment.function.as_ref();
+                let arguments = function.and_then(|f| f.arguments.clone()).unwrap_or_default();
+                let name = function.and_then(|f| f.name.clone()).filter(|name| !name.is_empty());
+
+                let key = (choice.index, fragment.index);
+                let first = !self.tool_calls.contains_key(&key);
+                let call = self.tool_calls.entry(key).or_insert_with(|| ToolCall {
+                    id: fragment
+                        .id
+                        .clone()
+                        .filter(|id| !id.is_empty())
+                        .unwrap_or_else(generate_tool_call_id),
+                    tool_type: "function".to_string(),
+                    function: FunctionCall {
+                        name: String::new(),
+                        arguments: String::new(),
+                    },
+                });
+                if let Some(name) = &name {
+                    call.function.name.push_str(name);
+                }
+                call.function.arguments.push_str(&arguments);
+
+                if first || name.is_some() || !arguments.is_empty() {
+                    events.push(StreamEvent::ToolCallDelta {
+                        choice: choice.index,
+                        index: fragment.index,
+                        id: first.then(|| call.id.clone()),
+                        name,
+                        arguments,
+                    });
+                }
+            }
+
+            if let Some(reason) = &choice.finish_reason {
+                events.extend(self.flush_tool_calls(Some(choice.index)));
+                events.push(StreamEvent::FinishReason(reason.clone()));
+            }
+        }
+
+        if let Some(usage) = &chunk.usage {
+            events.push(StreamEvent::Usage(usage.clone()));
+        }
+
+        events
+    }
+
+    // 收到 [DONE] 或连接结束时调用，只会给出一次 Done
+    pub(crate) fn finish(&mut self) -> Vec<error::Result<StreamEvent>> {
+        if self.done {
+            return Vec::new();
+        }
+        self.done = true;
+        let mut events: Vec<_> = self.flush_tool_calls(None).into_iter().map(Ok).collect();
+        events.push(Ok(StreamEvent::Done));
+        events
+    }
+
+    // 只给出已经结束的 choice 的调用；None 表示全部
+    fn flush_tool_calls(&mut self, choice: Option<u32>) -> Vec<StreamEvent> {
+        let keys = self
+            .tool_calls
+            .keys()
+            .filter(|(c, _)| choice.is_none_or(|choice| *c == choice))
+            .copied()
+            .collect::<Vec<_>>();
+        keys.into_iter()
+            .filter_map(|key| self.tool_calls.remove(&key))
+            .map(|call| StreamEvent::ToolCall {
+                id: call.id,
+                name: call.function.name,
+                arguments: call.function.arguments,
+            })
+            .collect()
+    }
+}
+
+fn content_events(choice: &StreamChoice, events: &mut Vec<StreamEvent>) {
+    if let Some(content) = &choice.delta.content {
+        if !content.is_empty() {
+            events.push(StreamEvent::Token {
+                index: choice.index,
+                text: content.clone(),
+            });
+        }
+    }
+
+    if let Some(annotations) = &choice.delta.annotations {
+        events.extend(citations_from_annotations(annotations).into_iter().map(StreamEvent::Citation));
+    }
+
+    if let Some(tokens) = choice.logprobs.as_ref().and_then(|l| l.content.as_ref()) {
+        if !tokens.is_empty() {
+            events.push(StreamEvent::Logprobs {
+                index: choice.index,
+                tokens: tokens.clone(),
+            });
+        }
+    }
+}
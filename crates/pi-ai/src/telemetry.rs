@@ -0,0 +1,358 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::stream::Stream;
+use tracing::field::Empty;
+use tracing::Span;
+
+use crate::config::TelemetryConfig;
+use crate::error::{Error, Result};
+use crate::fim::{FimRequest, FimResponse};
+use crate::message::Message;
+use crate::models::{ChatCompletionRequest, ChatCompletionResponse};
+use crate::provider::{Provider, ProviderType};
+use crate::stream::StreamEvent;
+
+const REDACTED: &str = "[REDACTED]";
+
+// 字段名遵循 OpenTelemetry GenAI 语义约定，tracing-opentelemetry 会原样转成 span 属性
+pub(crate) fn chat_span(provider: &Provider, request: &ChatCompletionRequest, stream: bool) -> Span {
+    operation_span(provider, "chat", &request.model, stream)
+}
+
+pub(crate) fn fim_span(provider: &Provider, request: &FimRequest, stream: bool) -> Span {
+    operation_span(provider, "text_completion", &request.model, stream)
+}
+
+fn operation_span(provider: &Provider, operation: &str, model: &str, stream: bool) -> Span {
+    tracing::info_span!(
+        target: "pi_ai::telemetry",
+        "gen_ai",
+        "otel.name" = %format!("{} {}", operation, model),
+        "otel.kind" = "client",
+        "otel.status_code" = Empty,
+        "gen_ai.operation.name" = operation,
+        "gen_ai.provider.name" = provider_name(provider),
+        "gen_ai.request.model" = model,
+        "gen_ai.request.temperature" = Empty,
+        "gen_ai.request.top_p" = Empty,
+        "gen_ai.request.max_tokens" = Empty,
+        "gen_ai.request.stream" = stream,
+        "gen_ai.response.id" = Empty,
+        "gen_ai.response.model" = Empty,
+        "gen_ai.response.finish_reasons" = Empty,
+        "gen_ai.usage.input_tokens" = Empty,
+        "gen_ai.usage.output_tokens" = Empty,
+        "gen_ai.client.operation.duration" = Empty,
+        "gen_ai.server.time_to_first_token" = Empty,
+        "gen_ai.input.messages" = Empty,
+        "gen_ai.output.messages" = Empty,
+        "error.type" = Empty,
+        "server.address" = %provider.config.base_url,
+    )
+}
+
+pub(crate) fn provider_name(provider: &Provider) -> &str {
+    match provider.provider_type {
+        ProviderType::OpenAI => "openai",
+        ProviderType::Anthropic => "anthropic",
+        ProviderType::Google => "gcp.gemini",
+        ProviderType::Azure => "azure.ai.openai",
+        ProviderType::Custom => &provider.name,
+    }
+}
+
+pub(crate) fn record_request(
+    span: &Span,
+    provider: &Provider,
+    request: &ChatCompletionRequest,
+    config: &TelemetryConfig,
+) {
+    record_sampling(span, request.temperature, request.top_p, request.max_tokens);
+    if config.capture_content {
+        let content = capture(&request.messages, provider, config);
+        span.record("gen_ai.input.messages", content.as_str());
+    }
+}
+
+// FIM 把渲染后的 prompt 当作一条 user 消息记录
+pub(crate) fn record_fim_request(span: &Span, provider: &Provider, request: &FimRequest, config: &TelemetryConfig) {
+    record_sampling(span, request.temperature, request.top_p, request.max_tokens);
+    if config.capture_content {
+        let prompt = request.resolved_template().render(&request.prefix, request.suffix.as_deref().unwrap_or_default());
+        let content = capture(&[Message::user(prompt)], provider, config);
+        span.record("gen_ai.input.messages", content.as_str());
+    }
+}
+
+fn record_sampling(span: &Span, temperature: Option<f32>, top_p: Option<f32>, max_tokens: Option<u32>) {
+    if let Some(temperature) = temperature {
+        span.record("gen_ai.request.temperature", temperature as f64);
+    }
+    if let Some(top_p) = top_p {
+        span.record("gen_ai.request.top_p", top_p as f64);
+    }
+    if let Some(max_tokens) = max_tokens {
+        span.record("gen_ai.request.max_tokens", max_tokens as i64);
+    }
+}
+
+pub(crate) fn record_response(
+    span: &Span,
+    provider: &Provider,
+    response: &ChatCompletionResponse,
+    started: Instant,
+    config: &TelemetryConfig,
+) {
+    let finish_reasons = response
+        .choices
+        .iter()
+        .filter_map(|c| c.finish_reason.as_deref())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    span.record("gen_ai.response.id", response.id.as_str());
+    span.record("gen_ai.response.model", response.model.as_str());
+    span.record("gen_ai.response.finish_reasons", finish_reasons.as_str());
+    span.record("gen_ai.usage.input_tokens", response.usage.prompt_tokens as i64);
+    span.record("gen_ai.usage.output_tokens", response.usage.completion_tokens as i64);
+    span.record("gen_ai.client.operation.duration", started.elapsed().as_secs_f64());
+
+    if config.capture_content {
+        let messages = response.choices.iter().map(|c| c.message.clone()).collect::<Vec<_>>();
+        let content = capture(&messages, provider, config);
+        span.record("gen_ai.output.messages", content.as_str());
+    }
+}
+
+pub(crate) fn record_fim_response(
+    span: &Span,
+    provider: &Provider,
+    response: &FimResponse,
+    started: Instant,
+    config: &TelemetryConfig,
+) {
+    let finish_reasons = response
+        .choices
+        .iter()
+        .filter_map(|c| c.finish_reason.as_deref())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    span.record("gen_ai.response.id", response.id.as_str());
+    span.record("gen_ai.response.model", response.model.as_str());
+    span.record("gen_ai.response.finish_reasons", finish_reasons.as_str());
+    if let Some(usage) = &response.usage {
+        span.record("gen_ai.usage.input_tokens", usage.prompt_tokens as i64);
+        span.record("gen_ai.usage.output_tokens", usage.completion_tokens as i64);
+    }
+    span.record("gen_ai.client.operation.duration", started.elapsed().as_secs_f64());
+
+    if config.capture_content {
+        let messages = response
+            .choices
+            .iter()
+            .map(|c| Message::assistant(c.text.as_str()))
+            .collect::<Vec<_>>();
+        let content = capture(&messages, provider, config);
+        span.record("gen_ai.output.messages", content.as_str());
+    }
+}
+
+pub(crate) fn record_error(span: &Span, error: &Error, started: Instant) {
+    let error_type = match error.kind() {
+        Some(kind) => kind.to_string(),
+        None => match error {
+            Error::Http(_) => "http".to_string(),
+            Error::Stream(_) => "stream".to_string(),
+            Error::CircuitOpen { .. } => "circuit_open".to_string(),
+            _ => "_OTHER".to_string(),
+        },
+    };
+    span.record("otel.status_code", "ERROR");
+    span.record("error.type", error_type.as_str());
+    span.record("gen_ai.client.operation.duration", started.elapsed().as_secs_f64());
+}
+
+// 序列化消息后做脱敏和截断；provider 的 api_key 总是会被替换
+pub(crate) fn capture(messages: &[Message], provider: &Provider, config: &TelemetryConfig) -> String {
+    let mut content = serde_json::to_string(messages).unwrap_or_default();
+
+    let secrets = config
+        .redact
+        .iter()
+        .map(String::as_str)
+        .chain(std::iter::once(provider.config.api_key.expose_secret()))
+        .filter(|s| !s.is_empty());
+    for secret in secrets {
+        content = content.replace(secret, REDACTED);
+    }
+
+    if let Some(max) = config.max_content_chars {
+        if let Some((index, _)) = content.char_indices().nth(max) {
+            content.truncate(index);
+            content.push_str("...");
+        }
+    }
+
+    content
+}
+
+pub(crate) struct InstrumentedStream {
+    inner: Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>,
+    span: Span,
+    started: Instant,
+    first_token: bool,
+    finish_reasons: Vec<String>,
+    output: String,
+    provider: Provider,
+    config: TelemetryConfig,
+    finished: bool,
+}
+
+impl InstrumentedStream {
+    pub(crate) fn new(
+        inner: Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>,
+        span: Span,
+        started: Instant,
+        provider: &Provider,
+        config: &TelemetryConfig,
+    ) -> Self {
+        Self {
+            inner,
+            span,
+            started,
+            first_token: false,
+            finish_reasons: Vec::new(),
+            output: String::new(),
+            provider: provider.clone(),
+            config: config.clone(),
+            finished: false,
+        }
+    }
+
+    fn observe(&mut self, event: &Result<StreamEvent>) {
+        match event {
+            Ok(StreamEvent::Token { index, text }) => {
+                self.mark_first_token();
+                // 只记录第一个 choice 的输出
+                if self.config.capture_content && *index == 0 {
+                    self.output.push_str(text);
+                }
+            }
+            Ok(StreamEvent::ToolCall { .. } | StreamEvent::HostedToolCall(_) | StreamEvent::ToolCallDelta { .. }) => {
+                self.mark_first_token()
+            }
+            Ok(StreamEvent::Usage(usage)) => {
+                self.span.record("gen_ai.usage.input_tokens", usage.prompt_tokens as i64);
+                self.span.record("gen_ai.usage.output_tokens", usage.completion_tokens as i64);
+            }
+            Ok(StreamEvent::FinishReason(reason)) => self.finish_reasons.push(reason.clone()),
+            Ok(StreamEvent::Reasoning(_)) => self.mark_first_token(),
+            Ok(StreamEvent::ResponseId(id)) => {
+                self.span.record("gen_ai.response.id", id.as_str());
+            }
+            Ok(StreamEvent::Logprobs { .. } | StreamEvent::Citation(_)) => {}
+            Ok(StreamEvent::Done) => self.finish(),
+            Ok(StreamEvent::Error(_)) => {
+                self.span.record("otel.status_code", "ERROR");
+                self.span.record("error.type", "stream");
+                self.finish();
+            }
+            Err(error) => {
+                record_error(&self.span, error, self.started);
+                self.finished = true;
+            }
+        }
+    }
+
+    fn mark_first_token(&mut self) {
+        if !self.first_token {
+            self.first_token = true;
+            self.span
+                .record("gen_ai.server.time_to_first_token", self.started.elapsed().as_secs_f64());
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        if !self.finish_reasons.is_empty() {
+            self.span
+                .record("gen_ai.response.finish_reasons", self.finish_reasons.join(",").as_str());
+        }
+        if self.config.capture_content {
+            let content = capture(
+                &[Message::assistant(self.output.as_str())],
+                &self.provider,
+                &self.config,
+            );
+            self.span.record("gen_ai.output.messages", content.as_str());
+        }
+        self.span
+            .record("gen_ai.client.operation.duration", self.started.elapsed().as_secs_f64());
+    }
+}
+
+impl Stream for InstrumentedStream {
+    type Item = Result<StreamEvent>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let poll = {
+            let _entered = this.span.enter();
+            this.inner.as_mut().poll_next(cx)
+        };
+
+        match &poll {
+            Poll::Ready(Some(event)) => this.observe(event),
+            Poll::Ready(None) => this.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for InstrumentedStream {
+    fn drop(&mut self) {
+        // 调用方提前丢弃流时也要记录耗时
+        self.finish();
+    }
+}
+
+#[cfg(feature = "otel")]
+pub mod otlp {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use opentelemetry_sdk::Resource;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    use crate::error::{Error, Result};
+
+    // endpoint 形如 http://localhost:4318/v1/traces
+    pub fn tracer_provider(endpoint: &str, service_name: &str) -> Result<SdkTracerProvider> {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|e| Error::InvalidConfig(format!("Failed to build OTLP exporter: {}", e)))?;
+
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name.to_string()).build())
+            .build())
+    }
+
+    pub fn layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("pi-ai"))
+    }
+}
@@ -1,6 +1,8 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use super::*;
+    use crate::*;
+    use crate::models::ToolChoice;
 
     #[test]
     fn test_provider_type_from_str() {
@@ -79,8 +81,1673 @@ mod tests {
         }
 
         match function {
-            ToolChoice::Function { func } => assert_eq!(func.name, "test_function"),
+            ToolChoice::Function { function: func } => assert_eq!(func.name, "test_function"),
             _ => panic!("Expected Function"),
         }
     }
+
+    async fn read_http_request(socket: &mut tokio::net::TcpStream) -> Option<(String, String, String)> {
+        use tokio::io::AsyncReadExt;
+
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let header_end = loop {
+            let n = socket.read(&mut chunk).await.unwrap_or(0);
+            if n == 0 {
+                return None;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+
+        let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+        let content_length = head
+            .lines()
+            .find_map(|l| {
+                let (k, v) = l.split_once(':')?;
+                k.eq_ignore_ascii_case("content-length").then(|| v.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+        while buf.len() < header_end + content_length {
+            let n = socket.read(&mut chunk).await.unwrap_or(0);
+            if n == 0 {
+                break;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+
+        let mut request_line = head.lines().next().unwrap_or_default().split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_string();
+        let path = request_line.next().unwrap_or_default().to_string();
+        let body = String::from_utf8_lossy(&buf[header_end..]).to_string();
+        Some((method, path, body))
+    }
+
+    async fn write_http_response(socket: &mut tokio::net::TcpStream, status: &str, body: &str) {
+        use tokio::io::AsyncWriteExt;
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let _ = socket.write_all(response.as_bytes()).await;
+    }
+
+    async fn spawn_mock_server(routes: Vec<(&'static str, &'static str, String)>) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    break;
+                };
+                let routes = routes.clone();
+                tokio::spawn(async move {
+                    let Some((method, path, _)) = read_http_request(&mut socket).await else {
+                        return;
+                    };
+                    let (status, body) = routes
+                        .iter()
+                        .find(|(m, p, _)| *m == method && *p == path)
+                        .map(|(_, _, body)| ("200 OK", body.clone()))
+                        .unwrap_or(("404 Not Found", "{}".to_string()));
+                    write_http_response(&mut socket, status, &body).await;
+                });
+            }
+        });
+
+        format!("http://{}/v1", addr)
+    }
+
+    // 按顺序返回预设的响应，并记录收到的请求体
+    async fn spawn_scripted_server(
+        responses: Vec<(&'static str, String)>,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = received.clone();
+
+        tokio::spawn(async move {
+            let mut responses = responses.into_iter();
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    break;
+                };
+                let Some((_, _, body)) = read_http_request(&mut socket).await else {
+                    continue;
+                };
+                log.lock().unwrap().push(body);
+                let (status, body) = responses.next().unwrap_or(("500 Internal Server Error", "{}".to_string()));
+                write_http_response(&mut socket, status, &body).await;
+            }
+        });
+
+        (format!("http://{}/v1", addr), received)
+    }
+
+    fn mock_client(base_url: String) -> Client {
+        let config = Config {
+            providers: Default::default(),
+            default_provider: "mock".to_string(),
+            timeout_secs: 5,
+            max_retries: 0,
+            telemetry: Default::default(),
+            circuit_breaker: Default::default(),
+            network: Default::default(),
+        }
+        .with_provider(
+            "mock".to_string(),
+            ProviderConfig {
+                api_key: "test-key".into(),
+                base_url,
+                model: "mock-model".to_string(),
+                ..Default::default()
+            },
+        );
+        Client::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_batch_input_line_formats() {
+        use crate::batch::{input_line, BatchFormat};
+
+        let request = BatchRequest::new(
+            "req-1",
+            ChatCompletionRequest::new(
+                "claude-3-haiku",
+                vec![Message::system("Be brief"), Message::user("Summarize")],
+            ),
+        );
+
+        let openai: serde_json::Value =
+            serde_json::from_str(&input_line(BatchFormat::OpenAI, &request).unwrap()).unwrap();
+        assert_eq!(openai["custom_id"], "req-1");
+        assert_eq!(openai["url"], "/v1/chat/completions");
+        assert_eq!(openai["body"]["model"], "claude-3-haiku");
+        assert!(openai["body"].get("stream").is_none());
+
+        let anthropic: serde_json::Value =
+            serde_json::from_str(&input_line(BatchFormat::Anthropic, &request).unwrap()).unwrap();
+        assert_eq!(anthropic["params"]["system"], "Be brief");
+        assert_eq!(anthropic["params"]["messages"][0]["role"], "user");
+        assert_eq!(anthropic["params"]["max_tokens"], 4096);
+    }
+
+    #[test]
+    fn test_batch_anthropic_result_parsing() {
+        use crate::batch::{parse_result_line, BatchFormat};
+
+        let line = r#"{"custom_id":"a","result":{"type":"succeeded","message":{"id":"msg_1","model":"claude","content":[{"type":"text","text":"done"}],"stop_reason":"end_turn","usage":{"input_tokens":3,"output_tokens":2}}}}"#;
+        let (id, result) = parse_result_line(BatchFormat::Anthropic, line).unwrap();
+        assert_eq!(id, "a");
+        let response = result.response().unwrap();
+        assert_eq!(response.choices[0].message.content, "done");
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("stop"));
+        assert_eq!(response.usage.total_tokens, 5);
+
+        let line = r#"{"custom_id":"b","result":{"type":"errored","error":{"type":"error","error":{"type":"invalid_request_error","message":"bad"}}}}"#;
+        match parse_result_line(BatchFormat::Anthropic, line).unwrap().1 {
+            BatchItemResult::Errored { code, message } => {
+                assert_eq!(code.as_deref(), Some("invalid_request_error"));
+                assert_eq!(message, "bad");
+            }
+            other => panic!("Expected Errored, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_lifecycle_against_mock_server() {
+        let output = [
+            r#"{"id":"r1","custom_id":"first","response":{"status_code":200,"body":{"id":"c1","object":"chat.completion","created":1,"model":"mock-model","choices":[{"index":0,"message":{"role":"Assistant","content":"one","name":null,"tool_calls":null,"tool_call_id":null},"finish_reason":"stop","logprobs":null}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2},"system_fingerprint":null}},"error":null}"#,
+            r#"{"id":"r2","custom_id":"second","response":{"status_code":429,"body":{"error":{"message":"slow down","code":"rate_limit_exceeded"}}},"error":null}"#,
+        ]
+        .join("\n");
+
+        let base_url = spawn_mock_server(vec![
+            ("POST", "/v1/files", r#"{"id":"file-in"}"#.to_string()),
+            ("POST", "/v1/batches", r#"{"id":"batch-1","status":"validating"}"#.to_string()),
+            (
+                "GET",
+                "/v1/batches/batch-1",
+                r#"{"id":"batch-1","status":"completed","output_file_id":"file-out","error_file_id":null}"#.to_string(),
+            ),
+            ("GET", "/v1/files/file-out/content", output),
+        ])
+        .await;
+
+        let client = mock_client(base_url);
+        let dir = std::env::temp_dir().join(format!("pi-ai-batch-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let requests = vec![
+            BatchRequest::new("first", ChatCompletionRequest::new("mock-model", vec![Message::user("1")])),
+            BatchRequest::new("second", ChatCompletionRequest::new("mock-model", vec![Message::user("2")])),
+        ];
+        let job = client.create_batch("mock", requests, &dir).await.unwrap();
+        assert_eq!(job.status(), BatchStatus::Validating);
+        assert_eq!(job.manifest.input_file_id.as_deref(), Some("file-in"));
+
+        let mut job = BatchJob::load(&dir).unwrap();
+        assert_eq!(job.manifest.batch_id.as_deref(), Some("batch-1"));
+        let status = client
+            .wait_for_batch(&mut job, std::time::Duration::from_millis(10))
+            .await
+            .unwrap();
+        assert_eq!(status, BatchStatus::Completed);
+
+        let results = client.batch_results(&mut job).await.unwrap();
+        assert_eq!(results["first"].response().unwrap().choices[0].message.content, "one");
+        assert!(matches!(results["second"], BatchItemResult::Errored { .. }));
+        assert!(BatchJob::load(&dir).unwrap().manifest.results_downloaded);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_fim_template_rendering() {
+        let request = FimRequest::new("qwen2.5-coder-7b", "fn add(a: i32, b: i32) -> i32 {\n")
+            .with_suffix("\n}")
+            .with_stop(vec!["\n\n".to_string()]);
+        assert_eq!(request.resolved_template(), FimTemplate::QwenCoder);
+
+        let body = request.build_body(false);
+        assert_eq!(
+            body["prompt"],
+            "<|fim_prefix|>fn add(a: i32, b: i32) -> i32 {\n<|fim_suffix|>\n}<|fim_middle|>"
+        );
+        assert!(body.get("suffix").is_none());
+        assert_eq!(body["stop"].as_array().unwrap().len(), 4);
+        assert_eq!(body["stop"][0], "\n\n");
+
+        let native = FimRequest::new("gpt-3.5-turbo-instruct", "def f():").with_suffix("return x");
+        let body = native.build_body(true);
+        assert_eq!(body["prompt"], "def f():");
+        assert_eq!(body["suffix"], "return x");
+    }
+
+    #[test]
+    fn test_fim_stop_sequence_filter_across_chunks() {
+        use crate::fim::StopSequenceFilter;
+
+        let mut filter = StopSequenceFilter::new(vec!["<|endoftext|>".to_string()]);
+        let mut out = filter.push("a + b<|end");
+        assert_eq!(out, "a + b");
+        out.push_str(&filter.push("oftext|> trailing"));
+        assert_eq!(out, "a + b");
+        assert!(filter.is_stopped());
+
+        let mut filter = StopSequenceFilter::new(vec!["<EOT>".to_string()]);
+        let mut out = filter.push("x <E");
+        out.push_str(&filter.push("nd"));
+        out.push_str(&filter.finish());
+        assert_eq!(out, "x <End");
+        assert!(!filter.is_stopped());
+    }
+
+    #[test]
+    fn test_provider_error_classification() {
+        use reqwest::header::{HeaderMap, HeaderValue};
+        use std::time::Duration;
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("7"));
+        headers.insert("x-request-id", HeaderValue::from_static("req_abc"));
+        let body = r#"{"error":{"message":"Rate limit reached","type":"requests","code":"rate_limit_exceeded"}}"#;
+        let err = ProviderError::from_response(ProviderType::OpenAI, 429, &headers, body.to_string());
+        assert_eq!(err.kind, ErrorKind::RateLimit);
+        assert!(err.is_retryable());
+        assert_eq!(err.retry_after, Some(Duration::from_secs(7)));
+        assert_eq!(err.request_id.as_deref(), Some("req_abc"));
+        assert_eq!(err.provider_code.as_deref(), Some("rate_limit_exceeded"));
+
+        let body = r#"{"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long: 210000 tokens > 200000 maximum"}}"#;
+        let err = ProviderError::from_response(ProviderType::Anthropic, 400, &HeaderMap::new(), body.to_string());
+        assert!(err.is_context_overflow());
+        assert!(!err.is_retryable());
+        assert!(Error::ApiError(err).is_context_overflow());
+
+        let body = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+        let err = ProviderError::from_response(ProviderType::Anthropic, 529, &HeaderMap::new(), body.to_string());
+        assert_eq!(err.kind, ErrorKind::Overloaded);
+
+        let body = r#"{"error":{"code":429,"message":"Quota exceeded","status":"RESOURCE_EXHAUSTED","details":[{"@type":"type.googleapis.com/google.rpc.RetryInfo","retryDelay":"30s"}]}}"#;
+        let err = ProviderError::from_response(ProviderType::Google, 429, &HeaderMap::new(), body.to_string());
+        assert_eq!(err.kind, ErrorKind::RateLimit);
+        assert_eq!(err.retry_after, Some(Duration::from_secs(30)));
+
+        for bad in ["-1", "nan", "inf", "1e300", "soon"] {
+            let mut headers = HeaderMap::new();
+            headers.insert("retry-after", HeaderValue::from_str(bad).unwrap());
+            let err = ProviderError::from_response(ProviderType::OpenAI, 429, &headers, String::new());
+            assert_eq!(err.retry_after, None, "{}", bad);
+        }
+        let body = r#"{"error":{"code":429,"message":"Quota exceeded","status":"RESOURCE_EXHAUSTED","details":[{"retryDelay":"-5s"}]}}"#;
+        let err = ProviderError::from_response(ProviderType::Google, 429, &HeaderMap::new(), body.to_string());
+        assert_eq!(err.retry_after, None);
+
+        let mut headers = HeaderMap::new();
+        let date = (chrono::Utc::now() + chrono::Duration::seconds(120)).format("%a, %d %b %Y %H:%M:%S GMT");
+        headers.insert("retry-after", HeaderValue::from_str(&date.to_string()).unwrap());
+        let err = ProviderError::from_response(ProviderType::OpenAI, 503, &headers, String::new());
+        let delay = err.retry_after.unwrap();
+        assert!(delay > Duration::from_secs(100) && delay <= Duration::from_secs(120));
+        headers.insert("retry-after", HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        let err = ProviderError::from_response(ProviderType::OpenAI, 503, &headers, String::new());
+        assert_eq!(err.retry_after, Some(Duration::ZERO));
+
+        let err = ProviderError::from_response(ProviderType::Custom, 401, &HeaderMap::new(), "Unauthorized".to_string());
+        assert_eq!(err.kind, ErrorKind::Authentication);
+        assert_eq!(err.message, "Unauthorized");
+    }
+
+    #[test]
+    fn test_responses_api_mapping() {
+        use crate::message::{FunctionCall, ToolCall};
+        use crate::responses::{build_responses_body, parse_responses_output, ResponsesStreamState};
+
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: FunctionCall {
+                name: "read".to_string(),
+                arguments: "{\"path\":\"a\"}".to_string(),
+            },
+        };
+        let reasoning = ReasoningItem {
+            id: Some("rs_1".to_string()),
+            summary: vec!["thinking".to_string()],
+            encrypted_content: Some("enc".to_string()),
+            signature: None,
+        };
+        let request = ChatCompletionRequest::new(
+            "o4-mini",
+            vec![
+                Message::system("sys"),
+                Message::user("hi"),
+                Message::assistant("").with_tool_calls(vec![call]).with_reasoning(vec![reasoning]),
+                Message::tool("contents", "call_1"),
+            ],
+        )
+        .with_max_tokens(100)
+        .with_tool_choice(ToolChoice::function("read"));
+
+        let config = ResponsesConfig {
+            include_encrypted_reasoning: true,
+            ..Default::default()
+        };
+        let body = build_responses_body(&request, &config, false).unwrap();
+        let input = body["input"].as_array().unwrap();
+        let types: Vec<_> = input.iter().map(|i| i["type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["message", "message", "reasoning", "function_call", "function_call_output"]);
+        assert_eq!(input[2]["encrypted_content"], "enc");
+        assert_eq!(input[4]["call_id"], "call_1");
+        assert_eq!(body["max_output_tokens"], 100);
+        assert_eq!(body["tool_choice"]["name"], "read");
+        assert_eq!(body["include"][0], "reasoning.encrypted_content");
+        assert_eq!(body["store"], false);
+
+        let output = serde_json::json!({
+            "id": "resp_1",
+            "object": "response",
+            "created_at": 10,
+            "model": "o4-mini",
+            "status": "completed",
+            "output": [
+                {"type": "reasoning", "id": "rs_2", "summary": [{"type": "summary_text", "text": "plan"}], "encrypted_content": "enc2"},
+                {"type": "function_call", "id": "fc_1", "call_id": "call_2", "name": "write", "arguments": "{}"}
+            ],
+            "usage": {"input_tokens": 5, "output_tokens": 7, "total_tokens": 12}
+        });
+        let response = parse_responses_output(&output).unwrap();
+        let message = &response.choices[0].message;
+        assert_eq!(response.id, "resp_1");
+        assert_eq!(response.choices[0].finish_reason.as_deref(), Some("tool_calls"));
+        assert_eq!(message.tool_calls.as_ref().unwrap()[0].id, "call_2");
+        assert_eq!(message.reasoning.as_ref().unwrap()[0].encrypted_content.as_deref(), Some("enc2"));
+        assert_eq!(response.usage.total_tokens, 12);
+
+        let mut state = ResponsesStreamState::default();
+        let events = state.handle(
+            b"event: response.output_text.delta\ndata: {\"type\":\"response.output_text.delta\",\"delta\":\"He\"}\n\ndata: {\"type\":\"response.output_item.done\",\"item\":{\"type\":\"function_call\",\"call_id\":\"c\",\"name\":\"n\",\"arguments\":\"{}\"}}\n\ndata: {\"type\":\"response.comp",
+        );
+        let events: Vec<_> = events.into_iter().map(|e| e.unwrap()).collect();
+        assert_eq!(events[0], StreamEvent::Token { index: 0, text: "He".to_string() });
+        assert!(matches!(&events[1], StreamEvent::ToolCall { id, .. } if id == "c"));
+        let events = state.handle(b"leted\",\"response\":{}}\n\n");
+        assert_eq!(events.into_iter().map(|e| e.unwrap()).collect::<Vec<_>>(), vec![StreamEvent::Done]);
+    }
+
+    #[test]
+    fn test_responses_stream_exposes_id_reasoning_and_terminal_states() {
+        use crate::message::ReasoningItem;
+        use crate::responses::ResponsesStreamState;
+
+        let collect = |data: &str| {
+            ResponsesStreamState::default()
+                .handle(data.as_bytes())
+                .into_iter()
+                .map(|e| e.unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let events = collect(concat!(
+            "data: {\"type\":\"response.created\",\"response\":{\"id\":\"resp_9\",\"status\":\"in_progress\"}}\n\n",
+            "data: {\"type\":\"response.output_item.done\",\"item\":{\"type\":\"reasoning\",\"id\":\"rs_1\",\"summary\":[{\"type\":\"summary_text\",\"text\":\"think\"}],\"encrypted_content\":\"enc\"}}\n\n",
+        ));
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::ResponseId("resp_9".to_string()),
+                StreamEvent::Reasoning(ReasoningItem {
+                    id: Some("rs_1".to_string()),
+                    summary: vec!["think".to_string()],
+                    encrypted_content: Some("enc".to_string()),
+                    signature: None,
+                }),
+            ]
+        );
+
+        let events = collect(
+            "data: {\"type\":\"response.incomplete\",\"response\":{\"status\":\"incomplete\",\"incomplete_details\":{\"reason\":\"max_output_tokens\"}}}\n\n",
+        );
+        assert_eq!(events, vec![StreamEvent::FinishReason("length".to_string()), StreamEvent::Done]);
+        let events = collect("data: {\"type\":\"response.incomplete\",\"response\":{}}\n\n");
+        assert_eq!(events, vec![StreamEvent::FinishReason("incomplete".to_string()), StreamEvent::Done]);
+
+        let events = collect(
+            "data: {\"type\":\"response.failed\",\"response\":{\"status\":\"failed\",\"error\":{\"code\":\"server_error\",\"message\":\"boom\"}}}\n\n",
+        );
+        assert_eq!(events, vec![StreamEvent::Error("server_error: boom".to_string())]);
+    }
+
+    #[test]
+    fn test_chat_body_drops_local_only_message_fields() {
+        use crate::message::ReasoningItem;
+
+        let client = mock_client("http://127.0.0.1:9/v1".to_string());
+        let reasoning = ReasoningItem {
+            id: Some("rs_1".to_string()),
+            summary: vec!["think".to_string()],
+            encrypted_content: Some("enc".to_string()),
+            signature: None,
+        };
+        let request = ChatCompletionRequest::new(
+            "m",
+            vec![Message::user("hi"), Message::assistant("hello").with_reasoning(vec![reasoning])],
+        );
+        let body = client.build_request_body(&request).unwrap();
+        let assistant = body["messages"][1].as_object().unwrap();
+        assert_eq!(assistant["content"], "hello");
+        assert!(!assistant.contains_key("reasoning"));
+        assert!(!assistant.contains_key("citations"));
+    }
+
+    #[test]
+    fn test_conversation_openai_round_trip() {
+        use crate::conversation::openai;
+
+        let messages = vec![
+            serde_json::json!({"role": "system", "content": "You are terse."}),
+            serde_json::json!({"role": "user", "content": [
+                {"type": "text", "text": "What is in this image?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA", "detail": "low"}}
+            ]}),
+            serde_json::json!({"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "lookup", "arguments": "{\"q\":\"cat\"}"}}
+            ]}),
+            serde_json::json!({"role": "tool", "tool_call_id": "call_1", "content": "a cat"}),
+            serde_json::json!({"role": "assistant", "content": "A cat."}),
+        ];
+
+        let conversation = openai::from_messages(&messages);
+        assert_eq!(conversation.len(), 5);
+        assert!(matches!(
+            &conversation[1].content[1],
+            ContentBlock::Image { source: MediaSource::Base64 { media_type, .. }, .. } if media_type == "image/png"
+        ));
+        assert_eq!(openai::to_messages(&conversation), messages);
+
+        // 经过扁平的 Message 模型再转回来，工具调用历史不能丢
+        let flat = ConversationMessage::to_messages(&conversation);
+        assert_eq!(flat[2].tool_calls.as_ref().unwrap()[0].id, "call_1");
+        assert_eq!(flat[3].tool_call_id.as_deref(), Some("call_1"));
+    }
+
+    #[test]
+    fn test_conversation_anthropic_round_trip() {
+        use crate::conversation::anthropic;
+
+        let system = serde_json::json!("Be helpful.");
+        let messages = vec![
+            serde_json::json!({"role": "user", "content": [
+                {"type": "document", "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBE"}, "title": "spec"},
+                {"type": "text", "text": "Summarise and check the weather."}
+            ]}),
+            serde_json::json!({"role": "assistant", "content": [
+                {"type": "thinking", "thinking": "need weather", "signature": "sig"},
+                {"type": "redacted_thinking", "data": "opaque"},
+                {"type": "text", "text": "The spec says hi.", "citations": [
+                    {"type": "page_location", "cited_text": "hi", "document_index": 0, "document_title": "spec", "start_page_number": 1, "end_page_number": 2}
+                ]},
+                {"type": "tool_use", "id": "toolu_1", "name": "weather", "input": {"city": "Paris"}}
+            ]}),
+            serde_json::json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "toolu_1", "content": "sunny", "is_error": true},
+                {"type": "text", "text": "Thanks"}
+            ]}),
+        ];
+
+        let conversation = anthropic::from_messages(Some(&system), &messages);
+        let roles: Vec<_> = conversation.iter().map(|m| m.role.clone()).collect();
+        assert_eq!(
+            roles,
+            [MessageRole::System, MessageRole::User, MessageRole::Assistant, MessageRole::Tool, MessageRole::User]
+        );
+
+        let (round_system, round_messages) = anthropic::to_messages(&conversation);
+        assert_eq!(round_system, Some(system));
+        assert_eq!(round_messages, messages);
+    }
+
+    #[test]
+    fn test_conversation_gemini_round_trip() {
+        use crate::conversation::gemini;
+
+        let system = serde_json::json!({"parts": [{"text": "Be brief."}]});
+        let contents = vec![
+            serde_json::json!({"role": "user", "parts": [{"text": "Weather?"}]}),
+            serde_json::json!({"role": "model", "parts": [
+                {"text": "thinking", "thought": true, "thoughtSignature": "sig"},
+                {"functionCall": {"name": "weather", "args": {"city": "Paris"}}}
+            ]}),
+            serde_json::json!({"role": "user", "parts": [
+                {"functionResponse": {"name": "weather", "response": {"temp": 20}}}
+            ]}),
+            serde_json::json!({"role": "model", "parts": [{"text": "20 degrees."}]}),
+        ];
+
+        let conversation = gemini::from_contents(Some(&system), &contents);
+        let (id, _, arguments) = conversation[2].tool_uses().next().unwrap();
+        assert_eq!(arguments, "{\"city\":\"Paris\"}");
+        assert!(matches!(
+            &conversation[3].content[0],
+            ContentBlock::ToolResult { tool_use_id, .. } if tool_use_id == id
+        ));
+
+        let (round_system, round_contents) = gemini::to_contents(&conversation);
+        assert_eq!(round_system, Some(system));
+        assert_eq!(round_contents, contents);
+    }
+
+    #[test]
+    fn test_conversation_cross_provider_tool_history() {
+        use crate::conversation::{anthropic, gemini, openai};
+
+        let messages = vec![
+            serde_json::json!({"role": "system", "content": "sys"}),
+            serde_json::json!({"role": "user", "content": "read both files"}),
+            serde_json::json!({"role": "assistant", "content": "Reading.", "tool_calls": [
+                {"id": "call_a", "type": "function", "function": {"name": "read", "arguments": "{\"path\":\"a\"}"}},
+                {"id": "call_b", "type": "function", "function": {"name": "read", "arguments": "{\"path\":\"b\"}"}}
+            ]}),
+            serde_json::json!({"role": "tool", "tool_call_id": "call_a", "content": "A"}),
+            serde_json::json!({"role": "tool", "tool_call_id": "call_b", "content": "B"}),
+        ];
+        let conversation = openai::from_messages(&messages);
+
+        let (system, anthropic_messages) = anthropic::to_messages(&conversation);
+        assert_eq!(system, Some(serde_json::json!("sys")));
+        assert_eq!(anthropic_messages.len(), 3);
+        assert_eq!(anthropic_messages[1]["content"][1]["input"]["path"], "a");
+        // 连续的工具结果合并进同一条 user 消息
+        let results = anthropic_messages[2]["content"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[1]["tool_use_id"], "call_b");
+
+        let back = openai::to_messages(&anthropic::from_messages(system.as_ref(), &anthropic_messages));
+        assert_eq!(back, messages);
+
+        let (_, contents) = gemini::to_contents(&conversation);
+        assert_eq!(contents[2]["parts"][1]["functionResponse"]["name"], "read");
+        assert_eq!(contents[2]["parts"][1]["functionResponse"]["response"]["content"], "B");
+    }
+
+    #[test]
+    fn test_model_listing_parsing() {
+        use crate::client::parse_model_page;
+
+        let vllm = serde_json::json!({"object": "list", "data": [
+            {"id": "Qwen/Qwen2.5-Coder-32B", "object": "model", "created": 1700000000, "owned_by": "vllm", "max_model_len": 32768}
+        ]});
+        let (models, next) = parse_model_page(ProviderType::Custom, &vllm).unwrap();
+        assert_eq!(models[0].context_length, Some(32768));
+        assert_eq!(models[0].owned_by.as_deref(), Some("vllm"));
+        assert_eq!(models[0].name(), "Qwen/Qwen2.5-Coder-32B");
+        assert!(next.is_none());
+
+        let anthropic = serde_json::json!({
+            "data": [{"type": "model", "id": "claude-sonnet-4", "display_name": "Claude Sonnet 4", "created_at": "2025-05-22T00:00:00Z"}],
+            "has_more": true,
+            "first_id": "claude-sonnet-4",
+            "last_id": "claude-sonnet-4"
+        });
+        let (models, next) = parse_model_page(ProviderType::Anthropic, &anthropic).unwrap();
+        assert_eq!(models[0].name(), "Claude Sonnet 4");
+        assert_eq!(models[0].created, Some(1747872000));
+        assert_eq!(next.as_deref(), Some("claude-sonnet-4"));
+
+        let gemini = serde_json::json!({
+            "models": [{"name": "models/gemini-2.0-flash", "displayName": "Gemini 2.0 Flash", "inputTokenLimit": 1048576, "outputTokenLimit": 8192}],
+            "nextPageToken": "page-2"
+        });
+        let (models, next) = parse_model_page(ProviderType::Google, &gemini).unwrap();
+        assert_eq!(models[0].id, "gemini-2.0-flash");
+        assert_eq!(models[0].context_length, Some(1048576));
+        assert_eq!(models[0].max_output_tokens, Some(8192));
+        assert_eq!(next.as_deref(), Some("page-2"));
+
+        assert!(parse_model_page(ProviderType::OpenAI, &serde_json::json!({"models": []})).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_models_paginates() {
+        let base_url = spawn_mock_server(vec![
+            (
+                "GET",
+                "/v1/models?limit=1000",
+                r#"{"data":[{"id":"m1","display_name":"M1"}],"has_more":true,"last_id":"m1"}"#.to_string(),
+            ),
+            (
+                "GET",
+                "/v1/models?limit=1000&after_id=m1",
+                r#"{"data":[{"id":"m2","display_name":"M2"}],"has_more":false,"last_id":"m2"}"#.to_string(),
+            ),
+        ])
+        .await;
+
+        let config = Config::default().with_provider(
+            "anthropic".to_string(),
+            ProviderConfig {
+                api_key: "test-key".into(),
+                base_url: base_url.clone(),
+                ..Default::default()
+            },
+        );
+        let models = Client::new(config).unwrap().list_models("anthropic").await.unwrap();
+        let ids: Vec<_> = models.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, ["m1", "m2"]);
+
+        let base_url = spawn_mock_server(vec![(
+            "GET",
+            "/v1/models",
+            r#"{"object":"list","data":[{"id":"llama3.2","object":"model","created":1,"owned_by":"library"}]}"#.to_string(),
+        )])
+        .await;
+        let models = mock_client(base_url).list_models("mock").await.unwrap();
+        assert_eq!(models[0].id, "llama3.2");
+        assert_eq!(models[0].owned_by.as_deref(), Some("library"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_emits_genai_spans() {
+        use futures::StreamExt;
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry::Value as OtelValue;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let completion = r#"{"id":"c1","object":"chat.completion","created":1,"model":"mock-model-0613","choices":[{"index":0,"message":{"role":"Assistant","content":"hi","name":null,"tool_calls":null,"tool_call_id":null},"finish_reason":"stop","logprobs":null}],"usage":{"prompt_tokens":11,"completion_tokens":3,"total_tokens":14},"system_fingerprint":null}"#;
+        let base_url = spawn_mock_server(vec![("POST", "/v1/chat/completions", completion.to_string())]).await;
+
+        let mut config = Config::default().with_provider(
+            "mock".to_string(),
+            ProviderConfig {
+                api_key: "test-key".into(),
+                base_url,
+                ..Default::default()
+            },
+        );
+        config.telemetry = TelemetryConfig {
+            capture_content: true,
+            redact: vec!["hunter2".to_string()],
+            max_content_chars: None,
+        };
+        let client = Client::new(config).unwrap();
+
+        let request = ChatCompletionRequest::new("mock-model", vec![Message::user("my password is hunter2")])
+            .with_temperature(0.2);
+        client.chat("mock", request).await.unwrap();
+
+        let stream_body = "data: {\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"role\":null,\"content\":\"Hel\",\"tool_calls\":null},\"finish_reason\":null}]}\n\ndata: {\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"role\":null,\"content\":null,\"tool_calls\":null},\"finish_reason\":\"stop\"}]}\n\n";
+        let base_url = spawn_mock_server(vec![("POST", "/v1/chat/completions", stream_body.to_string())]).await;
+        let stream_client = mock_client(base_url);
+        let stream = stream_client
+            .chat_stream("mock", ChatCompletionRequest::new("mock-model", vec![Message::user("hi")]))
+            .await
+            .unwrap();
+        let events: Vec<_> = stream.collect().await;
+        assert_eq!(events.len(), 3);
+
+        let _ = provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        let attr = |span: &opentelemetry_sdk::trace::SpanData, key: &str| {
+            span.attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.clone())
+        };
+
+        let chat = &spans[0];
+        assert_eq!(chat.name, "chat mock-model");
+        assert_eq!(attr(chat, "gen_ai.provider.name"), Some(OtelValue::from("mock")));
+        assert_eq!(attr(chat, "gen_ai.response.model"), Some(OtelValue::from("mock-model-0613")));
+        assert_eq!(attr(chat, "gen_ai.usage.input_tokens"), Some(OtelValue::I64(11)));
+        assert_eq!(attr(chat, "gen_ai.usage.output_tokens"), Some(OtelValue::I64(3)));
+        assert_eq!(attr(chat, "gen_ai.response.finish_reasons"), Some(OtelValue::from("stop")));
+        assert!(attr(chat, "gen_ai.client.operation.duration").is_some());
+        let input = attr(chat, "gen_ai.input.messages").unwrap().to_string();
+        assert!(input.contains("[REDACTED]") && !input.contains("hunter2"));
+
+        let streamed = &spans[1];
+        assert_eq!(attr(streamed, "gen_ai.request.stream"), Some(OtelValue::Bool(true)));
+        assert!(attr(streamed, "gen_ai.server.time_to_first_token").is_some());
+        assert_eq!(attr(streamed, "gen_ai.response.finish_reasons"), Some(OtelValue::from("stop")));
+        assert!(attr(streamed, "gen_ai.input.messages").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker_fails_fast_when_provider_is_down() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        drop(listener);
+
+        let mut config = Config::default().with_provider(
+            "down".to_string(),
+            ProviderConfig {
+                base_url,
+                ..Default::default()
+            },
+        );
+        config.circuit_breaker.failure_threshold = 2;
+        let client = Client::new(config).unwrap();
+
+        let request = ChatCompletionRequest::new("m", vec![Message::user("hi")]);
+        for _ in 0..2 {
+            assert!(matches!(client.chat("down", request.clone()).await, Err(Error::Http(_))));
+        }
+        let err = client.chat("down", request).await.unwrap_err();
+        assert!(matches!(err, Error::CircuitOpen { ref provider, .. } if provider == "down"));
+        assert!(err.retry_after().is_some());
+
+        let health = &client.provider_health()["down"];
+        assert_eq!(health.state, CircuitState::Open);
+        assert_eq!(health.total_requests, 2);
+        assert_eq!(health.consecutive_failures, 2);
+        assert_eq!(health.error_rate, 1.0);
+        assert!(!health.is_available());
+        assert_eq!(client.provider_health()["openai"].state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_circuit_breaker_half_open_probes() {
+        use crate::health::HealthTracker;
+        use std::time::Duration;
+
+        let tracker = HealthTracker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_secs: 1,
+            ..Default::default()
+        });
+        let down = || Error::Stream("connection reset".to_string());
+        let latency = Duration::from_millis(10);
+
+        // 限流不代表服务不可用，不触发熔断
+        let rate_limited = Error::ApiError(ProviderError::new(ErrorKind::RateLimit, 429, "slow down"));
+        for _ in 0..5 {
+            tracker.record("p", latency, Some(&rate_limited));
+        }
+        assert_eq!(tracker.snapshot("p").state, CircuitState::Closed);
+
+        tracker.record("p", latency, Some(&down()));
+        tracker.record("p", latency, Some(&down()));
+        assert!(matches!(tracker.check("p"), Err(Error::CircuitOpen { .. })));
+
+        std::thread::sleep(Duration::from_millis(1100));
+        assert_eq!(tracker.snapshot("p").state, CircuitState::HalfOpen);
+        assert!(tracker.check("p").is_ok());
+        assert!(tracker.check("p").is_err(), "only one probe at a time");
+        tracker.record("p", Duration::from_millis(30), None);
+
+        let health = tracker.snapshot("p");
+        assert_eq!(health.state, CircuitState::Closed);
+        assert_eq!(health.error_rate, 0.0);
+        assert_eq!(health.avg_latency_ms, Some(30));
+        assert_eq!(health.total_requests, 8);
+        assert_eq!(health.total_failures, 2);
+
+        tracker.record("p", latency, Some(&down()));
+        tracker.record("p", latency, Some(&down()));
+        std::thread::sleep(Duration::from_millis(1100));
+        assert!(tracker.check("p").is_ok());
+        tracker.record("p", latency, Some(&down()));
+        assert_eq!(tracker.snapshot("p").state, CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_provider_network_config() {
+        let defaults = NetworkConfig {
+            proxy: Some(ProxyConfig {
+                url: "http://proxy.corp:3128".to_string(),
+                no_proxy: vec!["localhost".to_string()],
+                username: None,
+                password: None,
+            }),
+            ca_certs: vec!["/etc/corp-ca.pem".into()],
+            connect_timeout_secs: Some(5),
+            ..Default::default()
+        };
+        let provider = NetworkConfig {
+            ca_certs: vec!["/etc/gateway-ca.pem".into()],
+            read_timeout_secs: Some(60),
+            ..Default::default()
+        };
+        let merged = provider.merged_with(&defaults);
+        assert_eq!(merged.proxy, defaults.proxy);
+        assert_eq!(merged.ca_certs.len(), 2);
+        assert_eq!(merged.connect_timeout_secs, Some(5));
+        assert_eq!(merged.read_timeout_secs, Some(60));
+
+        // HTTP 代理收到的是绝对 URI 形式的请求行
+        let proxy_url = spawn_mock_server(vec![(
+            "GET",
+            "http://gateway.internal.test/v1/models",
+            r#"{"data":[{"id":"internal-model"}]}"#.to_string(),
+        )])
+        .await;
+        let mut config = Config::default().with_provider(
+            "gateway".to_string(),
+            ProviderConfig {
+                base_url: "http://gateway.internal.test/v1".to_string(),
+                ..Default::default()
+            },
+        );
+        config.network.proxy = Some(ProxyConfig {
+            url: proxy_url.trim_end_matches("/v1").to_string(),
+            no_proxy: Vec::new(),
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+        });
+        let models = Client::new(config).unwrap().list_models("gateway").await.unwrap();
+        assert_eq!(models[0].id, "internal-model");
+
+        let invalid = |network: NetworkConfig| {
+            let config = Config::default().with_provider(
+                "gateway".to_string(),
+                ProviderConfig {
+                    network,
+                    ..Default::default()
+                },
+            );
+            matches!(Client::new(config), Err(Error::InvalidConfig(_)))
+        };
+        assert!(invalid(NetworkConfig {
+            client_cert: Some("/tmp/cert.pem".into()),
+            ..Default::default()
+        }));
+        assert!(invalid(NetworkConfig {
+            ca_certs: vec!["/nonexistent/ca.pem".into()],
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_overflow_reduction_strategies() {
+        use crate::message::{FunctionCall, ToolCall};
+        use crate::overflow::parse_token_counts;
+
+        let openai = ProviderError::new(
+            ErrorKind::ContextLengthExceeded,
+            400,
+            "This model's maximum context length is 8,192 tokens. However, you requested 9000 tokens (8000 in the messages, 1000 in the completion).",
+        );
+        assert_eq!(parse_token_counts(&openai), (Some(8192), Some(9000)));
+        let anthropic = ProviderError::new(
+            ErrorKind::ContextLengthExceeded,
+            400,
+            "prompt is too long: 210000 tokens > 200000 maximum",
+        );
+        assert_eq!(parse_token_counts(&anthropic), (Some(200000), Some(210000)));
+
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: FunctionCall {
+                name: "read".to_string(),
+                arguments: "{}".to_string(),
+            },
+        };
+        let mut request = ChatCompletionRequest::new(
+            "m",
+            vec![
+                Message::system("sys"),
+                Message::user("first"),
+                Message::assistant("").with_tool_calls(vec![call]),
+                Message::tool("x".repeat(100), "call_1"),
+                Message::assistant("done"),
+                Message::user("second"),
+                Message::assistant("ok"),
+                Message::user("third"),
+            ],
+        )
+        .with_max_tokens(4000);
+        let context = OverflowContext::default();
+
+        let truncated = TruncateToolOutputs { max_chars: 20 }.reduce(&mut request, &context);
+        assert!(matches!(truncated, Some(Reduction::TruncatedToolOutputs { count: 1, chars_removed: 80 })));
+        assert!(request.messages[3].content.contains("80 characters truncated"));
+        assert!(TruncateToolOutputs { max_chars: 20 }.reduce(&mut request, &context).is_none());
+
+        // 没有上限信息时每次只丢最旧的一轮，工具调用和结果一起丢
+        let Some(Reduction::DroppedMessages(dropped)) = DropOldestTurns::default().reduce(&mut request, &context) else {
+            panic!("expected dropped messages");
+        };
+        assert_eq!(dropped.len(), 4);
+        assert_eq!(request.messages[0].content, "sys");
+        assert_eq!(request.messages[1].content, "second");
+        assert!(DropOldestTurns::default().reduce(&mut request, &context).is_some());
+        assert!(DropOldestTurns::default().reduce(&mut request, &context).is_none());
+        assert_eq!(request.messages.len(), 2);
+
+        let context = OverflowContext {
+            context_limit: Some(1000),
+            estimated_prompt_tokens: 200,
+            ..Default::default()
+        };
+        let reduced = ReduceMaxTokens::default().reduce(&mut request, &context);
+        assert!(matches!(reduced, Some(Reduction::ReducedMaxTokens { from: Some(4000), to: 800 })));
+        assert!(ReduceMaxTokens::default().reduce(&mut request, &context).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chat_with_recovery_retries_after_overflow() {
+        let overflow = r#"{"error":{"message":"This model's maximum context length is 50 tokens. However, you requested 120 tokens.","type":"invalid_request_error","code":"context_length_exceeded"}}"#;
+        let completion = r#"{"id":"c1","object":"chat.completion","created":1,"model":"mock-model","choices":[{"index":0,"message":{"role":"Assistant","content":"fits now","name":null,"tool_calls":null,"tool_call_id":null},"finish_reason":"stop","logprobs":null}],"usage":{"prompt_tokens":1,"completion_tokens":1,"total_tokens":2},"system_fingerprint":null}"#;
+        let (base_url, received) = spawn_scripted_server(vec![
+            ("400 Bad Request", overflow.to_string()),
+            ("200 OK", completion.to_string()),
+        ])
+        .await;
+
+        let history = vec![
+            Message::user("old question ".repeat(20)),
+            Message::assistant("old answer ".repeat(20)),
+            Message::user("new question"),
+        ];
+        let request = ChatCompletionRequest::new("mock-model", history);
+        let client = mock_client(base_url);
+
+        let recovered = client
+            .chat_with_recovery("mock", request.clone(), &OverflowRecovery::new())
+            .await
+            .unwrap();
+        assert_eq!(recovered.response.choices[0].message.content, "fits now");
+        assert_eq!(recovered.request.messages.len(), 1);
+        assert!(matches!(&recovered.reductions[..], [Reduction::DroppedMessages(dropped)] if dropped.len() == 2));
+        {
+            let bodies = received.lock().unwrap();
+            assert_eq!(bodies.len(), 2);
+            assert!(!bodies[1].contains("old question"));
+        }
+
+        // 默认不开启恢复，错误原样返回
+        let (base_url, _) = spawn_scripted_server(vec![("400 Bad Request", overflow.to_string())]).await;
+        let err = mock_client(base_url).chat("mock", request).await.unwrap_err();
+        assert!(err.is_context_overflow());
+    }
+
+    #[test]
+    fn test_logprobs_parsing_and_best_choice() {
+        use crate::stream::{chunk_to_event, parse_sse_line};
+
+        let request = ChatCompletionRequest::new("gpt-4", vec![Message::user("classify")])
+            .with_n(2)
+            .with_logprobs(2)
+            .with_seed(7);
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["n"], 2);
+        assert_eq!(body["logprobs"], true);
+        assert_eq!(body["top_logprobs"], 2);
+        assert_eq!(body["seed"], 7);
+        let plain = serde_json::to_value(ChatCompletionRequest::new("gpt-4", vec![])).unwrap();
+        assert!(plain.get("n").is_none() && plain.get("logprobs").is_none());
+
+        let response: ChatCompletionResponse = serde_json::from_str(
+            r#"{"id":"c1","object":"chat.completion","created":1,"model":"gpt-4","choices":[
+                {"index":0,"message":{"role":"Assistant","content":"bug","name":null,"tool_calls":null,"tool_call_id":null},"finish_reason":"stop",
+                 "logprobs":{"content":[{"token":"bug","logprob":-1.2,"bytes":[98,117,103],"top_logprobs":[{"token":"bug","logprob":-1.2,"bytes":null},{"token":"ok","logprob":-0.4,"bytes":null}]}],"refusal":null}},
+                {"index":1,"message":{"role":"Assistant","content":"ok","name":null,"tool_calls":null,"tool_call_id":null},"finish_reason":"stop",
+                 "logprobs":{"content":[{"token":"ok","logprob":-0.1,"bytes":[111,107],"top_logprobs":[]}]}}],
+            "usage":{"prompt_tokens":1,"completion_tokens":2,"total_tokens":3},"system_fingerprint":null}"#,
+        )
+        .unwrap();
+
+        let first = response.choices[0].logprobs.as_ref().unwrap();
+        assert_eq!(first.tokens()[0].top_logprobs[1].token, "ok");
+        assert_eq!(first.tokens()[0].bytes.as_deref(), Some(&b"bug"[..]));
+        assert!((first.confidence().unwrap() - (-1.2f64).exp()).abs() < 1e-9);
+        assert!((first.perplexity().unwrap() - 1.2f64.exp()).abs() < 1e-9);
+
+        assert_eq!(response.best_choice().unwrap().index, 1);
+        let longest = response.best_choice_by(|c| Some(c.message.content.len() as f64)).unwrap();
+        assert_eq!(longest.index, 0);
+
+        // 没有 logprobs 时退回第一个候选
+        let mut without = response.clone();
+        without.choices.iter_mut().for_each(|c| c.logprobs = None);
+        assert_eq!(without.best_choice().unwrap().index, 0);
+
+        let chunk = parse_sse_line(
+            r#"data: {"id":"s1","object":"chat.completion.chunk","created":1,"model":"gpt-4","choices":[{"index":0,"delta":{"content":"ok"},"finish_reason":null,"logprobs":{"content":[{"token":"ok","logprob":-0.1,"bytes":null,"top_logprobs":[]}]}}]}"#,
+        )
+        .unwrap()
+        .unwrap();
+        let events = chunk_to_event(&chunk);
+        assert_eq!(events[0], StreamEvent::Token { index: 0, text: "ok".to_string() });
+        assert!(matches!(&events[1], StreamEvent::Logprobs { tokens, .. } if tokens[0].logprob == -0.1));
+    }
+
+    #[test]
+    fn test_tool_call_id_normalization() {
+        use crate::message::{FunctionCall, ToolCall};
+
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            tool_type: "function".to_string(),
+            function: FunctionCall {
+                name: "read_file".to_string(),
+                arguments: "{}".to_string(),
+            },
+        };
+        let history = vec![
+            Message::user("read both files"),
+            Message::assistant("").with_tool_calls(vec![call("toolu_01A"), call("call.with:colons")]),
+            Message::tool("a", "toolu_01A"),
+            Message::tool("b", "call.with:colons"),
+            Message::assistant("").with_tool_calls(vec![call("")]),
+            Message {
+                tool_call_id: None,
+                ..Message::tool("c", "")
+            },
+        ];
+
+        // Anthropic 只允许字母、数字、下划线和连字符
+        let mut anthropic = history.clone();
+        let mapping = normalize_tool_call_ids(&mut anthropic, ToolCallIdFormat::Anthropic);
+        assert_eq!(mapping.len(), 1);
+        let rewritten = &mapping["call.with:colons"];
+        assert!(rewritten.starts_with("toolu_") && ToolCallIdFormat::Anthropic.is_valid(rewritten));
+        assert_eq!(anthropic[1].tool_calls.as_ref().unwrap()[0].id, "toolu_01A");
+        assert_eq!(anthropic[3].tool_call_id.as_ref(), Some(rewritten));
+        let generated = anthropic[4].tool_calls.as_ref().unwrap()[0].id.clone();
+        assert!(!generated.is_empty());
+        assert_eq!(anthropic[5].tool_call_id.as_deref(), Some(generated.as_str()));
+        validate_tool_calls(&anthropic).unwrap();
+
+        // 同一段历史每次改写结果一致
+        let mut again = history.clone();
+        normalize_tool_call_ids(&mut again, ToolCallIdFormat::Anthropic);
+        assert_eq!(again[3].tool_call_id, anthropic[3].tool_call_id);
+
+        let mut mistral = history.clone();
+        normalize_tool_call_ids(&mut mistral, ToolCallIdFormat::Mistral);
+        let ids = mistral
+            .iter()
+            .flat_map(|m| m.tool_calls.iter().flatten().map(|c| c.id.clone()))
+            .collect::<Vec<_>>();
+        assert!(ids.iter().all(|id| id.len() == 9 && id.chars().all(|c| c.is_ascii_alphanumeric())));
+        assert_eq!(ids.iter().collect::<std::collections::HashSet<_>>().len(), 3);
+        assert_eq!(mistral[2].tool_call_id.as_ref(), Some(&ids[0]));
+        validate_tool_calls(&mistral).unwrap();
+
+        let missing_result = &anthropic[..5];
+        let err = validate_tool_calls(missing_result).unwrap_err();
+        assert!(matches!(err, Error::InvalidRequest(msg) if msg.contains(&generated)));
+        let orphan = vec![Message::user("hi"), Message::tool("x", "toolu_unknown")];
+        assert!(matches!(validate_tool_calls(&orphan), Err(Error::InvalidRequest(_))));
+
+        // 本地服务返回的 tool call 可能没有 id
+        let message: Message = serde_json::from_str(
+            r#"{"role":"Assistant","content":"","name":null,"tool_calls":[{"type":"function","function":{"name":"ls","arguments":"{}"}}],"tool_call_id":null}"#,
+        )
+        .unwrap();
+        assert_eq!(message.tool_calls.unwrap()[0].id, "");
+        let chunk = crate::stream::parse_sse_line(
+            r#"data: {"id":"s1","object":"chat.completion.chunk","created":1,"model":"local","choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"name":"ls","arguments":"{}"}}]},"finish_reason":null}]}"#,
+        )
+        .unwrap()
+        .unwrap();
+        let events = crate::stream::chunk_to_event(&chunk);
+        assert!(matches!(&events[0], StreamEvent::ToolCall { id, .. } if id.starts_with("pi_call_")));
+    }
+
+    #[test]
+    fn test_hosted_tools_and_citations() {
+        use crate::conversation::anthropic;
+        use crate::hosted::{anthropic_tool_value, apply_chat_tools};
+        use crate::responses::{build_responses_body, parse_responses_output, ResponsesStreamState};
+
+        let search = HostedTool::WebSearch(WebSearchOptions {
+            max_uses: Some(3),
+            allowed_domains: vec!["docs.rs".to_string()],
+            search_context_size: Some("low".to_string()),
+            ..Default::default()
+        });
+        let request = ChatCompletionRequest::new("gpt-4.1", vec![Message::user("latest tokio?")])
+            .with_hosted_tool(search.clone())
+            .with_hosted_tool(HostedTool::code_execution());
+
+        let body = build_responses_body(&request, &ResponsesConfig::default(), false).unwrap();
+        assert_eq!(body["tools"][0]["type"], "web_search");
+        assert_eq!(body["tools"][0]["filters"]["allowed_domains"][0], "docs.rs");
+        assert_eq!(body["tools"][1]["container"]["type"], "auto");
+        assert_eq!(body["include"][0], "web_search_call.action.sources");
+
+        let anthropic_tool = anthropic_tool_value(&search).unwrap();
+        assert_eq!(anthropic_tool["type"], "web_search_20250305");
+        assert_eq!(anthropic_tool["max_uses"], 3);
+        assert!(anthropic_tool_value(&HostedTool::file_search(vec!["vs_1".to_string()])).is_none());
+
+        let mut chat_body = serde_json::json!({});
+        apply_chat_tools(&mut chat_body, std::slice::from_ref(&search)).unwrap();
+        assert_eq!(chat_body["web_search_options"]["search_context_size"], "low");
+        let err = apply_chat_tools(&mut chat_body, &request.hosted_tools).unwrap_err();
+        assert!(matches!(err, Error::InvalidRequest(_)));
+
+        let output = serde_json::json!({
+            "id": "resp_1", "object": "response", "created_at": 1, "model": "gpt-4.1", "status": "completed",
+            "output": [
+                {"type": "web_search_call", "id": "ws_1", "status": "completed",
+                 "action": {"type": "search", "query": "tokio release", "sources": [{"type": "url", "url": "https://docs.rs/tokio"}]}},
+                {"type": "message", "role": "assistant", "content": [
+                    {"type": "output_text", "text": "Intro. ", "annotations": []},
+                    {"type": "output_text", "text": "Tokio 1.x.", "annotations": [
+                        {"type": "url_citation", "start_index": 0, "end_index": 10, "url": "https://docs.rs/tokio", "title": "tokio"}]}]}
+            ],
+            "usage": {"input_tokens": 1, "output_tokens": 1, "total_tokens": 2}
+        });
+        let message = parse_responses_output(&output).unwrap().choices.remove(0).message;
+        let calls = message.hosted_tool_calls.as_ref().unwrap();
+        assert_eq!(calls[0].name, "web_search");
+        assert_eq!(calls[0].input["query"], "tokio release");
+        assert_eq!(calls[0].sources[0].url, "https://docs.rs/tokio");
+        let citation = &message.citations.as_ref().unwrap()[0];
+        assert_eq!((citation.start_index, citation.end_index), (Some(7), Some(17)));
+        assert_eq!(&message.content[7..17], "Tokio 1.x.");
+
+        let mut state = ResponsesStreamState::default();
+        let events = state.handle(
+            b"data: {\"type\":\"response.output_item.done\",\"item\":{\"type\":\"web_search_call\",\"id\":\"ws_2\",\"status\":\"completed\",\"action\":{\"query\":\"q\"}}}\n\ndata: {\"type\":\"response.output_text.annotation.added\",\"annotation\":{\"type\":\"url_citation\",\"url\":\"https://a.dev\",\"start_index\":0,\"end_index\":3}}\n\n",
+        );
+        let events: Vec<_> = events.into_iter().map(|e| e.unwrap()).collect();
+        assert!(matches!(&events[0], StreamEvent::HostedToolCall(call) if call.id == "ws_2"));
+        assert!(matches!(&events[1], StreamEvent::Citation(c) if c.url.as_deref() == Some("https://a.dev")));
+
+        // Anthropic 的 server_tool_use / web_search_tool_result 进入消息模型后可以原样回放
+        let raw = serde_json::json!({"role": "assistant", "content": [
+            {"type": "server_tool_use", "id": "srvtoolu_1", "name": "web_search", "input": {"query": "tokio"}},
+            {"type": "web_search_tool_result", "tool_use_id": "srvtoolu_1", "content": [
+                {"type": "web_search_result", "url": "https://tokio.rs", "title": "Tokio", "encrypted_content": "abc", "page_age": "1 day"}]},
+            {"type": "text", "text": "Tokio is async.", "citations": [
+                {"type": "web_search_result_location", "url": "https://tokio.rs", "title": "Tokio", "encrypted_index": "idx", "cited_text": "async"}]}
+        ]});
+        let conversation = anthropic::from_messages(None, std::slice::from_ref(&raw));
+        let messages = ConversationMessage::to_messages(&conversation);
+        let call = &messages[0].hosted_tool_calls.as_ref().unwrap()[0];
+        assert_eq!(call.sources[0].encrypted_content.as_deref(), Some("abc"));
+        assert_eq!(messages[0].citations.as_ref().unwrap()[0].encrypted_index.as_deref(), Some("idx"));
+
+        let (_, replayed) = anthropic::to_messages(&ConversationMessage::from_messages(&messages));
+        assert_eq!(replayed[0], raw);
+    }
+
+    #[tokio::test]
+    async fn test_llm_backend_trait_and_embeddings() {
+        use futures::StreamExt;
+
+        struct EchoBackend;
+
+        #[async_trait::async_trait]
+        impl LlmBackend for EchoBackend {
+            async fn chat(&self, _provider: &str, request: ChatCompletionRequest) -> Result<ChatCompletionResponse> {
+                let last = request.messages.last().map(|m| m.content.clone()).unwrap_or_default();
+                Ok(ChatCompletionResponse {
+                    id: "echo".to_string(),
+                    object: "chat.completion".to_string(),
+                    created: 0,
+                    model: request.model,
+                    choices: vec![CompletionChoice {
+                        index: 0,
+                        message: Message::assistant(last),
+                        finish_reason: Some("stop".to_string()),
+                        logprobs: None,
+                    }],
+                    usage: Default::default(),
+                    system_fingerprint: None,
+                })
+            }
+
+            async fn chat_stream(&self, _provider: &str, _request: ChatCompletionRequest) -> Result<EventStream> {
+                let events = vec![Ok(StreamEvent::Token { index: 0, text: "hi".to_string() }), Ok(StreamEvent::Done)];
+                Ok(Box::pin(futures::stream::iter(events)))
+            }
+        }
+
+        let backend: std::sync::Arc<dyn LlmBackend> = std::sync::Arc::new(EchoBackend);
+        let request = ChatCompletionRequest::new("local", vec![Message::user("ping")]);
+        let response = backend.chat("local", request.clone()).await.unwrap();
+        assert_eq!(response.choices[0].message.content, "ping");
+        let events = backend.chat_stream("local", request).await.unwrap().collect::<Vec<_>>().await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(backend.embed("local", EmbeddingRequest::new("e", vec![])).await, Err(Error::UnsupportedProvider(_))));
+
+        let base_url = spawn_mock_server(vec![(
+            "POST",
+            "/v1/embeddings",
+            r#"{"object":"list","model":"text-embedding-3-small","data":[{"object":"embedding","index":1,"embedding":[0.5,0.25]},{"object":"embedding","index":0,"embedding":[1.0,0.0]}],"usage":{"prompt_tokens":4,"total_tokens":4}}"#.to_string(),
+        )])
+        .await;
+        let client: Box<dyn LlmBackend> = Box::new(mock_client(base_url));
+        let response = client
+            .embed("mock", EmbeddingRequest::new("text-embedding-3-small", vec!["a".into(), "b".into()]))
+            .await
+            .unwrap();
+        assert_eq!(response.vectors(), vec![&[1.0f32, 0.0][..], &[0.5, 0.25][..]]);
+        assert_eq!(response.usage.total_tokens, 4);
+
+        let base_url = spawn_mock_server(vec![(
+            "POST",
+            "/v1/models/text-embedding-004:batchEmbedContents",
+            r#"{"embeddings":[{"values":[0.1,0.2,0.3]}]}"#.to_string(),
+        )])
+        .await;
+        let config = Config::default().with_provider(
+            "google".to_string(),
+            ProviderConfig {
+                api_key: "test-key".into(),
+                base_url,
+                ..Default::default()
+            },
+        );
+        let response = Client::new(config)
+            .unwrap()
+            .embed("google", EmbeddingRequest::new("text-embedding-004", vec!["a".into()]))
+            .await
+            .unwrap();
+        assert_eq!(response.data[0].embedding, vec![0.1, 0.2, 0.3]);
+    }
+
+    #[test]
+    fn test_secret_string_redaction_and_sources() {
+        let secret = SecretString::new("sk-live-123");
+        assert_eq!(secret.expose_secret(), "sk-live-123");
+        assert_eq!(format!("{}", secret), "[REDACTED]");
+        assert!(!format!("{:?}", secret).contains("sk-live-123"));
+        // 直接写入的密钥不能静默写成读不回来的占位符
+        assert!(serde_json::to_value(&secret).is_err());
+        assert!(serde_json::from_str::<SecretString>(r#""[REDACTED]""#).is_err());
+
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct Saved {
+            #[serde(serialize_with = "crate::secret::serialize_exposed")]
+            key: SecretString,
+            #[serde(serialize_with = "crate::secret::serialize_exposed_map")]
+            keys: std::collections::HashMap<String, SecretString>,
+        }
+        let saved = Saved {
+            key: secret.clone(),
+            keys: [("openai".to_string(), secret.clone())].into_iter().collect(),
+        };
+        let written = serde_json::to_string(&saved).unwrap();
+        let loaded: Saved = serde_json::from_str(&written).unwrap();
+        assert_eq!(loaded.key.expose_secret(), "sk-live-123");
+        assert_eq!(loaded.keys["openai"].expose_secret(), "sk-live-123");
+        assert_eq!(serde_json::from_str::<SecretString>(r#""sk-plain""#).unwrap().expose_secret(), "sk-plain");
+
+        let config = ProviderConfig {
+            api_key: secret.clone(),
+            ..Default::default()
+        };
+        assert!(!format!("{:?}", config).contains("sk-live-123"));
+        assert!(serde_json::to_string(&config).is_err());
+
+        std::env::set_var("PI_AI_TEST_SECRET", "from-env");
+        let from_env = SecretString::from_env("PI_AI_TEST_SECRET").unwrap();
+        assert_eq!(from_env.expose_secret(), "from-env");
+        let written = serde_json::to_value(&from_env).unwrap();
+        assert_eq!(written, serde_json::json!({ "env": "PI_AI_TEST_SECRET" }));
+        assert_eq!(serde_json::from_value::<SecretString>(written).unwrap(), from_env);
+        assert!(SecretString::from_env("PI_AI_TEST_SECRET_MISSING").is_err());
+
+        let path = std::env::temp_dir().join(format!("pi-ai-secret-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let from_file = SecretString::from_file(&path).unwrap();
+        assert_eq!(from_file.expose_secret(), "from-file");
+        assert_eq!(from_file.source(), &SecretSource::File(path.clone()));
+        std::fs::remove_file(&path).unwrap();
+
+        if cfg!(unix) {
+            let from_command = SecretString::from_command("echo from-command").unwrap();
+            assert_eq!(from_command.expose_secret(), "from-command");
+            assert!(SecretString::from_command("exit 3").is_err());
+        }
+    }
+
+    #[test]
+    fn test_backend_default_provider_and_model() {
+        let config = Config::default()
+            .with_provider(
+                "anthropic".to_string(),
+                ProviderConfig {
+                    api_key: "test-key".into(),
+                    model: "claude-3-5-sonnet-latest".to_string(),
+                    ..Default::default()
+                },
+            )
+            .with_provider("bare".to_string(), ProviderConfig::default())
+            .with_default_provider("anthropic".to_string());
+        let backend: Box<dyn LlmBackend> = Box::new(Client::new(config).unwrap());
+
+        assert_eq!(backend.default_provider().as_deref(), Some("anthropic"));
+        assert_eq!(backend.default_model("anthropic").as_deref(), Some("claude-3-5-sonnet-latest"));
+        assert_eq!(backend.default_model("bare"), None);
+        assert_eq!(backend.default_model("missing"), None);
+    }
+
+    #[test]
+    fn test_chat_stream_assembles_tool_call_fragments() {
+        use crate::stream::ChatStreamState;
+
+        let mut state = ChatStreamState::default();
+        let chunk = |delta: &str, finish: &str| {
+            format!(
+                "data: {{\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{{\"index\":0,\"delta\":{},\"finish_reason\":{}}}]}}\n\n",
+                delta, finish
+            )
+        };
+        let body = [
+            chunk(r#"{"content":"Let me look"}"#, "null"),
+            chunk(r#"{"tool_calls":[{"index":0,"id":"call_a","type":"function","function":{"name":"read","arguments":""}}]}"#, "null"),
+            chunk(r#"{"tool_calls":[{"index":0,"function":{"arguments":"{\"path\":"}}]}"#, "null"),
+            chunk(r#"{"tool_calls":[{"index":1,"id":"call_b","type":"function","function":{"name":"ls","arguments":"{}"}}]}"#, "null"),
+            chunk(r#"{"tool_calls":[{"index":0,"function":{"arguments":"\"a.rs\"}"}}]}"#, "null"),
+            chunk("{}", "\"tool_calls\""),
+            "data: {\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[],\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":4,\"total_tokens\":13}}\n\ndata: [DONE]\n\n".to_string(),
+        ]
+        .concat();
+
+        // 按任意位置切开，模拟跨 chunk 的 SSE 行
+        let (head, tail) = body.as_bytes().split_at(101);
+        let mut events = state.handle(head);
+        events.extend(state.handle(tail));
+        let events: Vec<_> = events.into_iter().map(|e| e.unwrap()).collect();
+
+        assert_eq!(events[0], StreamEvent::Token { index: 0, text: "Let me look".to_string() });
+        assert_eq!(
+            events[1],
+            StreamEvent::ToolCallDelta {
+                choice: 0,
+                index: 0,
+                id: Some("call_a".to_string()),
+                name: Some("read".to_string()),
+                arguments: String::new(),
+            }
+        );
+        let deltas = events
+            .iter()
+            .filter(|e| matches!(e, StreamEvent::ToolCallDelta { index: 0, .. }))
+            .count();
+        assert_eq!(deltas, 3);
+
+        let calls: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::ToolCall { id, name, arguments } => Some((id.as_str(), name.as_str(), arguments.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(calls, vec![("call_a", "read", r#"{"path":"a.rs"}"#), ("call_b", "ls", "{}")]);
+
+        let usage = events.iter().find_map(|e| match e {
+            StreamEvent::Usage(usage) => Some(usage.total_tokens),
+            _ => None,
+        });
+        assert_eq!(usage, Some(13));
+        assert_eq!(events.last(), Some(&StreamEvent::Done));
+        // 结束原因和 usage 都在唯一的 Done 之前
+        assert_eq!(events.iter().filter(|e| **e == StreamEvent::Done).count(), 1);
+        assert!(events.contains(&StreamEvent::FinishReason("tool_calls".to_string())));
+    }
+
+    #[test]
+    fn test_chat_stream_keeps_choices_apart() {
+        use crate::stream::ChatStreamState;
+
+        let chunk = |index: u32, delta: &str, finish: &str| {
+            format!(
+                "data: {{\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{{\"index\":{},\"delta\":{},\"finish_reason\":{}}}]}}\n\n",
+                index, delta, finish
+            )
+        };
+        let body = [
+            chunk(0, r#"{"content":"A"}"#, "null"),
+            chunk(1, r#"{"content":"B"}"#, "null"),
+            chunk(0, r#"{"tool_calls":[{"index":0,"id":"call_a","type":"function","function":{"name":"read","arguments":"{\"p\":"}}]}"#, "null"),
+            chunk(1, r#"{"tool_calls":[{"index":0,"id":"call_b","type":"function","function":{"name":"ls","arguments":"{}"}}]}"#, "null"),
+            chunk(0, r#"{"tool_calls":[{"index":0,"function":{"arguments":"1}"}}]}"#, "null"),
+            chunk(1, "{}", "\"tool_calls\""),
+            chunk(0, "{}", "\"tool_calls\""),
+            "data: [DONE]\n\n".to_string(),
+        ]
+        .concat();
+
+        let mut state = ChatStreamState::default();
+        let events: Vec<_> = state.handle(body.as_bytes()).into_iter().map(|e| e.unwrap()).collect();
+
+        assert_eq!(events[0], StreamEvent::Token { index: 0, text: "A".to_string() });
+        assert_eq!(events[1], StreamEvent::Token { index: 1, text: "B".to_string() });
+        assert!(events.contains(&StreamEvent::ToolCallDelta {
+            choice: 1,
+            index: 0,
+            id: Some("call_b".to_string()),
+            name: Some("ls".to_string()),
+            arguments: "{}".to_string(),
+        }));
+        let calls: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::ToolCall { id, arguments, .. } => Some((id.as_str(), arguments.as_str())),
+                _ => None,
+            })
+            .collect();
+        // choice 1 先结束，它的调用先给出，且不会吞掉 choice 0 的分片
+        assert_eq!(calls, vec![("call_b", "{}"), ("call_a", r#"{"p":1}"#)]);
+    }
+
+    #[test]
+    fn test_truncate_middle_keeps_head_and_tail() {
+        let mut content = format!("{}{}", "a".repeat(50), "b".repeat(50));
+        assert_eq!(crate::overflow::truncate_middle(&mut content, 20), Some(80));
+        assert!(content.starts_with(&"a".repeat(10)));
+        assert!(content.ends_with(&"b".repeat(10)));
+        assert!(content.contains("80 characters truncated"));
+
+        // 已截断过或本来就不长的内容保持不变
+        let before = content.clone();
+        assert_eq!(crate::overflow::truncate_middle(&mut content, 20), None);
+        assert_eq!(content, before);
+        let mut short = "中文内容".to_string();
+        assert_eq!(crate::overflow::truncate_middle(&mut short, 4), None);
+    }
+
+    #[test]
+    fn test_fim_stream_reads_chat_shaped_deltas() {
+        use crate::fim::FimStreamState;
+
+        let mut state = FimStreamState::new(Vec::new());
+        let chunk = concat!(
+            "data: {\"choices\":[{\"index\":0,\"text\":\"a + \",\"finish_reason\":null}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"b\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"\"},\"finish_reason\":\"stop\"}]}\n\n",
+        );
+        let tokens: Vec<String> = state
+            .handle(Some(Ok(chunk.as_bytes().to_vec())))
+            .into_iter()
+            .filter_map(|event| match event {
+                Ok(StreamEvent::Token { text, .. }) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(tokens.concat(), "a + b");
+    }
+
+    #[tokio::test]
+    async fn test_fim_goes_through_span_and_health_tracking() {
+        use crate::fim::FimRequest;
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry::Value as OtelValue;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let completion = r#"{"id":"f1","model":"mock-coder","choices":[{"index":0,"text":"a + b","finish_reason":"stop"}],"usage":{"prompt_tokens":6,"completion_tokens":2,"total_tokens":8}}"#;
+        let base_url = spawn_mock_server(vec![("POST", "/v1/completions", completion.to_string())]).await;
+        let client = mock_client(base_url);
+
+        let response = client
+            .complete_fim("mock", FimRequest::new("mock-coder", "fn add(a, b) { ").with_suffix(" }"))
+            .await
+            .unwrap();
+        assert_eq!(response.text(), "a + b");
+        assert_eq!(client.provider_health()["mock"].total_requests, 1);
+
+        let _ = provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        let attr = |key: &str| {
+            spans[0].attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.clone())
+        };
+        assert_eq!(spans[0].name, "text_completion mock-coder");
+        assert_eq!(attr("gen_ai.operation.name"), Some(OtelValue::from("text_completion")));
+        assert_eq!(attr("gen_ai.usage.output_tokens"), Some(OtelValue::I64(2)));
+        assert_eq!(attr("gen_ai.response.finish_reasons"), Some(OtelValue::from("stop")));
+    }
+
+    #[test]
+    fn test_responses_chaining_sends_only_new_items() {
+        use crate::responses::build_responses_body;
+
+        let request = ChatCompletionRequest::new(
+            "gpt-4.1",
+            vec![
+                Message::system("sys"),
+                Message::user("first"),
+                Message::assistant("answer"),
+                Message::user("second"),
+            ],
+        );
+        let body = build_responses_body(&request, &ResponsesConfig::default(), false).unwrap();
+        assert_eq!(body["input"].as_array().unwrap().len(), 4);
+
+        let chained = request.with_previous_response_id("resp_1");
+        let body = build_responses_body(&chained, &ResponsesConfig::default(), false).unwrap();
+        let input = body["input"].as_array().unwrap();
+        assert_eq!(input.len(), 1);
+        assert_eq!(input[0]["content"][0]["text"], "second");
+        assert_eq!(body["previous_response_id"], "resp_1");
+    }
+
+    #[test]
+    fn test_conversation_openai_reasoning_round_trip() {
+        use crate::conversation::openai;
+
+        let messages = vec![
+            serde_json::json!({"role": "user", "content": "2+2?"}),
+            serde_json::json!({"role": "assistant", "content": "4", "reasoning_content": "Add two and two."}),
+        ];
+
+        let conversation = openai::from_messages(&messages);
+        assert!(matches!(
+            &conversation[1].content[0],
+            ContentBlock::Reasoning { text, .. } if text == "Add two and two."
+        ));
+        assert_eq!(openai::to_messages_for(&conversation, openai::Dialect::DeepSeek), messages);
+
+        // OpenAI 不接受输入里的 reasoning_content 和 annotations
+        let mut conversation = conversation;
+        conversation[1].content.push(ContentBlock::Text {
+            text: String::new(),
+            citations: vec![Citation {
+                kind: "url_citation".to_string(),
+                url: Some("https://a.dev".to_string()),
+                ..Default::default()
+            }],
+        });
+        let plain = openai::to_messages(&conversation);
+        assert_eq!(plain[1], serde_json::json!({"role": "assistant", "content": "4"}));
+    }
+
+    #[tokio::test]
+    async fn test_streamed_span_records_usage_and_finish_reason() {
+        use futures::StreamExt;
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry::Value as OtelValue;
+        use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let stream_body = concat!(
+            "data: {\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"length\"}]}\n\n",
+            "data: {\"id\":\"s\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[],\"usage\":{\"prompt_tokens\":8,\"completion_tokens\":1,\"total_tokens\":9}}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let base_url = spawn_mock_server(vec![("POST", "/v1/chat/completions", stream_body.to_string())]).await;
+        let client = mock_client(base_url);
+        let mut stream = client
+            .chat_stream("mock", ChatCompletionRequest::new("mock-model", vec![Message::user("hi")]))
+            .await
+            .unwrap();
+        // 读到 Done 就停下的调用方也能拿到 usage
+        while let Some(event) = stream.next().await {
+            if event.unwrap() == StreamEvent::Done {
+                break;
+            }
+        }
+        drop(stream);
+
+        let _ = provider.force_flush();
+        let spans = exporter.get_finished_spans().unwrap();
+        let attr = |key: &str| {
+            spans[0].attributes.iter().find(|kv| kv.key.as_str() == key).map(|kv| kv.value.clone())
+        };
+        assert_eq!(attr("gen_ai.usage.input_tokens"), Some(OtelValue::I64(8)));
+        assert_eq!(attr("gen_ai.usage.output_tokens"), Some(OtelValue::I64(1)));
+        assert_eq!(attr("gen_ai.response.finish_reasons"), Some(OtelValue::from("length")));
+    }
+
+    #[test]
+    fn test_fill_missing_tool_results() {
+        use crate::message::{FunctionCall, ToolCall};
+
+        let call = |id: &str| ToolCall {
+            id: id.to_string(),
+            tool_type: "function".to_string(),
+            function: FunctionCall {
+                name: "run".to_string(),
+                arguments: "{}".to_string(),
+            },
+        };
+        let mut messages = vec![
+            Message::user("go"),
+            Message::assistant("").with_tool_calls(vec![call("a"), call("b")]),
+            Message::tool("ran a", "a"),
+            Message::user("never mind"),
+            Message::assistant("").with_tool_calls(vec![call("c")]),
+        ];
+        assert!(validate_tool_calls(&messages).is_err());
+
+        assert_eq!(fill_missing_tool_results(&mut messages), 2);
+        assert_eq!(messages.len(), 7);
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("b"));
+        assert_eq!(messages[4].content, "never mind");
+        assert_eq!(messages[6].tool_call_id.as_deref(), Some("c"));
+        validate_tool_calls(&messages).unwrap();
+        assert_eq!(fill_missing_tool_results(&mut messages), 0);
+    }
 }
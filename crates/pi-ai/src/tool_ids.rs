@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::conversation::content::SYNTHETIC_ID_PREFIX;
+use crate::error::{Error, Result};
+use crate::message::{Message, MessageRole};
+use crate::provider::{Provider, ProviderType};
+
+const OPENAI_MAX_ID_LEN: usize = 40;
+const MISTRAL_ID_LEN: usize = 9;
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+// 补给没有结果的 tool call 的占位内容
+pub const NOT_EXECUTED: &str = "Tool call was not executed.";
+
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolCallIdFormat {
+    // 只补齐缺失的 id，不改写已有 id
+    Passthrough,
+    // 最长 40 个字符
+    OpenAi,
+    // ^[a-zA-Z0-9_-]+$
+    Anthropic,
+    // 恰好 9 个字母或数字
+    Mistral,
+}
+
+impl ToolCallIdFormat {
+    pub fn for_provider(provider: &Provider) -> Self {
+        if let Some(format) = provider.config.tool_call_ids {
+            return format;
+        }
+        match provider.provider_type {
+            ProviderType::OpenAI | ProviderType::Azure => ToolCallIdFormat::OpenAi,
+            ProviderType::Anthropic => ToolCallIdFormat::Anthropic,
+            ProviderType::Google | ProviderType::Custom => ToolCallIdFormat::Passthrough,
+        }
+    }
+
+    pub fn is_valid(&self, id: &str) -> bool {
+        if id.is_empty() {
+            return false;
+        }
+        match self {
+            ToolCallIdFormat::Passthrough => true,
+            ToolCallIdFormat::OpenAi => id.len() <= OPENAI_MAX_ID_LEN,
+            ToolCallIdFormat::Anthropic => id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+            ToolCallIdFormat::Mistral => {
+                id.len() == MISTRAL_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric())
+            }
+        }
+    }
+
+    // 由原 id 确定性地生成新 id，同一段历史每次发送得到的结果一致
+    fn rewrite(&self, id: &str, salt: u64) -> String {
+        let hash = encode_base62(fnv1a(id.as_bytes(), salt));
+        match self {
+            ToolCallIdFormat::Passthrough if salt == 0 => id.to_string(),
+            ToolCallIdFormat::Passthrough => format!("{}_{}", id, salt),
+            ToolCallIdFormat::OpenAi => format!("call_{}", hash),
+            ToolCallIdFormat::Anthropic => format!("toolu_{}", hash),
+            ToolCallIdFormat::Mistral => hash.chars().cycle().take(MISTRAL_ID_LEN).collect(),
+        }
+    }
+}
+
+// 给响应里缺少 id 的 tool call 用，保证进程内唯一
+pub fn generate_tool_call_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    let count = ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}{}", SYNTHETIC_ID_PREFIX, encode_base62(nanos ^ count.rotate_left(32)))
+}
+
+// 补齐缺失的 id，并把不符合目标格式的 id 连同对应的 tool 结果一起改写；返回旧 id 到新 id 的映射
+pub fn normalize_tool_call_ids(messages: &mut [Message], format: ToolCallIdFormat) -> HashMap<String, String> {
+    let mut mapping: HashMap<String, String> = HashMap::new();
+    let mut used = messages
+        .iter()
+        .flat_map(|m| m.tool_calls.iter().flatten().map(|c| c.id.clone()))
+        .filter(|id| format.is_valid(id))
+        .collect::<HashSet<_>>();
+    let assign = |original: String, used: &mut HashSet<String>| -> String {
+        let mut salt = 0;
+        let mut id = format.rewrite(&original, salt);
+        while !format.is_valid(&id) || used.contains(&id) {
+            salt += 1;
+            id = format.rewrite(&original, salt);
+        }
+        used.insert(id.clone());
+        id
+    };
+
+    // 没有 id 的 tool 结果按顺序对应上一条 assistant 消息里没有 id 的调用
+    let mut unnamed_calls = Vec::new();
+    for (index, message) in messages.iter_mut().enumerate() {
+        match message.role {
+            MessageRole::Assistant => {
+                unnamed_calls.clear();
+                for (call_index, call) in message.tool_calls.iter_mut().flatten().enumerate() {
+                    if call.id.is_empty() {
+                        let placeholder = format!("{}{}_{}", SYNTHETIC_ID_PREFIX, index, call_index);
+                        call.id = if format.is_valid(&placeholder) {
+                            used.insert(placeholder.clone());
+                            placeholder
+                        } else {
+                            assign(placeholder, &mut used)
+                        };
+                        unnamed_calls.push(call.id.clone());
+                    } else if !format.is_valid(&call.id) {
+                        let rewritten = match mapping.get(&call.id) {
+                            Some(id) => id.clone(),
+                            None => assign(call.id.clone(), &mut used),
+                        };
+                        mapping.insert(call.id.clone(), rewritten.clone());
+                        call.id = rewritten;
+                    }
+                }
+                unnamed_calls.reverse();
+            }
+            MessageRole::Tool => match message.tool_call_id.as_deref() {
+                None | Some("") => message.tool_call_id = unnamed_calls.pop(),
+                Some(id) => {
+                    if let Some(rewritten) = mapping.get(id) {
+                        message.tool_call_id = Some(rewritten.clone());
+                    }
+                }
+            },
+            _ => {}
+        }
+    }
+
+    mapping
+}
+
+// 每个 tool call 都要有紧随其后的结果，每个结果也必须对应上一条 assistant 消息里的调用
+pub fn validate_tool_calls(messages: &[Message]) -> Result<()> {
+    let mut pending: Vec<&str> = Vec::new();
+
+    for message in messages {
+        if message.role == MessageRole::Tool {
+            let id = message
+                .tool_call_id
+                .as_deref()
+                .filter(|id| !id.is_empty())
+                .ok_or_else(|| Error::InvalidRequest("Tool message is missing tool_call_id".to_string()))?;
+            match pending.iter().position(|pending_id| *pending_id == id) {
+                Some(position) => {
+                    pending.remove(position);
+                }
+                None => {
+                    return Err(Error::InvalidRequest(format!(
+                        "Tool result {} does not match any preceding tool call",
+                        id
+                    )))
+                }
+            }
+            continue;
+        }
+
+        if let Some(id) = pending.first() {
+            return Err(Error::InvalidRequest(format!("Tool call {} has no matching tool result", id)));
+        }
+        if message.role == MessageRole::Assistant {
+            pending = message.tool_calls.iter().flatten().map(|c| c.id.as_str()).collect();
+        }
+    }
+
+    match pending.first() {
+        Some(id) => Err(Error::InvalidRequest(format!("Tool call {} has no matching tool result", id))),
+        None => Ok(()),
+    }
+}
+
+// 给没有结果的 tool call 补一条占位结果，放在该调用之后那段 tool 消息的末尾。
+// 调用方还没执行（等待审批）或中途放弃的调用会出现这种情况；返回补上的条数
+pub fn fill_missing_tool_results(messages: &mut Vec<Message>) -> usize {
+    let mut filled = 0;
+    let mut i = 0;
+    while i < messages.len() {
+        if messages[i].role != MessageRole::Assistant {
+            i += 1;
+            continue;
+        }
+        let calls: Vec<String> = messages[i].tool_calls.iter().flatten().map(|c| c.id.clone()).collect();
+        let mut end = i + 1;
+        while end < messages.len() && messages[end].role == MessageRole::Tool {
+            end += 1;
+        }
+        let answered: Vec<&str> = messages[i + 1..end]
+            .iter()
+            .filter_map(|m| m.tool_call_id.as_deref())
+            .collect();
+        let missing: Vec<String> = calls
+            .into_iter()
+            .filter(|id| !answered.contains(&id.as_str()))
+            .collect();
+        for (offset, id) in missing.iter().enumerate() {
+            messages.insert(end + offset, Message::tool(NOT_EXECUTED, id.clone()));
+        }
+        filled += missing.len();
+        i = end + missing.len();
+    }
+    filled
+}
+
+pub(crate) fn prepare_messages(provider: &Provider, messages: &mut Vec<Message>) -> Result<()> {
+    let mapping = normalize_tool_call_ids(messages, ToolCallIdFormat::for_provider(provider));
+    if !mapping.is_empty() {
+        tracing::debug!("Rewrote {} tool call ids for provider {}", mapping.len(), provider.name);
+    }
+    let filled = fill_missing_tool_results(messages);
+    if filled > 0 {
+        tracing::warn!("Sending {} unanswered tool calls to {} as not executed", filled, provider.name);
+    }
+    validate_tool_calls(messages)
+}
+
+pub(crate) fn fill_missing_ids(message: &mut Message) {
+    for call in message.tool_calls.iter_mut().flatten() {
+        if call.id.is_empty() {
+            call.id = generate_tool_call_id();
+        }
+    }
+}
+
+fn fnv1a(bytes: &[u8], salt: u64) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64 ^ salt;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn encode_base62(mut value: u64) -> String {
+    let mut out = Vec::new();
+    loop {
+        out.push(BASE62[(value % 62) as usize]);
+        value /= 62;
+        if value == 0 {
+            break;
+        }
+    }
+    out.reverse();
+    String::from_utf8(out).unwrap_or_default()
+}
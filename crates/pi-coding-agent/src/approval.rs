@@ -0,0 +1,54 @@
+use async_trait::async_trait;
+use pi_agent_core::{AgentError, AgentResult, ApprovalDecision, ApprovalHandler, ApprovalPolicy, ApprovalRequest};
+use std::io::Write;
+
+// 只读工具直接放行，其余在终端询问
+pub fn default_policy() -> ApprovalPolicy {
+    ApprovalPolicy::new().allow("file_read").allow("file_search")
+}
+
+pub struct TerminalApproval;
+
+#[async_trait]
+impl ApprovalHandler for TerminalApproval {
+    async fn review(&self, request: &ApprovalRequest) -> AgentResult<ApprovalDecision> {
+        let request = request.clone();
+        tokio::task::spawn_blocking(move || prompt(&request))
+            .await
+            .map_err(|e| AgentError::Other(e.to_string()))?
+    }
+}
+
+fn prompt(request: &ApprovalRequest) -> AgentResult<ApprovalDecision> {
+    println!("\nThe assistant wants to run {} with {}", request.tool_name, request.arguments);
+    print!("Allow? [y]es / [n]o [reason] / [a]lways / [e]dit: ");
+    std::io::stdout().flush()?;
+
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let input = input.trim();
+    let (choice, rest) = input.split_once(' ').unwrap_or((input, ""));
+
+    let decision = match choice {
+        "y" | "yes" => ApprovalDecision::Approve,
+        "a" | "always" => ApprovalDecision::ApproveAlways,
+        "e" | "edit" => {
+            print!("Arguments: ");
+            std::io::stdout().flush()?;
+            let mut arguments = String::new();
+            std::io::stdin().read_line(&mut arguments)?;
+            ApprovalDecision::Edit {
+                arguments: arguments.trim().to_string(),
+            }
+        }
+        _ => ApprovalDecision::Deny {
+            reason: if rest.is_empty() {
+                "rejected by the user".to_string()
+            } else {
+                rest.to_string()
+            },
+        },
+    };
+
+    Ok(decision)
+}
@@ -1,10 +1,11 @@
+use crate::approval::{self, TerminalApproval};
 use crate::cli::{Cli, Commands};
 use crate::config::Config;
 use crate::ui::run_chat_ui;
 use anyhow::Result;
 use futures::StreamExt;
-use pi_ai::{Client, Config as LLMConfig};
-use pi_agent_core::{Agent, AgentConfig, ToolRegistry};
+use pi_ai::{Client, Config as LLMConfig, ProviderConfig};
+use pi_agent_core::{Agent, AgentConfig, CompactionConfig, ExecutorConfig, JsonlContextStore, ToolRegistry, TracingObserver};
 use std::sync::Arc;
 use tracing::info;
 
@@ -81,7 +82,18 @@ async fn handle_chat_command(
 ) -> Result<()> {
     info!("Starting chat mode");
 
-    let llm_config = LLMConfig::default();
+    // --model 可以写成 "provider/model"，所以把配置里所有 provider 都注册上
+    let mut llm_config = LLMConfig::from_env()?;
+    for (name, api_key) in &config.api_keys {
+        let provider = llm_config
+            .providers
+            .entry(name.clone())
+            .or_insert_with(|| ProviderConfig::for_provider(name));
+        provider.api_key = api_key.clone();
+        if let Some(base_url) = config.get_base_url(name) {
+            provider.base_url = base_url.clone();
+        }
+    }
     let llm_client = Arc::new(Client::new(llm_config)?);
     let tool_registry = Arc::new(ToolRegistry::new());
 
@@ -92,9 +104,32 @@ async fn handle_chat_command(
     )
     .with_model(model.unwrap_or_else(|| config.default_model.clone()))
     .with_temperature(temperature.unwrap_or(config.default_temperature))
-    .with_max_tokens(max_tokens.unwrap_or(config.default_max_tokens));
+    .with_max_tokens(max_tokens.unwrap_or(config.default_max_tokens))
+    .with_executor_config(ExecutorConfig {
+        max_concurrent_tools: config.tool_config.max_concurrent_tools,
+        tool_timeout_secs: Some(config.tool_config.tool_timeout_secs),
+        compaction: Some(CompactionConfig::default()),
+        rollback_tools: true,
+        ..ExecutorConfig::default()
+    })
+    .with_approval_policy(approval::default_policy());
 
-    let agent = Arc::new(Agent::new(agent_config, llm_client, tool_registry));
+    // 界面模式没有审批对话框，只有显式打开 auto_approve_in_ui 才让工具不经确认执行
+    if ui && !config.tool_config.auto_approve_in_ui {
+        anyhow::bail!(
+            "The chat UI cannot ask for tool approval yet. Set tool_config.auto_approve_in_ui = true \
+             to let tools run without confirmation, or use the terminal chat."
+        );
+    }
+    let agent = Agent::new(agent_config, llm_client, tool_registry)
+        .with_context_store(Arc::new(JsonlContextStore::new(config.sessions_dir())));
+    let agent = if ui {
+        agent
+    } else {
+        agent.with_approval_handler(Arc::new(TerminalApproval))
+    };
+    agent.add_observer(Arc::new(TracingObserver));
+    let agent = Arc::new(agent);
     agent.initialize().await?;
 
     if ui {
@@ -108,7 +143,8 @@ async fn handle_chat_command(
 
 async fn run_chat_cli(agent: Arc<Agent>, stream: bool) -> Result<()> {
     println!("Pi Coding Agent - Chat Mode");
-    println!("Type 'quit' or 'exit' to end the conversation\n");
+    println!("Type 'quit' or 'exit' to end the conversation");
+    println!("Type '/undo' to remove the last exchange or '/retry' to regenerate the last response\n");
 
     loop {
         print!(" > ");
@@ -128,6 +164,23 @@ async fn run_chat_cli(agent: Arc<Agent>, stream: bool) -> Result<()> {
             break;
         }
 
+        if input == "/undo" {
+            match agent.undo_last_turn().await? {
+                Some(rewind) => println!("Removed {} messages\n", rewind.removed.len()),
+                None => println!("Nothing to undo\n"),
+            }
+            continue;
+        }
+
+        if input == "/retry" {
+            let result = agent.regenerate().await?;
+            match result.messages.last() {
+                Some(last_message) => println!("Assistant: {}\n", last_message.content),
+                None => println!("Assistant: No response\n"),
+            }
+            continue;
+        }
+
         if stream {
             let mut stream_response = agent.chat_stream(input.to_string()).await?;
             print!("Assistant: ");
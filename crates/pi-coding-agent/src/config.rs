@@ -1,4 +1,5 @@
 use anyhow::Result;
+use pi_ai::SecretString;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -8,10 +9,15 @@ pub struct Config {
     pub default_model: String,
     pub default_temperature: f32,
     pub default_max_tokens: u32,
-    pub api_keys: HashMap<String, String>,
+    // 直接写在配置里的密钥按明文写回，来自环境变量等来源的只写来源
+    #[serde(serialize_with = "pi_ai::secret::serialize_exposed_map")]
+    pub api_keys: HashMap<String, SecretString>,
     pub base_urls: HashMap<String, String>,
     pub ui_config: UiConfig,
     pub tool_config: ToolConfig,
+    // 会话保存目录，为空时使用 ~/.pi/sessions
+    #[serde(default)]
+    pub sessions_dir: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,15 +33,18 @@ pub struct ToolConfig {
     pub enabled_tools: Vec<String>,
     pub tool_timeout_secs: u64,
     pub max_concurrent_tools: usize,
+    // 界面模式还不能询问审批，打开后工具不经确认直接执行
+    #[serde(default)]
+    pub auto_approve_in_ui: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         let mut api_keys = HashMap::new();
-        if let Ok(key) = std::env::var("OPENAI_API_KEY") {
+        if let Ok(key) = SecretString::from_env("OPENAI_API_KEY") {
             api_keys.insert("openai".to_string(), key);
         }
-        if let Ok(key) = std::env::var("ANTHROPIC_API_KEY") {
+        if let Ok(key) = SecretString::from_env("ANTHROPIC_API_KEY") {
             api_keys.insert("anthropic".to_string(), key);
         }
 
@@ -70,7 +79,9 @@ impl Default for Config {
                 ],
                 tool_timeout_secs: 30,
                 max_concurrent_tools: 3,
+                auto_approve_in_ui: false,
             },
+            sessions_dir: None,
         }
     }
 }
@@ -109,6 +120,16 @@ impl Config {
         Ok(())
     }
 
+    pub fn sessions_dir(&self) -> PathBuf {
+        if let Some(dir) = &self.sessions_dir {
+            return PathBuf::from(dir);
+        }
+        let mut path = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
+        path.push(".pi");
+        path.push("sessions");
+        path
+    }
+
     fn default_config_path() -> PathBuf {
         let mut path = dirs::home_dir().unwrap_or_else(|| PathBuf::from("."));
         path.push(".pi");
@@ -116,7 +137,7 @@ impl Config {
         path
     }
 
-    pub fn get_api_key(&self, provider: &str) -> Option<&String> {
+    pub fn get_api_key(&self, provider: &str) -> Option<&SecretString> {
         self.api_keys.get(provider)
     }
 
@@ -6,7 +6,7 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::result::Result;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 pub struct FileReadTool;
 
@@ -51,7 +51,12 @@ impl ToolHandler for FileReadTool {
     }
 }
 
-pub struct FileWriteTool;
+// 记录每次写入前的文件内容（不存在为 None），撤销对话轮次时按写入的逆序还原。
+// 只保存在内存里，重启后之前的写入无法回滚
+#[derive(Default)]
+pub struct FileWriteTool {
+    backups: Mutex<HashMap<String, Vec<Option<String>>>>,
+}
 
 #[async_trait]
 impl ToolHandler for FileWriteTool {
@@ -63,6 +68,10 @@ impl ToolHandler for FileWriteTool {
         "Writes content to a file"
     }
 
+    fn parallel_safe(&self) -> bool {
+        false
+    }
+
     fn parameters(&self) -> ToolParameters {
         let mut properties = HashMap::new();
         properties.insert(
@@ -105,11 +114,39 @@ impl ToolHandler for FileWriteTool {
                 .map_err(|e| pi_agent_core::AgentError::ToolExecution(format!("Failed to create directory: {}", e)))?;
         }
 
+        let previous = fs::read_to_string(path).ok();
         match fs::write(path, content) {
-            Ok(_) => Ok(ToolExecutionResult::success("File written successfully".to_string())),
+            Ok(_) => {
+                self.backups.lock().unwrap().entry(path.to_string()).or_default().push(previous);
+                Ok(ToolExecutionResult::success("File written successfully".to_string()))
+            }
             Err(e) => Ok(ToolExecutionResult::failure(format!("Failed to write file: {}", e))),
         }
     }
+
+    async fn rollback(&self, arguments: Value, output: Option<&str>) -> Result<(), pi_agent_core::AgentError> {
+        // 写入失败时没有留下备份，也不需要还原
+        if output.is_some_and(|output| output.starts_with("Error:")) {
+            return Ok(());
+        }
+        let path = arguments
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| pi_agent_core::AgentError::InvalidToolArguments("Missing 'path' argument".to_string()))?;
+
+        let previous = self
+            .backups
+            .lock()
+            .unwrap()
+            .get_mut(path)
+            .and_then(|backups| backups.pop())
+            .ok_or_else(|| pi_agent_core::AgentError::ToolExecution(format!("No backup recorded for {}", path)))?;
+        let restored = match previous {
+            Some(content) => fs::write(path, content),
+            None => fs::remove_file(path),
+        };
+        restored.map_err(|e| pi_agent_core::AgentError::ToolExecution(format!("Failed to restore {}: {}", path, e)))
+    }
 }
 
 pub struct FileSearchTool;
@@ -204,6 +241,10 @@ impl ToolHandler for ExecuteCommandTool {
         "Executes a shell command"
     }
 
+    fn parallel_safe(&self) -> bool {
+        false
+    }
+
     fn parameters(&self) -> ToolParameters {
         let mut properties = HashMap::new();
         properties.insert(
@@ -238,15 +279,18 @@ impl ToolHandler for ExecuteCommandTool {
 
         let working_dir = arguments.get("working_dir").and_then(|v| v.as_str());
 
+        // 超时或被取消时 future 会被丢弃，此时要结束子进程
         let output = if let Some(dir) = working_dir {
             tokio::process::Command::new("sh")
                 .args(["-c", command])
                 .current_dir(dir)
+                .kill_on_drop(true)
                 .output()
                 .await
         } else {
             tokio::process::Command::new("sh")
                 .args(["-c", command])
+                .kill_on_drop(true)
                 .output()
                 .await
         };
@@ -279,7 +323,7 @@ impl ToolHandler for ExecuteCommandTool {
 pub fn get_default_tools() -> Vec<Tool> {
     vec![
         Tool::new(Arc::new(FileReadTool)),
-        Tool::new(Arc::new(FileWriteTool)),
+        Tool::new(Arc::new(FileWriteTool::default())),
         Tool::new(Arc::new(FileSearchTool)),
         Tool::new(Arc::new(ExecuteCommandTool)),
     ]
@@ -1,21 +1,32 @@
+use pi_ai::SecretString;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SlackConfig {
-    pub bot_token: String,
-    pub app_token: String,
-    pub signing_secret: String,
+    pub bot_token: SecretString,
+    pub app_token: SecretString,
+    pub signing_secret: SecretString,
     pub channels: Vec<String>,
     pub allowed_users: Option<Vec<String>>,
+    // "provider/model" 或者只写 model
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u32>,
+    // SQLite 文件路径；为空时会话只保存在内存中
+    #[serde(default)]
+    pub sessions_db: Option<String>,
 }
 
 impl SlackConfig {
     pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
         Ok(Self {
-            bot_token: std::env::var("SLACK_BOT_TOKEN")?,
-            app_token: std::env::var("SLACK_APP_TOKEN")?,
-            signing_secret: std::env::var("SLACK_SIGNING_SECRET")?,
+            bot_token: SecretString::from_env("SLACK_BOT_TOKEN")?,
+            app_token: SecretString::from_env("SLACK_APP_TOKEN")?,
+            signing_secret: SecretString::from_env("SLACK_SIGNING_SECRET")?,
             channels: std::env::var("SLACK_CHANNELS")
                 .unwrap_or_default()
                 .split(',')
@@ -25,6 +36,16 @@ impl SlackConfig {
             allowed_users: std::env::var("SLACK_ALLOWED_USERS")
                 .ok()
                 .map(|s| s.split(',').map(|s| s.trim().to_string()).collect()),
+            model: std::env::var("SLACK_BOT_MODEL").ok().filter(|s| !s.is_empty()),
+            temperature: std::env::var("SLACK_BOT_TEMPERATURE")
+                .ok()
+                .map(|s| s.parse())
+                .transpose()?,
+            max_tokens: std::env::var("SLACK_BOT_MAX_TOKENS")
+                .ok()
+                .map(|s| s.parse())
+                .transpose()?,
+            sessions_db: std::env::var("SLACK_BOT_SESSIONS_DB").ok().filter(|s| !s.is_empty()),
         })
     }
 }
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use clap::Parser;
 use pi_ai::Client;
-use pi_agent_core::{Agent, AgentConfig, ToolRegistry};
+use pi_agent_core::{Agent, AgentConfig, SqliteContextStore, ToolRegistry};
 use pi_mom::{SlackBot, SlackConfig};
 use std::sync::Arc;
 use tracing::{error, info, Level};
@@ -32,13 +32,26 @@ async fn main() -> Result<()> {
     let llm_client = Arc::new(Client::from_env()?);
     let tool_registry = Arc::new(ToolRegistry::new());
 
-    let agent_config = AgentConfig::new(
+    let mut agent_config = AgentConfig::new(
         "slack-bot".to_string(),
         "Pi Slack Bot".to_string(),
         "You are a helpful AI assistant integrated with Slack. Provide concise and helpful responses to user questions.",
     );
-
-    let agent = Arc::new(Agent::new(agent_config, llm_client, tool_registry));
+    if let Some(model) = &config.model {
+        agent_config = agent_config.with_model(model.clone());
+    }
+    if let Some(temperature) = config.temperature {
+        agent_config = agent_config.with_temperature(temperature);
+    }
+    if let Some(max_tokens) = config.max_tokens {
+        agent_config = agent_config.with_max_tokens(max_tokens);
+    }
+
+    let mut agent = Agent::new(agent_config, llm_client, tool_registry);
+    if let Some(path) = &config.sessions_db {
+        agent = agent.with_context_store(Arc::new(SqliteContextStore::open(path)?));
+    }
+    let agent = Arc::new(agent);
     agent.initialize().await?;
 
     let bot = SlackBot::new(config, agent);
@@ -54,7 +54,7 @@ impl SlackBot {
         let response = self
             .http_client
             .get(url)
-            .header("Authorization", format!("Bearer {}", self.config.bot_token))
+            .header("Authorization", format!("Bearer {}", self.config.bot_token.expose_secret()))
             .send()
             .await?;
 
@@ -123,7 +123,7 @@ impl SlackBot {
         let response = self
             .http_client
             .post(url)
-            .header("Authorization", format!("Bearer {}", self.config.bot_token))
+            .header("Authorization", format!("Bearer {}", self.config.bot_token.expose_secret()))
             .json(&message)
             .send()
             .await?;
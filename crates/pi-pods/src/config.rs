@@ -1,3 +1,4 @@
+use pi_ai::SecretString;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -10,7 +11,7 @@ pub struct PodConfig {
     pub registry: String,
     pub vllm_image: String,
     pub api_server: String,
-    pub api_token: String,
+    pub api_token: SecretString,
 }
 
 impl Default for PodConfig {
@@ -24,8 +25,7 @@ impl Default for PodConfig {
             vllm_image: "vllm/vllm-openai:latest".to_string(),
             api_server: std::env::var("KUBERNETES_API_SERVER")
                 .unwrap_or_else(|_| "http://localhost:8080".to_string()),
-            api_token: std::env::var("KUBERNETES_API_TOKEN")
-                .unwrap_or_default(),
+            api_token: SecretString::from_env("KUBERNETES_API_TOKEN").unwrap_or_default(),
         }
     }
 }